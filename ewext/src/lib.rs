@@ -763,6 +763,34 @@ pub unsafe extern "C" fn luaopen_ewext(lua: *mut lua_State) -> c_int {
             })?
         }
         add_lua_fn!(set_cache);
+        fn set_smoothing(lua: LuaState) -> eyre::Result<()> {
+            let enabled = lua.to_bool(1);
+            let delay = lua.to_integer(2).max(0) as u64;
+            let max_extrapolation = lua.to_integer(3).max(0) as u64;
+            let exclude_tags = lua.to_string(4)?;
+            ExtState::with_global(|state| {
+                state.modules.entity_sync.as_mut().unwrap().set_smoothing(
+                    enabled,
+                    delay,
+                    max_extrapolation,
+                    &exclude_tags,
+                );
+                Ok(())
+            })?
+        }
+        add_lua_fn!(set_smoothing);
+        fn set_compact_updates(lua: LuaState) -> eyre::Result<()> {
+            ExtState::with_global(|state| {
+                state
+                    .modules
+                    .entity_sync
+                    .as_mut()
+                    .unwrap()
+                    .set_compact_updates(lua.to_bool(1));
+                Ok(())
+            })?
+        }
+        add_lua_fn!(set_compact_updates);
     }
     #[cfg(debug_assertions)]
     println!("Initializing ewext - Ok");
@@ -21,9 +21,14 @@ use shared::{
     Destination, NoitaOutbound, PeerId, RemoteMessage, WorldPos,
     des::{Gid, InterestRequest, ProjectileFired, RemoteDes},
 };
+use smoothing::SmoothingConfig;
 use std::sync::{LazyLock, Mutex};
+use std::time::Duration;
+use update_codecs::UpdateCodecs;
 mod diff_model;
 mod interest;
+mod smoothing;
+mod update_codecs;
 
 static ENTITY_EXCLUDES: LazyLock<FxHashSet<&'static str>> = LazyLock::new(|| {
     let mut hs = FxHashSet::default();
@@ -58,6 +63,8 @@ pub(crate) struct EntitySync {
     peer_order: Vec<PeerId>,
     log_performance: bool,
     entity_manager: EntityManager,
+    smoothing: SmoothingConfig,
+    codecs: UpdateCodecs,
 }
 impl EntitySync {
     pub(crate) fn set_perf(&mut self, perf: bool) {
@@ -66,6 +73,28 @@ impl EntitySync {
     pub(crate) fn set_cache(&mut self, cache: bool) {
         self.entity_manager.set_cache(cache);
     }
+    pub(crate) fn set_smoothing(
+        &mut self,
+        enabled: bool,
+        delay_ms: u64,
+        max_extrapolation_ms: u64,
+        exclude_tags: &str,
+    ) {
+        self.smoothing = SmoothingConfig {
+            enabled,
+            delay: Duration::from_millis(delay_ms),
+            max_extrapolation: Duration::from_millis(max_extrapolation_ms),
+            exclude_tags: exclude_tags
+                .split(',')
+                .map(str::trim)
+                .filter(|tag| !tag.is_empty())
+                .map(str::to_owned)
+                .collect(),
+        };
+    }
+    pub(crate) fn set_compact_updates(&mut self, enabled: bool) {
+        self.codecs.enabled = enabled;
+    }
     /*pub(crate) fn has_gid(&self, gid: Gid) -> bool {
         self.local_diff_model.has_gid(gid) || self.remote_models.values().any(|r| r.has_gid(gid))
     }*/
@@ -113,6 +142,8 @@ impl Default for EntitySync {
             peer_order: Vec::new(),
             log_performance: false,
             entity_manager: EntityManager::default(),
+            smoothing: SmoothingConfig::default(),
+            codecs: UpdateCodecs::default(),
         }
     }
 }
@@ -156,16 +187,16 @@ impl EntitySync {
             Ok(())
         };
         if !self.local_diff_model.update_buffer.is_empty() {
+            let (compact, plain): (Vec<PeerId>, Vec<PeerId>) = self
+                .interest_tracker
+                .iter_interested()
+                .filter(|p| !new_intersects.contains(p))
+                .partition(|p| self.codecs.is_compact(*p));
             let res = std::mem::take(&mut self.local_diff_model.update_buffer);
             let (RemoteDes::EntityUpdate(diff), err) = send_remotedes_ret(
                 ctx,
                 true,
-                Destination::Peers(
-                    self.interest_tracker
-                        .iter_interested()
-                        .filter(|p| !new_intersects.contains(p))
-                        .collect(),
-                ),
+                Destination::Peers(plain),
                 RemoteDes::EntityUpdate(res),
             ) else {
                 unreachable!()
@@ -173,6 +204,42 @@ impl EntitySync {
             self.local_diff_model.update_buffer = diff;
             err1?;
             err?;
+            for peer in compact {
+                if let Some(encoder) = self.codecs.encoder_for(peer) {
+                    send_remotedes(
+                        ctx.net,
+                        true,
+                        Destination::Peer(peer),
+                        RemoteDes::CompactEntityUpdate(
+                            encoder.encode(&self.local_diff_model.update_buffer),
+                        ),
+                    )?;
+                }
+            }
+        }
+        for (peer, buffer) in self.local_diff_model.motion_buffers.iter_mut() {
+            if buffer.is_empty() || new_intersects.contains(peer) {
+                continue;
+            }
+            if let Some(encoder) = self.codecs.encoder_for(*peer) {
+                send_remotedes(
+                    ctx.net,
+                    true,
+                    Destination::Peer(*peer),
+                    RemoteDes::CompactEntityUpdate(encoder.encode(buffer)),
+                )?;
+                continue;
+            }
+            let (RemoteDes::EntityUpdate(diff), err) = send_remotedes_ret(
+                ctx,
+                true,
+                Destination::Peer(*peer),
+                RemoteDes::EntityUpdate(std::mem::take(buffer)),
+            ) else {
+                unreachable!()
+            };
+            *buffer = diff;
+            err?;
         }
         Ok(())
     }
@@ -337,6 +404,7 @@ impl EntitySync {
                     remote.remove_entities(&mut self.entity_manager)?
                 }
                 self.interest_tracker.remove_peer(peer);
+                self.codecs.remove_peer(peer);
                 let _ = crate::ExtState::with_global(|state| {
                     state.fps_by_player.remove(&peer);
                     state.player_entity_map.remove_by_left(&peer);
@@ -345,6 +413,9 @@ impl EntitySync {
             shared::des::ProxyToDes::DeleteEntity(entity) => {
                 EntityID(entity).kill();
             }
+            shared::des::ProxyToDes::LostAuthority(gid) => {
+                self.local_diff_model.lost_authority(gid);
+            }
         }
         Ok(())
     }
@@ -434,9 +505,12 @@ impl EntitySync {
                 cam_pos.insert(source, pos);
             }
             RemoteDes::DeadEntities(vec) => self.spawn_once.extend(vec),
-            RemoteDes::InterestRequest(interest_request) => self
-                .interest_tracker
-                .handle_interest_request(source, interest_request),
+            RemoteDes::InterestRequest(interest_request) => {
+                self.codecs
+                    .set_peer_encoding(source, interest_request.encoding);
+                self.interest_tracker
+                    .handle_interest_request(source, interest_request)
+            }
             RemoteDes::EntityUpdate(vec) => {
                 self.remote_models
                     .entry(source)
@@ -456,7 +530,17 @@ impl EntitySync {
                     remote.remove_entities(&mut self.entity_manager)?
                 }
             }
-            RemoteDes::Reset => self.interest_tracker.reset_interest_for(source),
+            RemoteDes::CompactEntityUpdate(updates) => {
+                let vec = self.codecs.decode(source, updates);
+                self.remote_models
+                    .entry(source)
+                    .or_insert(RemoteDiffModel::new(source))
+                    .apply_diff(vec, &mut self.entity_manager)?;
+            }
+            RemoteDes::Reset => {
+                self.interest_tracker.reset_interest_for(source);
+                self.codecs.peer_reset(source);
+            }
             RemoteDes::Projectiles(vec) => {
                 self.remote_models
                     .entry(source)
@@ -620,7 +704,10 @@ impl Module for EntitySync {
                 ctx.net,
                 false,
                 Destination::Broadcast,
-                RemoteDes::InterestRequest(InterestRequest { pos }),
+                RemoteDes::InterestRequest(InterestRequest {
+                    pos,
+                    encoding: self.codecs.our_encoding(),
+                }),
             )?;
         }
         if frame_num % 5 == 1 {
@@ -633,6 +720,7 @@ impl Module for EntitySync {
         }
 
         for lost in self.interest_tracker.drain_lost_interest() {
+            self.codecs.reset_encoder(lost);
             send_remotedes(
                 ctx.net,
                 true,
@@ -673,10 +761,24 @@ impl Module for EntitySync {
         }
         {
             let new_intersects = self.interest_tracker.got_any_new_interested();
+            for peer in &new_intersects {
+                self.codecs.reset_encoder(*peer);
+            }
+            let peers: Vec<_> = self
+                .interest_tracker
+                .iter_interested_with_pos()
+                .filter(|(peer, _)| !new_intersects.contains(peer))
+                .collect();
             let dead;
             (dead, self.local_index) = match self
                 .local_diff_model
-                .update_tracked_entities(ctx, self.local_index, start, &mut self.entity_manager)
+                .update_tracked_entities(
+                    ctx,
+                    self.local_index,
+                    start,
+                    &peers,
+                    &mut self.entity_manager,
+                )
                 .wrap_err("Failed to update locally tracked entities")
             {
                 Ok(ret) => ret,
@@ -760,7 +862,13 @@ impl Module for EntitySync {
                     Some(remote_model) => {
                         let vi = self.remote_index.entry(*owner).or_insert(0);
                         let v = remote_model
-                            .apply_entities(ctx, *vi, start, &mut self.entity_manager)
+                            .apply_entities(
+                                ctx,
+                                *vi,
+                                start,
+                                &self.smoothing,
+                                &mut self.entity_manager,
+                            )
                             .wrap_err("Failed to apply entity infos")?;
                         self.remote_index.insert(*owner, v);
                         if self.log_performance {
@@ -1,4 +1,5 @@
 use super::NetManager;
+use super::smoothing::{MotionBuffer, SmoothingConfig};
 use crate::{ephemerial, modules::ModuleCtx, my_peer_id, print_error};
 use bimap::BiHashMap;
 use eyre::{Context, OptionExt, eyre};
@@ -29,10 +30,120 @@ use shared::{
 };
 use std::borrow::Cow;
 use std::num::NonZero;
-use std::time::Instant;
+use std::time::{Duration, Instant};
 pub(crate) static DES_TAG: &str = "ew_des";
 pub(crate) static DES_SCRIPTS_TAG: &str = "ew_des_lua";
 
+/// Peers closer than that to an entity get all of its movement at full precision.
+const NEAR_DISTANCE: f32 = 512.0;
+/// Peers closer than that get movement less often, peers further away get it even less often.
+const MID_DISTANCE: f32 = 1024.0;
+
+/// How often and how precisely movement of an entity is sent to a peer, depending on how far they are from it.
+#[derive(Clone, Copy)]
+enum UpdateTier {
+    Near,
+    Mid,
+    Far,
+}
+
+impl UpdateTier {
+    fn for_distance(entity: &EntityInfo, (px, py): (f32, f32)) -> Self {
+        let dist_sq = (entity.x - px).powi(2) + (entity.y - py).powi(2);
+        if dist_sq < NEAR_DISTANCE.powi(2) {
+            Self::Near
+        } else if dist_sq < MID_DISTANCE.powi(2) {
+            Self::Mid
+        } else {
+            Self::Far
+        }
+    }
+
+    /// Minimum time between two movement updates.
+    fn interval(self) -> Duration {
+        match self {
+            Self::Near => Duration::ZERO,
+            Self::Mid => Duration::from_millis(50),
+            Self::Far => Duration::from_millis(100),
+        }
+    }
+
+    /// Positions and velocities are rounded to multiples of this, 0 means no rounding.
+    fn precision(self) -> f32 {
+        match self {
+            Self::Near => 0.0,
+            Self::Mid => 0.5,
+            Self::Far => 1.0,
+        }
+    }
+}
+
+fn quantize(value: f32, step: f32) -> f32 {
+    if step > 0.0 {
+        (value / step).round() * step
+    } else {
+        value
+    }
+}
+
+/// Part of entity info that is sent separately to every peer.
+#[derive(Clone, Copy, PartialEq)]
+struct Motion {
+    x: f32,
+    y: f32,
+    vx: f32,
+    vy: f32,
+    r: f32,
+}
+
+impl Motion {
+    fn new(info: &EntityInfo, tier: UpdateTier) -> Self {
+        let step = tier.precision();
+        Self {
+            x: quantize(info.x, step),
+            y: quantize(info.y, step),
+            vx: quantize(info.vx, step),
+            vy: quantize(info.vy, step),
+            r: quantize(info.r, step / 16.0),
+        }
+    }
+}
+
+struct SentMotion {
+    motion: Motion,
+    at: Instant,
+}
+
+/// Adds movement of an entity to a peer's buffer, if it changed and the peer is due for an update.
+fn queue_motion(
+    sent: &mut FxHashMap<Lid, SentMotion>,
+    buffer: &mut Vec<EntityUpdate>,
+    lid: Lid,
+    info: &EntityInfo,
+    tier: UpdateTier,
+    now: Instant,
+) {
+    let motion = Motion::new(info, tier);
+    let last = sent.get(&lid);
+    if let Some(last) = last
+        && (last.motion == motion || now.duration_since(last.at) < tier.interval())
+    {
+        return;
+    }
+    let last = last.map(|last| last.motion);
+    buffer.push(EntityUpdate::CurrentEntity(lid));
+    if last.is_none_or(|last| (last.x, last.y) != (motion.x, motion.y)) {
+        buffer.push(EntityUpdate::SetPosition(motion.x, motion.y));
+    }
+    if last.is_none_or(|last| (last.vx, last.vy) != (motion.vx, motion.vy)) {
+        buffer.push(EntityUpdate::SetVelocity(motion.vx, motion.vy));
+    }
+    if last.is_none_or(|last| last.r != motion.r) {
+        buffer.push(EntityUpdate::SetRotation(motion.r));
+    }
+    sent.insert(lid, SentMotion { motion, at: now });
+}
+
 #[derive(Clone)]
 struct EntityEntryPair {
     last: Option<EntityInfo>,
@@ -63,6 +174,11 @@ pub(crate) struct LocalDiffModel {
     wait_to_transfer: u8,
     pub update_buffer: Vec<EntityUpdate>,
     pub init_buffer: Vec<EntityInit>,
+    /// Movement is sent separately to every interested peer, as how often it's sent depends on distance.
+    pub motion_buffers: FxHashMap<PeerId, Vec<EntityUpdate>>,
+    motion_sent: FxHashMap<PeerId, FxHashMap<Lid, SentMotion>>,
+    /// Entities someone else turned out to have authority over, our copies of them are removed.
+    lost_authority: Vec<Gid>,
 }
 impl LocalDiffModel {
     /*pub(crate) fn get_lids(&self) -> Vec<Lid> {
@@ -181,6 +297,8 @@ pub(crate) struct RemoteDiffModel {
     pending_remove: Vec<Lid>,
     pending_death_notify: Vec<(Lid, bool, Option<PeerId>)>,
     peer_id: PeerId,
+    /// Recently received positions, used to smooth out movement.
+    motion: FxHashMap<Lid, MotionBuffer>,
 }
 
 impl RemoteDiffModel {
@@ -204,6 +322,7 @@ impl RemoteDiffModel {
             pending_remove: Default::default(),
             pending_death_notify: Default::default(),
             peer_id,
+            motion: Default::default(),
         }
     }
     /*pub fn has_gid(&self, gid: Gid) -> bool {
@@ -247,6 +366,9 @@ impl Default for LocalDiffModel {
             wait_to_transfer: 0,
             update_buffer: Vec::with_capacity(512),
             init_buffer: Vec::with_capacity(512),
+            motion_buffers: Default::default(),
+            motion_sent: Default::default(),
+            lost_authority: Vec::new(),
         }
     }
 }
@@ -1121,15 +1243,25 @@ impl LocalDiffModel {
         Ok(start)
     }
 
+    /// `peers` are interested peers along with their camera positions.
     #[allow(clippy::type_complexity)]
     pub(crate) fn update_tracked_entities(
         &mut self,
         ctx: &mut ModuleCtx,
         start: usize,
         tmr: Instant,
+        peers: &[(PeerId, (f32, f32))],
         entity_manager: &mut EntityManager,
     ) -> eyre::Result<(Vec<(WorldPos, SpawnOnce)>, usize)> {
         self.update_buffer.clear();
+        self.motion_sent
+            .retain(|peer, _| peers.iter().any(|(p, _)| p == peer));
+        self.motion_buffers
+            .retain(|peer, _| peers.iter().any(|(p, _)| p == peer));
+        for buffer in self.motion_buffers.values_mut() {
+            buffer.clear();
+        }
+        let now = Instant::now();
         let (cam_x, cam_y) = entity_manager.camera_pos();
         let cam_x = cam_x as f32;
         let cam_y = cam_y as f32;
@@ -1164,6 +1296,25 @@ impl LocalDiffModel {
         for (gid, lid) in to_untrack {
             self.tracker.untrack_entity(ctx, gid, lid, None)?
         }
+        for gid in self.lost_authority.drain(..) {
+            let Some(lid) = self
+                .entity_entries
+                .iter()
+                .find(|(_, e)| e.gid == gid)
+                .map(|(lid, _)| *lid)
+            else {
+                continue;
+            };
+            self.entity_entries.remove(&lid);
+            self.upload.remove(&lid);
+            self.dont_save.remove(&lid);
+            self.update_buffer.push(EntityUpdate::RemoveEntity(lid));
+            if let Some((_, entity)) = self.tracker.tracked.remove_by_left(&lid) {
+                self.tracker.global_entities.remove(&entity);
+                entity_manager.remove_ent(&entity);
+                entity.kill();
+            }
+        }
         let mut should_transfer = false;
         if let Some(pe) = ctx.player_map.get_by_left(&my_peer_id()) {
             let (px, py) = pe.position()?;
@@ -1264,22 +1415,16 @@ impl LocalDiffModel {
                         &mut had_any_delta,
                         lid,
                     );
-                    diff(
-                        &(current.x, current.y),
-                        &mut (last.x, last.y),
-                        || EntityUpdate::SetPosition(current.x, current.y),
-                        &mut self.update_buffer,
-                        &mut had_any_delta,
-                        lid,
-                    );
-                    diff(
-                        &(current.vx, current.vy),
-                        &mut (last.vx, last.vy),
-                        || EntityUpdate::SetVelocity(current.vx, current.vy),
-                        &mut self.update_buffer,
-                        &mut had_any_delta,
-                        lid,
-                    );
+                    for &(peer, pos) in peers {
+                        queue_motion(
+                            self.motion_sent.entry(peer).or_default(),
+                            self.motion_buffers.entry(peer).or_default(),
+                            lid,
+                            current,
+                            UpdateTier::for_distance(current, pos),
+                            now,
+                        );
+                    }
                     diff(
                         &current.hp,
                         &mut last.hp,
@@ -1312,14 +1457,6 @@ impl LocalDiffModel {
                         &mut had_any_delta,
                         lid,
                     );
-                    diff(
-                        &current.r,
-                        &mut last.r,
-                        || EntityUpdate::SetRotation(current.r),
-                        &mut self.update_buffer,
-                        &mut had_any_delta,
-                        lid,
-                    );
                     diff(
                         &current.phys,
                         &mut last.phys,
@@ -1399,6 +1536,12 @@ impl LocalDiffModel {
                 break;
             }
         }
+        if end == 0 {
+            // Went through every entity, forget the ones that aren't tracked anymore.
+            for sent in self.motion_sent.values_mut() {
+                sent.retain(|lid, _| self.entity_entries.contains_key(lid));
+            }
+        }
         for (lid, peer) in self.tracker.pending_localize.drain(..) {
             self.update_buffer
                 .push(EntityUpdate::LocalizeEntity(lid, peer));
@@ -1446,6 +1589,10 @@ impl LocalDiffModel {
         self.tracker.pending_authority.extend(full_entity_data);
     }
 
+    pub(crate) fn lost_authority(&mut self, gid: Gid) {
+        self.lost_authority.push(gid);
+    }
+
     pub(crate) fn entity_grabbed(
         &mut self,
         source: PeerId,
@@ -1555,9 +1702,12 @@ impl RemoteDiffModel {
     ) -> eyre::Result<()> {
         let empty_data = &mut EntityInfo::default();
         let mut ent_data = &mut EntityInfo::default();
+        let mut current = None;
+        let now = Instant::now();
         for entry in diff {
             match entry {
                 EntityUpdate::CurrentEntity(lid) => {
+                    current = self.entity_infos.contains_key(&lid).then_some(lid);
                     ent_data = self.entity_infos.get_mut(&lid).unwrap_or(empty_data);
                 }
                 EntityUpdate::LocalizeEntity(lid, peer_id) => {
@@ -1568,6 +1718,8 @@ impl RemoteDiffModel {
                         safe_entitykill(entity_manager);
                     }
                     self.entity_infos.remove(&lid);
+                    self.motion.remove(&lid);
+                    current = None;
                     ent_data = empty_data;
                 }
                 EntityUpdate::RemoveEntity(lid) => self.pending_remove.push(lid),
@@ -1578,9 +1730,19 @@ impl RemoteDiffModel {
                 } => self
                     .pending_death_notify
                     .push((lid, wait_on_kill, responsible_peer)),
-                EntityUpdate::SetPosition(x, y) => (ent_data.x, ent_data.y) = (x, y),
+                EntityUpdate::SetPosition(x, y) => {
+                    (ent_data.x, ent_data.y) = (x, y);
+                    if let Some(lid) = current {
+                        self.motion.entry(lid).or_default().push_position(now, x, y);
+                    }
+                }
                 EntityUpdate::SetRotation(r) => ent_data.r = r,
-                EntityUpdate::SetVelocity(vx, vy) => (ent_data.vx, ent_data.vy) = (vx, vy),
+                EntityUpdate::SetVelocity(vx, vy) => {
+                    (ent_data.vx, ent_data.vy) = (vx, vy);
+                    if let Some(lid) = current {
+                        self.motion.entry(lid).or_default().set_velocity(vx, vy);
+                    }
+                }
                 EntityUpdate::SetHp(hp) => ent_data.hp = hp,
                 EntityUpdate::SetFacingDirection(direction) => {
                     ent_data.facing_direction = direction
@@ -1610,6 +1772,7 @@ impl RemoteDiffModel {
         entity_info: &EntityInfo,
         entity: EntityID,
         lid: &Lid,
+        (x, y): (f32, f32),
         entity_manager: &mut EntityManager,
     ) -> eyre::Result<Option<Lid>> {
         if entity_info.kind == EntityKind::Item && item_in_my_inventory(entity)?
@@ -1758,13 +1921,9 @@ impl RemoteDiffModel {
                 true
             };
             if should_send_rotation && should_send_position {
-                entity.set_position(
-                    entity_info.x as f64,
-                    entity_info.y as f64,
-                    Some(entity_info.r as f64),
-                )?;
+                entity.set_position(x as f64, y as f64, Some(entity_info.r as f64))?;
             } else if should_send_position {
-                entity.set_position(entity_info.x as f64, entity_info.y as f64, None)?;
+                entity.set_position(x as f64, y as f64, None)?;
             } else if should_send_rotation {
                 let (x, y) = entity.position()?;
                 entity.set_position(x, y, Some(entity_info.r as f64))?;
@@ -1989,8 +2148,10 @@ impl RemoteDiffModel {
         ctx: &mut ModuleCtx,
         start: usize,
         tmr: Instant,
+        smoothing: &SmoothingConfig,
         entity_manager: &mut EntityManager,
     ) -> eyre::Result<usize> {
+        let now = Instant::now();
         let mut to_remove = Vec::new();
         let l = self.entity_infos.len();
         let mut end = None;
@@ -1999,6 +2160,14 @@ impl RemoteDiffModel {
             match self.tracked.get_by_left(lid) {
                 Some(entity) if entity.is_alive() => {
                     entity_manager.set_current_entity(*entity)?;
+                    let pos = smoothed_position(
+                        &mut self.motion,
+                        lid,
+                        entity_info,
+                        *entity,
+                        smoothing,
+                        now,
+                    );
                     if tmr.elapsed().as_micros() > 5000 || start > i {
                         if end.is_none() && start <= i {
                             end = Some(i);
@@ -2026,23 +2195,19 @@ impl RemoteDiffModel {
                             };
                             if should_send_rotation && should_send_position {
                                 entity.set_position(
-                                    entity_info.x as f64,
-                                    entity_info.y as f64,
+                                    pos.0 as f64,
+                                    pos.1 as f64,
                                     Some(entity_info.r as f64),
                                 )?;
                             } else if should_send_position {
-                                entity.set_position(
-                                    entity_info.x as f64,
-                                    entity_info.y as f64,
-                                    None,
-                                )?;
+                                entity.set_position(pos.0 as f64, pos.1 as f64, None)?;
                             } else if should_send_rotation {
                                 let (x, y) = entity.position()?;
                                 entity.set_position(x, y, Some(entity_info.r as f64))?;
                             }
                         }
                     } else {
-                        match self.inner(ctx, entity_info, *entity, lid, entity_manager) {
+                        match self.inner(ctx, entity_info, *entity, lid, pos, entity_manager) {
                             Ok(Some(lid)) => to_remove.push(lid),
                             Err(s) => print_error(s)?,
                             _ => {}
@@ -2083,6 +2248,7 @@ impl RemoteDiffModel {
         for lid in to_remove {
             self.grab_request.push(lid);
             self.entity_infos.remove(&lid);
+            self.motion.remove(&lid);
         }
         Ok(end.unwrap_or(0))
     }
@@ -2097,6 +2263,7 @@ impl RemoteDiffModel {
                 .and_then(|peer| ctx.player_map.get_by_left(&peer))
                 .copied();
             self.entity_infos.remove(&lid);
+            self.motion.remove(&lid);
             let Some(entity) = self.tracked.get_by_left(&lid).copied() else {
                 continue;
             };
@@ -2151,6 +2318,7 @@ impl RemoteDiffModel {
         }
         for lid in self.pending_remove.drain(..) {
             self.entity_infos.remove(&lid);
+            self.motion.remove(&lid);
             if let Some((_, entity)) = self.tracked.remove_by_left(&lid) {
                 entity_manager.set_current_entity(entity)?;
                 safe_entitykill(entity_manager);
@@ -2422,6 +2590,24 @@ pub fn init_remote_entity(
     Ok(())
 }
 
+/// Position to show a remote entity at, the last received one when smoothing doesn't apply.
+fn smoothed_position(
+    motion: &mut FxHashMap<Lid, MotionBuffer>,
+    lid: &Lid,
+    info: &EntityInfo,
+    entity: EntityID,
+    config: &SmoothingConfig,
+    now: Instant,
+) -> (f32, f32) {
+    if !config.enabled || config.exclude_tags.iter().any(|tag| entity.has_tag(tag)) {
+        return (info.x, info.y);
+    }
+    motion
+        .get_mut(lid)
+        .and_then(|buffer| buffer.position_at(now, config))
+        .unwrap_or((info.x, info.y))
+}
+
 fn item_in_inventory(entity: EntityID) -> Result<bool, eyre::Error> {
     Ok(entity.root()? != Some(entity))
 }
@@ -2772,3 +2958,114 @@ fn sun(entity: &mut EntityManager, counter: u8) -> eyre::Result<()> {
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn info_at(x: f32, y: f32) -> EntityInfo {
+        EntityInfo {
+            x,
+            y,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn tiers_by_distance() {
+        let entity = info_at(100.0, -50.0);
+        let tier = |dx: f32| UpdateTier::for_distance(&entity, (100.0 + dx, -50.0));
+        assert!(matches!(tier(0.0), UpdateTier::Near));
+        assert!(matches!(tier(NEAR_DISTANCE - 0.5), UpdateTier::Near));
+        assert!(matches!(tier(NEAR_DISTANCE), UpdateTier::Mid));
+        assert!(matches!(tier(-(MID_DISTANCE - 0.5)), UpdateTier::Mid));
+        assert!(matches!(tier(MID_DISTANCE), UpdateTier::Far));
+        assert!(matches!(
+            UpdateTier::for_distance(&entity, (100.0, -50.0 + MID_DISTANCE * 4.0)),
+            UpdateTier::Far
+        ));
+    }
+
+    /// Movement updates queued over a second of an entity moving every frame.
+    fn updates_sent(tier: UpdateTier) -> usize {
+        let mut sent = FxHashMap::default();
+        let mut buffer = Vec::new();
+        let start = Instant::now();
+        for frame in 0..60 {
+            let info = info_at(frame as f32 * 3.0, 0.0);
+            let now = start + Duration::from_millis(frame * 1000 / 60);
+            queue_motion(&mut sent, &mut buffer, Lid(1), &info, tier, now);
+        }
+        buffer
+            .iter()
+            .filter(|update| matches!(update, EntityUpdate::CurrentEntity(_)))
+            .count()
+    }
+
+    #[test]
+    fn far_tiers_send_less_often() {
+        let near = updates_sent(UpdateTier::Near);
+        let mid = updates_sent(UpdateTier::Mid);
+        let far = updates_sent(UpdateTier::Far);
+        assert_eq!(near, 60);
+        assert!(mid < near && far < mid, "near {near}, mid {mid}, far {far}");
+        assert!(far <= 10, "far {far}");
+    }
+
+    fn sent_position(tier: UpdateTier, x: f32) -> Option<f32> {
+        let mut buffer = Vec::new();
+        let info = info_at(x, 0.0);
+        queue_motion(
+            &mut FxHashMap::default(),
+            &mut buffer,
+            Lid(1),
+            &info,
+            tier,
+            Instant::now(),
+        );
+        buffer.iter().find_map(|update| match update {
+            EntityUpdate::SetPosition(x, _) => Some(*x),
+            _ => None,
+        })
+    }
+
+    #[test]
+    fn far_tiers_send_less_precisely() {
+        assert_eq!(sent_position(UpdateTier::Near, 10.3), Some(10.3));
+        assert_eq!(sent_position(UpdateTier::Mid, 10.3), Some(10.5));
+        assert_eq!(sent_position(UpdateTier::Far, 10.3), Some(10.0));
+
+        // Changes below the precision of a tier aren't sent at all.
+        let mut sent = FxHashMap::default();
+        let mut buffer = Vec::new();
+        let start = Instant::now();
+        let later = start + UpdateTier::Far.interval() * 2;
+        queue_motion(
+            &mut sent,
+            &mut buffer,
+            Lid(1),
+            &info_at(10.3, 0.0),
+            UpdateTier::Far,
+            start,
+        );
+        buffer.clear();
+        queue_motion(
+            &mut sent,
+            &mut buffer,
+            Lid(1),
+            &info_at(10.4, 0.0),
+            UpdateTier::Far,
+            later,
+        );
+        assert!(buffer.is_empty());
+        queue_motion(
+            &mut sent,
+            &mut buffer,
+            Lid(1),
+            &info_at(10.4, 0.0),
+            UpdateTier::Near,
+            later,
+        );
+        assert!(!buffer.is_empty());
+    }
+}
@@ -1,4 +1,4 @@
-use rustc_hash::FxHashSet;
+use rustc_hash::{FxHashMap, FxHashSet};
 use shared::des::INTEREST_REQUEST_RADIUS;
 use shared::{PeerId, des::InterestRequest};
 pub(crate) struct InterestTracker {
@@ -6,6 +6,8 @@ pub(crate) struct InterestTracker {
     x: f64,
     y: f64,
     interested_peers: FxHashSet<PeerId>,
+    /// Camera position from the last interest request of every peer.
+    peer_pos: FxHashMap<PeerId, (f32, f32)>,
     added_any: Vec<PeerId>,
     lost_interest: Vec<PeerId>,
 }
@@ -18,6 +20,7 @@ impl InterestTracker {
             x: 0.0,
             y: 0.0,
             interested_peers: Default::default(),
+            peer_pos: Default::default(),
             lost_interest: Vec::with_capacity(4),
             added_any: Vec::with_capacity(4),
         }
@@ -32,6 +35,7 @@ impl InterestTracker {
         let rx = request.pos.x as f64;
         let ry = request.pos.y as f64;
         let radius = INTEREST_REQUEST_RADIUS;
+        self.peer_pos.insert(peer, (rx as f32, ry as f32));
 
         let dist_sq = (rx - self.x).powi(2) + (ry - self.y).powi(2);
         if dist_sq < (radius as f64).powi(2) && self.interested_peers.insert(peer) {
@@ -55,6 +59,7 @@ impl InterestTracker {
 
     pub(crate) fn remove_peer(&mut self, peer: PeerId) {
         self.interested_peers.remove(&peer);
+        self.peer_pos.remove(&peer);
         self.added_any.retain(|p| p != &peer);
         self.lost_interest.retain(|p| p != &peer);
     }
@@ -63,6 +68,14 @@ impl InterestTracker {
         self.interested_peers.iter().copied()
     }
 
+    pub(crate) fn iter_interested_with_pos(
+        &self,
+    ) -> impl Iterator<Item = (PeerId, (f32, f32))> + '_ {
+        self.interested_peers
+            .iter()
+            .filter_map(|peer| Some((*peer, *self.peer_pos.get(peer)?)))
+    }
+
     pub(crate) fn contains(&self, peer_id: PeerId) -> bool {
         self.interested_peers.contains(&peer_id)
     }
@@ -0,0 +1,194 @@
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+
+/// How many position updates are kept per entity.
+const MAX_SAMPLES: usize = 8;
+/// Entities that moved further than that between two updates were teleported, and shouldn't be smoothed.
+const TELEPORT_DISTANCE: f32 = 256.0;
+/// Entities only send position when it changes, so a long gap means the entity stood still until now.
+const MAX_INTERVAL: Duration = Duration::from_millis(100);
+
+/// How remote entities are moved between updates, set from proxy options.
+pub(crate) struct SmoothingConfig {
+    pub(crate) enabled: bool,
+    /// Entities are shown this far in the past, so that there is usually a newer update to interpolate towards.
+    pub(crate) delay: Duration,
+    /// How far past the last update entities are moved using their velocity when updates are late.
+    pub(crate) max_extrapolation: Duration,
+    /// Entities with any of these tags are shown exactly where the last update put them.
+    pub(crate) exclude_tags: Vec<String>,
+}
+
+impl Default for SmoothingConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            delay: Duration::from_millis(50),
+            max_extrapolation: Duration::from_millis(150),
+            exclude_tags: Vec::new(),
+        }
+    }
+}
+
+struct Sample {
+    at: Instant,
+    x: f32,
+    y: f32,
+}
+
+/// Recent positions of a remote entity, along with the time they arrived at.
+#[derive(Default)]
+pub(crate) struct MotionBuffer {
+    samples: VecDeque<Sample>,
+    vx: f32,
+    vy: f32,
+}
+
+impl MotionBuffer {
+    pub(crate) fn push_position(&mut self, at: Instant, x: f32, y: f32) {
+        if let Some(last) = self.samples.back_mut() {
+            if (last.x - x).hypot(last.y - y) > TELEPORT_DISTANCE {
+                self.samples.clear();
+            } else if let Some(start) = at.checked_sub(MAX_INTERVAL)
+                && last.at < start
+            {
+                // Otherwise the whole time it stood still would be spent moving to the new position.
+                last.at = start;
+            }
+        }
+        if self.samples.len() == MAX_SAMPLES {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(Sample { at, x, y });
+    }
+
+    /// Velocity is in pixels per second.
+    pub(crate) fn set_velocity(&mut self, vx: f32, vy: f32) {
+        (self.vx, self.vy) = (vx, vy);
+    }
+
+    /// Where the entity should be shown at `now`, `None` if nothing was received yet.
+    pub(crate) fn position_at(
+        &mut self,
+        now: Instant,
+        config: &SmoothingConfig,
+    ) -> Option<(f32, f32)> {
+        let render_at = now.checked_sub(config.delay).unwrap_or(now);
+        // Only the last sample before `render_at` is needed to interpolate.
+        while self.samples.len() > 1 && self.samples[1].at <= render_at {
+            self.samples.pop_front();
+        }
+        let first = self.samples.front()?;
+        if render_at <= first.at {
+            return Some((first.x, first.y));
+        }
+        if let Some(next) = self.samples.get(1) {
+            let span = next.at.duration_since(first.at).as_secs_f32();
+            let t = if span > 0.0 {
+                render_at.duration_since(first.at).as_secs_f32() / span
+            } else {
+                1.0
+            };
+            return Some((
+                first.x + (next.x - first.x) * t,
+                first.y + (next.y - first.y) * t,
+            ));
+        }
+        let late = render_at
+            .duration_since(first.at)
+            .min(config.max_extrapolation)
+            .as_secs_f32();
+        Some((first.x + self.vx * late, first.y + self.vy * late))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn config() -> SmoothingConfig {
+        SmoothingConfig {
+            enabled: true,
+            ..Default::default()
+        }
+    }
+
+    fn assert_at(position: Option<(f32, f32)>, x: f32, y: f32) {
+        let (px, py) = position.unwrap();
+        assert!(
+            (px - x).abs() < 0.01 && (py - y).abs() < 0.01,
+            "at ({px}, {py}), expected ({x}, {y})"
+        );
+    }
+
+    #[test]
+    fn interpolates_between_samples() {
+        let config = config();
+        let start = Instant::now();
+        let mut motion = MotionBuffer::default();
+        assert!(motion.position_at(start, &config).is_none());
+        motion.push_position(start, 0.0, 0.0);
+        motion.push_position(start + Duration::from_millis(40), 20.0, -8.0);
+        motion.push_position(start + Duration::from_millis(80), 20.0, -8.0);
+
+        assert_at(motion.position_at(start, &config), 0.0, 0.0);
+        let now = start + config.delay + Duration::from_millis(10);
+        assert_at(motion.position_at(now, &config), 5.0, -2.0);
+        let now = start + config.delay + Duration::from_millis(30);
+        assert_at(motion.position_at(now, &config), 15.0, -6.0);
+        let now = start + config.delay + Duration::from_millis(60);
+        assert_at(motion.position_at(now, &config), 20.0, -8.0);
+    }
+
+    #[test]
+    fn moves_from_where_it_stood_still() {
+        let config = config();
+        let start = Instant::now();
+        let mut motion = MotionBuffer::default();
+        motion.push_position(start, 0.0, 0.0);
+        let moved = start + Duration::from_secs(2);
+        motion.push_position(moved, 10.0, 0.0);
+        let now = moved - MAX_INTERVAL / 2 + config.delay;
+        assert_at(motion.position_at(now, &config), 5.0, 0.0);
+    }
+
+    #[test]
+    fn extrapolation_is_capped() {
+        let config = config();
+        let start = Instant::now();
+        let mut motion = MotionBuffer::default();
+        motion.push_position(start, 10.0, 10.0);
+        motion.set_velocity(100.0, -50.0);
+
+        let now = start + config.delay + Duration::from_millis(100);
+        assert_at(motion.position_at(now, &config), 20.0, 5.0);
+        let limit = config.max_extrapolation.as_secs_f32();
+        let now = start + config.delay + Duration::from_secs(5);
+        assert_at(
+            motion.position_at(now, &config),
+            10.0 + 100.0 * limit,
+            10.0 - 50.0 * limit,
+        );
+    }
+
+    #[test]
+    fn teleports_are_not_smoothed() {
+        let config = config();
+        let start = Instant::now();
+        let mut motion = MotionBuffer::default();
+        motion.push_position(start, 0.0, 0.0);
+        let teleported = start + Duration::from_millis(20);
+        motion.push_position(teleported, TELEPORT_DISTANCE * 2.0, 0.0);
+
+        assert_at(
+            motion.position_at(teleported, &config),
+            TELEPORT_DISTANCE * 2.0,
+            0.0,
+        );
+        motion.push_position(teleported + Duration::from_millis(20), 0.0, 0.0);
+        let now = teleported + config.delay + Duration::from_millis(10);
+        assert_at(motion.position_at(now, &config), 0.0, 0.0);
+    }
+}
@@ -0,0 +1,67 @@
+use rustc_hash::FxHashMap;
+use shared::{
+    PeerId,
+    des::{
+        EntityUpdate,
+        compact::{CompactUpdates, UpdateDecoder, UpdateEncoder, UpdateEncoding},
+    },
+};
+
+/// Keeps compact entity update encoders and decoders for every peer.
+/// Peers tell which encoding they want in their interest requests, compact one is only used if both sides enable it.
+#[derive(Default)]
+pub(crate) struct UpdateCodecs {
+    pub(crate) enabled: bool,
+    peer_encoding: FxHashMap<PeerId, UpdateEncoding>,
+    encoders: FxHashMap<PeerId, UpdateEncoder>,
+    decoders: FxHashMap<PeerId, UpdateDecoder>,
+}
+
+impl UpdateCodecs {
+    pub(crate) fn our_encoding(&self) -> UpdateEncoding {
+        if self.enabled {
+            UpdateEncoding::Compact
+        } else {
+            UpdateEncoding::Plain
+        }
+    }
+
+    pub(crate) fn set_peer_encoding(&mut self, peer: PeerId, encoding: UpdateEncoding) {
+        if self.peer_encoding.insert(peer, encoding) != Some(encoding) {
+            self.reset_encoder(peer);
+        }
+    }
+
+    pub(crate) fn is_compact(&self, peer: PeerId) -> bool {
+        self.enabled && self.peer_encoding.get(&peer).copied() == Some(UpdateEncoding::Compact)
+    }
+
+    /// Encoder to use for updates sent to `peer`, `None` if it should get plain updates.
+    pub(crate) fn encoder_for(&mut self, peer: PeerId) -> Option<&mut UpdateEncoder> {
+        self.is_compact(peer)
+            .then(|| self.encoders.entry(peer).or_default())
+    }
+
+    /// Has to be called whenever the peer might've lost the entities we've sent before.
+    pub(crate) fn reset_encoder(&mut self, peer: PeerId) {
+        if let Some(encoder) = self.encoders.get_mut(&peer) {
+            encoder.reset();
+        }
+    }
+
+    pub(crate) fn decode(&mut self, source: PeerId, updates: CompactUpdates) -> Vec<EntityUpdate> {
+        self.decoders.entry(source).or_default().decode(updates)
+    }
+
+    /// Peer restarted, so it starts encoding from scratch and expects the same from us.
+    pub(crate) fn peer_reset(&mut self, peer: PeerId) {
+        self.reset_encoder(peer);
+        self.decoders.remove(&peer);
+    }
+
+    pub(crate) fn remove_peer(&mut self, peer: PeerId) {
+        self.peer_encoding.remove(&peer);
+        self.encoders.remove(&peer);
+        self.decoders.remove(&peer);
+    }
+}
@@ -1,4 +1,5 @@
 use std::{
+    collections::BTreeMap,
     fmt::Display,
     fs, mem,
     net::IpAddr,
@@ -6,18 +7,19 @@ use std::{
     path::PathBuf,
     process::exit,
     sync::{Arc, atomic::Ordering},
-    time::Duration,
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
 use arboard::Clipboard;
 use image::{DynamicImage::ImageRgba8, RgbaImage};
 use mod_manager::Modmanager;
+use poll_promise::Promise;
 use self_update::SelfUpdateManager;
 use serde::{Deserialize, Serialize};
 use steamworks::{LobbyId, SteamAPIInitError};
 use tangled::{Peer, Reliability};
 use tokio::time;
-use tracing::{error, info};
+use tracing::{error, info, warn};
 use unic_langid::LanguageIdentifier;
 
 use eframe::egui::{
@@ -32,20 +34,23 @@ use crate::{
         mod_manager,
         noita_launcher::{LaunchTokenResult, NoitaLauncher},
         releases::Version,
+        save_archive::{self, Imported},
         save_paths::SavePaths,
+        save_slots::{DEFAULT_SLOT, SaveSlots, SlotInfo, unix_now},
         save_state::SaveState,
         self_restart::SelfRestarter,
         self_update,
         settings::Settings,
     },
     cli::Args,
+    entity_inspector::EntityInspector,
     lang::{set_current_locale, tr},
     lobby_code::{LobbyCode, LobbyError, LobbyKind},
     net::{
-        NetManager, NetManagerInit, NetManagerPaths, RunInfo,
+        DeviceState, MAX_CHANNEL_NAME, NetManager, NetManagerInit, NetManagerPaths, RunInfo,
         messages::NetMsg,
         omni::{OmniPeerId, PeerVariant},
-        steam_networking,
+        snapshots, steam_networking,
     },
     paths::{self, Paths},
     player_cosmetics::{PlayerPngDesc, display_player_skin},
@@ -84,6 +89,7 @@ enum ConnectedMenu {
     Map,
     NoitaLog,
     ProxyLog,
+    Entities,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -104,6 +110,8 @@ pub struct AppSavedState {
     pub random_ports: bool,
     pub public_lobby: bool,
     pub allow_friends: bool,
+    /// Save slot used when hosting.
+    pub save_slot: String,
 }
 
 impl Default for AppSavedState {
@@ -121,6 +129,7 @@ impl Default for AppSavedState {
             random_ports: false,
             public_lobby: false,
             allow_friends: true,
+            save_slot: DEFAULT_SLOT.to_owned(),
         }
     }
 }
@@ -132,6 +141,8 @@ pub struct App {
     steam_state: Result<steam_helper::SteamState, SteamAPIInitError>,
     app_saved_state: AppSavedState,
     run_save_state: SaveState,
+    save_slots: SaveSlots,
+    slot_menu: SlotMenu,
     self_update: SelfUpdateManager,
     lobby_id_field: String,
     args: Args,
@@ -139,6 +150,9 @@ pub struct App {
     can_start_automatically: bool,
     player_image: RgbaImage,
     end_run_button: EndRunButton,
+    rollback_menu: RollbackMenu,
+    voice_channel_menu: VoiceChannelMenu,
+    recording_menu: RecordingMenu,
     appearance: PlayerAppearance,
     connected_menu: ConnectedMenu,
     show_host_settings: bool,
@@ -148,6 +162,9 @@ pub struct App {
     my_lobby_kind: LobbyKind,
     show_lobby_list: bool,
     map: ImageMap,
+    entity_inspector: EntityInspector,
+    /// Problems with loading the save state, shown until dismissed.
+    save_warnings: Vec<String>,
     refresh_timer: time::Instant,
     noitalog_number: usize,
     noitalog: Vec<String>,
@@ -223,7 +240,18 @@ impl App {
         cc.egui_ctx
             .set_zoom_factor(args.ui_zoom_factor.unwrap_or(default_zoom_factor));
         info!("Creating the app...");
-        let run_save_state = SaveState::new(&save_paths.save_state_path);
+        let save_slots = SaveSlots::new(&save_paths.save_state_path);
+        if let Some(slot) = &args.save_slot {
+            saved_state.save_slot = slot.clone();
+        }
+        if !save_slots.exists(&saved_state.save_slot) {
+            warn!(
+                "Save slot {} doesn't exist, using the default one",
+                saved_state.save_slot
+            );
+            saved_state.save_slot = DEFAULT_SLOT.to_owned();
+        }
+        let run_save_state = save_slots.save_state(&saved_state.save_slot);
         let player_image = if let Some(path) = &paths.noita_quantew_player_spritesheet
             && path.exists()
         {
@@ -246,8 +274,13 @@ impl App {
             args,
             can_start_automatically: false,
             run_save_state,
+            save_slots,
+            slot_menu: SlotMenu::default(),
             player_image,
             end_run_button: EndRunButton::default(),
+            rollback_menu: RollbackMenu::default(),
+            voice_channel_menu: VoiceChannelMenu::default(),
+            recording_menu: RecordingMenu::default(),
             appearance,
             connected_menu: ConnectedMenu::Normal,
             show_host_settings: false,
@@ -257,6 +290,8 @@ impl App {
             my_lobby_kind,
             show_lobby_list: false,
             map: Default::default(),
+            entity_inspector: Default::default(),
+            save_warnings: Vec::new(),
             refresh_timer: time::Instant::now(),
             noitalog_number: 0,
             noitalog: Vec::new(),
@@ -526,6 +561,7 @@ impl App {
                         ui.set_min_size(ui.available_size());
                         // heading_with_underline(ui, tr("Info"));
                         // ui.label(tr("info_stress_tests"));
+                        self.panel_save_slots(ui);
                     });
                 },
             );
@@ -564,7 +600,7 @@ impl App {
                                 self.show_audio_settings = !self.show_audio_settings
                             }
                             if self.show_audio_settings {
-                                self.audio.show_ui(ui, true);
+                                self.audio.show_ui(ui, true, None);
                             }
                             if self.running_on_steamdeck && ui.button("Close Proxy").clicked() {
                                 exit(0)
@@ -695,6 +731,20 @@ impl App {
         }
     }
 
+    fn panel_save_slots(&mut self, ui: &mut Ui) {
+        heading_with_underline(ui, "Save slot");
+        if let Some(slot) = self.slot_menu.show(
+            ui,
+            &self.save_slots,
+            &self.app_saved_state.save_slot,
+            &mut self.app_saved_state.game_settings,
+        ) {
+            info!("Loading save slot {slot}");
+            self.run_save_state = self.save_slots.save_state(&slot);
+            self.app_saved_state.save_slot = slot;
+        }
+    }
+
     fn panel_right_bar(&mut self, ui: &mut Ui, ctx: &Context) {
         let lang_label = self
             .app_saved_state
@@ -720,6 +770,7 @@ impl App {
         let secret_active = ui.input(|i| i.modifiers.ctrl && i.key_down(Key::D));
         if secret_active && ui.button("reset all data").clicked() {
             self.app_saved_state = Default::default();
+            self.run_save_state = self.save_slots.save_state(DEFAULT_SLOT);
             self.paths = Default::default();
             self.state = AppState::LangPick;
         }
@@ -971,6 +1022,7 @@ impl App {
             } else {
                 ui.label(tr("noita_not_yet"));
             }
+            show_recording_notice(ui, netman);
         });
         egui::SidePanel::left("players")
             .resizable(false)
@@ -1022,6 +1074,13 @@ impl App {
                 if !netman.active_mods.lock().unwrap().is_empty() {
                     ui.selectable_value(&mut self.connected_menu, ConnectedMenu::Mods, "Mod List");
                 }
+                if netman.peer.is_host() && self.app_saved_state.show_extra_debug_stuff {
+                    ui.selectable_value(
+                        &mut self.connected_menu,
+                        ConnectedMenu::Entities,
+                        "Entities",
+                    );
+                }
                 if last == ConnectedMenu::Settings && last != self.connected_menu {
                     let new_settings = self.app_saved_state.game_settings.clone();
                     *netman.pending_settings.lock().unwrap() = new_settings.clone();
@@ -1123,6 +1182,7 @@ impl App {
                     if netman.peer.is_host() {
                         ui.add_space(15.0);
                         self.end_run_button.show(ui, netman);
+                        self.rollback_menu.show(ui, netman);
                         ui.add_space(15.0);
                         {
                             let mut temp = netman.no_more_players.load(Ordering::Relaxed);
@@ -1160,6 +1220,7 @@ impl App {
                     }
                 }
                 ConnectedMenu::Map => self.map.ui(ui, netman, ctx),
+                ConnectedMenu::Entities => self.entity_inspector.ui(ui, netman),
                 ConnectedMenu::BanList => {
                     let mut ban_list = netman.ban_list.lock().unwrap();
                     let mut i = ban_list.len();
@@ -1198,7 +1259,19 @@ impl App {
                     }
                 },
                 ConnectedMenu::VoIP => {
-                    let mut save = self.audio.show_ui(ui, false);
+                    self.voice_channel_menu.show(ui, netman);
+                    ui.separator();
+                    self.recording_menu.show(ui, netman);
+                    ui.separator();
+                    show_audio_devices(ui, netman);
+                    if netman.is_server_muted() {
+                        ui.colored_label(Color32::RED, "The host muted you, nobody hears you");
+                    }
+                    let mut save = self.audio.show_ui(
+                        ui,
+                        false,
+                        Some((netman.input_meter.level_db(), netman.input_meter.is_open())),
+                    );
                     for peer in netman.peer.iter_peer_ids() {
                         if netman.peer.my_id() != peer {
                             ui.label(format!(
@@ -1393,6 +1466,7 @@ impl eframe::App for App {
                             }
                             if ui.button(tr("New-game")).clicked() {
                                 self.state = AppState::Connect;
+                                self.run_save_state.backup();
                                 self.run_save_state.reset();
                             }
                         });
@@ -1443,6 +1517,21 @@ impl eframe::App for App {
                 }
             }
         };
+        self.save_warnings
+            .extend(self.run_save_state.take_warnings());
+        if !self.save_warnings.is_empty() {
+            Window::new("Save could not be fully loaded")
+                .auto_sized()
+                .anchor(Align2::CENTER_CENTER, [0.0, 0.0])
+                .show(ctx, |ui| {
+                    for warning in &self.save_warnings {
+                        ui.label(warning);
+                    }
+                    if ui.button("Ok").clicked() {
+                        self.save_warnings.clear();
+                    }
+                });
+        }
     }
     fn on_exit(&mut self, _: Option<&eframe::glow::Context>) {
         self.set_settings()
@@ -1482,6 +1571,426 @@ impl EndRunButton {
     }
 }
 
+#[derive(Default)]
+struct RollbackMenu {
+    confirmation: Option<u64>,
+}
+
+impl RollbackMenu {
+    fn show(&mut self, ui: &mut Ui, netman: &mut NetManStopOnDrop) {
+        egui::CollapsingHeader::new("World snapshots").show(ui, |ui| {
+            let dir = snapshots::snapshot_dir(netman.init_settings.save_state.path());
+            let ids = snapshots::list(&dir);
+            if ids.is_empty() {
+                ui.label("No snapshots yet, one is taken every 5 minutes");
+            }
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs();
+            for id in ids {
+                ui.horizontal(|ui| {
+                    ui.label(format!("{} minutes ago", now.saturating_sub(id) / 60));
+                    if self.confirmation == Some(id) {
+                        if ui
+                            .add(
+                                Button::new("Really roll back the world?")
+                                    .small()
+                                    .fill(Color32::LIGHT_RED),
+                            )
+                            .clicked()
+                        {
+                            self.confirmation = None;
+                            *netman.rollback_to.lock().unwrap() = Some(id);
+                        }
+                        if ui.small_button("cancel").clicked() {
+                            self.confirmation = None;
+                        }
+                    } else if ui.small_button("roll back").clicked() {
+                        self.confirmation = Some(id);
+                    }
+                });
+            }
+        });
+    }
+}
+
+#[derive(Default)]
+struct VoiceChannelMenu {
+    new_channel: String,
+}
+
+impl VoiceChannelMenu {
+    fn show(&mut self, ui: &mut Ui, netman: &NetManStopOnDrop) {
+        let channels = netman.voice_channels.lock().unwrap().clone();
+        let mine = channels.get(&netman.peer.my_id()).cloned();
+        let mut members: BTreeMap<&str, usize> = BTreeMap::new();
+        for channel in channels.values() {
+            *members.entry(channel).or_default() += 1;
+        }
+        ui.label("Players in the same voice channel hear each other anywhere, everyone else only nearby.");
+        ui.horizontal(|ui| match &mine {
+            Some(channel) => {
+                ui.label(format!("In voice channel \"{channel}\""));
+                if ui.small_button("leave").clicked() {
+                    netman.join_voice_channel(None);
+                }
+            }
+            None => {
+                ui.label("Not in a voice channel");
+            }
+        });
+        for (channel, count) in members {
+            if mine.as_deref() == Some(channel) {
+                continue;
+            }
+            ui.horizontal(|ui| {
+                ui.label(format!("{channel} ({count})"));
+                if ui.small_button("join").clicked() {
+                    netman.join_voice_channel(Some(channel.to_owned()));
+                }
+            });
+        }
+        ui.horizontal(|ui| {
+            ui.add(
+                egui::TextEdit::singleline(&mut self.new_channel)
+                    .hint_text("party, red team...")
+                    .char_limit(MAX_CHANNEL_NAME)
+                    .desired_width(150.0),
+            );
+            let name = self.new_channel.trim();
+            if ui
+                .add_enabled(!name.is_empty(), Button::new("join"))
+                .clicked()
+            {
+                netman.join_voice_channel(Some(name.to_owned()));
+                self.new_channel.clear();
+            }
+        });
+    }
+}
+
+#[derive(Default)]
+struct RecordingMenu {
+    error: Option<String>,
+}
+
+impl RecordingMenu {
+    fn show(&mut self, ui: &mut Ui, netman: &NetManStopOnDrop) {
+        match netman.voice_recorder.recording_to() {
+            Some(dir) => {
+                ui.horizontal(|ui| {
+                    ui.colored_label(Color32::RED, "● recording voice");
+                    if ui.small_button("stop").clicked() {
+                        netman.stop_voice_recording();
+                    }
+                });
+                ui.label(format!("to {}", dir.display()));
+            }
+            None => {
+                if ui
+                    .button("start recording voice")
+                    .on_hover_text(
+                        "Records voice of every player and yours into separate files. Everyone in the lobby is told about it.",
+                    )
+                    .clicked()
+                {
+                    self.error = netman
+                        .start_voice_recording()
+                        .err()
+                        .map(|err| format!("Could not start recording: {err}"));
+                }
+            }
+        }
+        if let Some(error) = &self.error {
+            ui.colored_label(Color32::RED, error);
+        }
+    }
+}
+
+/// Devices voice is using, and whether they are the picked ones.
+fn show_audio_devices(ui: &mut Ui, netman: &NetManStopOnDrop) {
+    let input = netman.audio_devices.input.lock().unwrap().clone();
+    let output = netman.audio_devices.output.lock().unwrap().clone();
+    for (kind, state) in [("microphone", input), ("speakers", output)] {
+        match state {
+            DeviceState::Headless => {}
+            DeviceState::Active(name) => {
+                ui.label(format!("{kind}: {name}"));
+            }
+            DeviceState::Fallback { active, picked } => {
+                ui.colored_label(
+                    Color32::YELLOW,
+                    format!("{kind}: {picked} is unavailable, using {active} until it's back"),
+                );
+            }
+            DeviceState::Missing => {
+                ui.colored_label(Color32::RED, format!("{kind}: no device available"));
+            }
+        }
+    }
+}
+
+/// Lets everyone know whose voice is being recorded, us included.
+fn show_recording_notice(ui: &mut Ui, netman: &NetManStopOnDrop) {
+    let nicknames = netman.nicknames.lock().unwrap();
+    let mut recording: Vec<String> = netman
+        .recording_peers
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|peer| {
+            nicknames
+                .get(peer)
+                .cloned()
+                .unwrap_or_else(|| peer.to_string())
+        })
+        .collect();
+    if netman.voice_recorder.is_recording() {
+        recording.push("you".to_owned());
+    }
+    if !recording.is_empty() {
+        ui.colored_label(
+            Color32::RED,
+            format!("● voice is being recorded by {}", recording.join(", ")),
+        );
+    }
+}
+
+#[derive(Default)]
+struct SlotMenu {
+    /// Read from disk when first shown and after every change.
+    slots: Option<Vec<(String, SlotInfo)>>,
+    new_name: String,
+    delete_confirmation: bool,
+    restore_confirmation: Option<u64>,
+    archive_task: Option<Promise<eyre::Result<ArchiveDone>>>,
+    status: Option<String>,
+    error: Option<String>,
+}
+
+enum ArchiveDone {
+    Exported(PathBuf),
+    Imported(Imported),
+    Cancelled,
+}
+
+impl SlotMenu {
+    /// Returns a slot to switch to, or the current one if it has to be reloaded.
+    /// Importing a run also replaces `game_settings` with the ones it was hosted with.
+    fn show(
+        &mut self,
+        ui: &mut Ui,
+        save_slots: &SaveSlots,
+        current: &str,
+        game_settings: &mut GameSettings,
+    ) -> Option<String> {
+        let mut switch_to = None;
+        let slots = self.slots.get_or_insert_with(|| save_slots.list());
+        ScrollArea::vertical()
+            .id_salt("save slots")
+            .max_height(ui.available_height() - 90.0)
+            .show(ui, |ui| {
+                for (name, info) in slots.iter() {
+                    ui.horizontal(|ui| {
+                        if ui.selectable_label(name == current, name).clicked() && name != current {
+                            switch_to = Some(name.clone());
+                        }
+                        ui.small(slot_summary(info));
+                    });
+                }
+            });
+        ui.horizontal(|ui| {
+            ui.add(
+                egui::TextEdit::singleline(&mut self.new_name)
+                    .hint_text("new slot name")
+                    .desired_width(120.0),
+            );
+            let name = self.new_name.trim().to_owned();
+            if ui.small_button("create").clicked() {
+                self.finish(save_slots.create(&name), &name, &mut switch_to);
+            }
+            if ui
+                .small_button("duplicate")
+                .on_hover_text("Copy the current slot under the new name")
+                .clicked()
+            {
+                self.finish(save_slots.duplicate(current, &name), &name, &mut switch_to);
+            }
+            if self.delete_confirmation {
+                if ui
+                    .add(
+                        Button::new(format!("Really delete {current}?"))
+                            .small()
+                            .fill(Color32::LIGHT_RED),
+                    )
+                    .clicked()
+                {
+                    self.delete_confirmation = false;
+                    self.finish(save_slots.delete(current), DEFAULT_SLOT, &mut switch_to);
+                }
+                if ui.small_button("cancel").clicked() {
+                    self.delete_confirmation = false;
+                }
+            } else if ui.small_button("delete").clicked() {
+                self.delete_confirmation = true;
+            }
+        });
+        ui.horizontal(|ui| {
+            let idle = self.archive_task.is_none();
+            if ui
+                .add_enabled(idle, Button::new("export").small())
+                .on_hover_text("Pack the run into a file, so that someone else can host it")
+                .clicked()
+            {
+                let from = save_slots.path(current);
+                let game_settings = game_settings.clone();
+                let file_name = format!("{current}.zip");
+                self.archive_task = Some(Promise::spawn_thread("export-save", move || {
+                    let Some(to) = rfd::FileDialog::new()
+                        .add_filter("zip", &["zip"])
+                        .set_file_name(file_name)
+                        .save_file()
+                    else {
+                        return Ok(ArchiveDone::Cancelled);
+                    };
+                    save_archive::export(&from, &game_settings, &to)?;
+                    Ok(ArchiveDone::Exported(to))
+                }));
+            }
+            if ui
+                .add_enabled(idle, Button::new("import").small())
+                .on_hover_text("Import an exported run into a new slot")
+                .clicked()
+            {
+                let save_slots = save_slots.clone();
+                self.archive_task = Some(Promise::spawn_thread("import-save", move || {
+                    let Some(from) = rfd::FileDialog::new()
+                        .add_filter("zip", &["zip"])
+                        .pick_file()
+                    else {
+                        return Ok(ArchiveDone::Cancelled);
+                    };
+                    Ok(ArchiveDone::Imported(save_archive::import(
+                        &from,
+                        &save_slots,
+                    )?))
+                }));
+            }
+            if let Some(task) = self.archive_task.take() {
+                match task.try_take() {
+                    Ok(Ok(ArchiveDone::Exported(to))) => {
+                        self.error = None;
+                        self.status = Some(format!("Exported to {}", to.display()));
+                    }
+                    Ok(Ok(ArchiveDone::Imported(imported))) => {
+                        self.error = None;
+                        self.status = Some(format!(
+                            "Imported as {}, game settings were replaced with the ones of the run",
+                            imported.slot
+                        ));
+                        *game_settings = imported.game_settings;
+                        self.slots = None;
+                        switch_to = Some(imported.slot);
+                    }
+                    Ok(Ok(ArchiveDone::Cancelled)) => {}
+                    Ok(Err(err)) => self.error = Some(format!("{err:#}")),
+                    Err(task) => {
+                        self.archive_task = Some(task);
+                        ui.spinner();
+                    }
+                }
+            }
+        });
+        egui::CollapsingHeader::new("Backups").show(ui, |ui| {
+            let backups = save_slots.backups(current);
+            let ids = backups.list();
+            if ids.is_empty() {
+                ui.label("No backups yet, one is taken whenever a saved run is continued");
+            }
+            let now = unix_now();
+            for id in ids {
+                ui.horizontal(|ui| {
+                    ui.label(format!("{} hours ago", now.saturating_sub(id) / 3600));
+                    if self.restore_confirmation == Some(id) {
+                        if ui
+                            .add(
+                                Button::new("Really replace the current save?")
+                                    .small()
+                                    .fill(Color32::LIGHT_RED),
+                            )
+                            .clicked()
+                        {
+                            self.restore_confirmation = None;
+                            self.finish(
+                                backups.restore(id, &save_slots.path(current)),
+                                current,
+                                &mut switch_to,
+                            );
+                        }
+                        if ui.small_button("cancel").clicked() {
+                            self.restore_confirmation = None;
+                        }
+                    } else if ui.small_button("restore").clicked() {
+                        self.restore_confirmation = Some(id);
+                    }
+                });
+            }
+        });
+        if let Some(status) = &self.status {
+            ui.label(status);
+        }
+        if let Some(error) = &self.error {
+            ui.colored_label(Color32::LIGHT_RED, error);
+        }
+        switch_to
+    }
+
+    fn finish<T>(
+        &mut self,
+        result: std::io::Result<T>,
+        switch_to_slot: &str,
+        switch_to: &mut Option<String>,
+    ) {
+        match result {
+            Ok(_) => {
+                self.error = None;
+                self.status = None;
+                self.new_name.clear();
+                *switch_to = Some(switch_to_slot.to_owned());
+            }
+            Err(err) => self.error = Some(err.to_string()),
+        }
+        self.slots = None;
+    }
+}
+
+fn slot_summary(info: &SlotInfo) -> String {
+    let Some(last_played) = info.last_played else {
+        return "empty".to_owned();
+    };
+    let mut parts = Vec::new();
+    if let Some(mode) = info.game_mode {
+        parts.push(mode.to_string());
+    }
+    if let Some(seed) = info.seed {
+        parts.push(format!("seed {seed}"));
+    }
+    if let Some(world_num) = info.world_num {
+        parts.push(format!("world {world_num}"));
+    }
+    if !info.players.is_empty() {
+        parts.push(info.players.join(", "));
+    }
+    let hours = unix_now().saturating_sub(last_played) / 3600;
+    parts.push(if hours < 48 {
+        format!("{hours}h ago")
+    } else {
+        format!("{} days ago", hours / 24)
+    });
+    parts.join(" - ")
+}
+
 fn filled_group<R>(ui: &mut Ui, add_contents: impl FnOnce(&mut Ui) -> R) -> InnerResponse<R> {
     let style = ui.style();
     let frame = egui::Frame {
@@ -1530,8 +2039,22 @@ fn show_player_list(ui: &mut Ui, netman: &mut NetManStopOnDrop) {
     ScrollArea::vertical().auto_shrink(false).show(ui, |ui| {
         let nicknames = netman.nicknames.lock().unwrap().clone();
         let minas = netman.minas.lock().unwrap().clone();
+        let voice_channels = netman.voice_channels.lock().unwrap().clone();
+        let muted_peers = netman.muted_peers.lock().unwrap().clone();
+        let moderation = netman.voice_moderation.lock().unwrap().clone();
         for peer in netman.peer.iter_peer_ids().clone() {
-            let role = peer_role(peer, netman);
+            let mut role = peer_role(peer, netman);
+            if let Some(channel) = voice_channels.get(&peer) {
+                role = format!("{role}, voice: {channel}");
+            }
+            if moderation.server_muted.contains(&peer) {
+                role += ", server muted";
+            } else if muted_peers.contains(&peer) {
+                role += ", muted";
+            }
+            if moderation.priority.contains(&peer) {
+                role += ", priority speaker";
+            }
             let peer_str = peer.to_string().clone();
             let username = nicknames.get(&peer).unwrap_or(&peer_str);
             let mina = minas.get(&peer);
@@ -1556,6 +2079,7 @@ fn show_player_list(ui: &mut Ui, netman: &mut NetManStopOnDrop) {
                             if ui.button("Mods").clicked() {
                                 netman.send(peer, &NetMsg::RequestMods, Reliability::Reliable);
                             }
+                            show_voice_controls(ui, netman, peer);
                         });
                     }
                 }
@@ -1564,6 +2088,49 @@ fn show_player_list(ui: &mut Ui, netman: &mut NetManStopOnDrop) {
     });
 }
 
+/// Muting for us, and server muting and priority speakers for the host.
+fn show_voice_controls(ui: &mut Ui, netman: &NetManStopOnDrop, peer: OmniPeerId) {
+    let muted = netman.muted_peers.lock().unwrap().contains(&peer);
+    if ui.button(if muted { "Unmute" } else { "Mute" }).clicked() {
+        netman.set_peer_muted(peer, !muted);
+    }
+    if netman.peer.is_host() {
+        let moderation = netman.voice_moderation.lock().unwrap().clone();
+        let server_muted = moderation.server_muted.contains(&peer);
+        if ui
+            .button(if server_muted {
+                "Server unmute"
+            } else {
+                "Server mute"
+            })
+            .on_hover_text("Nobody hears them while server muted")
+            .clicked()
+        {
+            netman.moderate_voice(|moderation| {
+                if server_muted {
+                    moderation.server_muted.remove(&peer);
+                } else {
+                    moderation.server_muted.insert(peer);
+                }
+            });
+        }
+        let priority = moderation.priority.contains(&peer);
+        if ui
+            .selectable_label(priority, "Priority")
+            .on_hover_text("Everyone else is quieter while they talk")
+            .clicked()
+        {
+            netman.moderate_voice(|moderation| {
+                if priority {
+                    moderation.priority.remove(&peer);
+                } else {
+                    moderation.priority.insert(peer);
+                }
+            });
+        }
+    }
+}
+
 fn display_with_labels(
     img: RgbaImage,
     ui: &mut Ui,
@@ -1590,6 +2157,7 @@ fn display_with_labels(
                         if ui.button("Mods").clicked() {
                             netman.send(peer, &NetMsg::RequestMods, Reliability::Reliable);
                         }
+                        show_voice_controls(ui, netman, peer);
                     });
                 }
                 ui.label(RichText::new(label_top).size(14.0));
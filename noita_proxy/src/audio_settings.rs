@@ -2,11 +2,52 @@ use bitcode::{Decode, Encode};
 use cpal::traits::{DeviceTrait, HostTrait};
 use serde::{Deserialize, Serialize};
 
-use eframe::egui::{ComboBox, Slider, Ui};
+use eframe::egui::{ComboBox, ProgressBar, Slider, Ui};
 
-use std::collections::HashMap;
+use std::{collections::HashMap, time::Duration};
 
-use crate::net::omni::OmniPeerId;
+use crate::net::{audio_host, omni::OmniPeerId};
+
+/// How proximity voice is positioned around the listener.
+#[derive(Debug, Serialize, Deserialize, Decode, Encode, Clone, Copy, PartialEq, Eq, Default)]
+pub enum VoiceMode {
+    /// Centered, only volume depends on distance.
+    Mono,
+    /// Panned left or right depending on where the speaker is.
+    #[default]
+    Panned,
+    /// Panned, and pitch shifted when the speaker moves towards or away from the listener.
+    Spatial,
+}
+
+/// Preset for outgoing voice, adapted to the connection while playing.
+#[derive(Debug, Serialize, Deserialize, Decode, Encode, Clone, Copy, PartialEq, Eq, Default)]
+pub enum VoiceQuality {
+    Low,
+    #[default]
+    Normal,
+    High,
+}
+
+impl VoiceQuality {
+    fn label(self) -> &'static str {
+        match self {
+            VoiceQuality::Low => "low, for slow connections",
+            VoiceQuality::Normal => "normal",
+            VoiceQuality::High => "high",
+        }
+    }
+}
+
+impl VoiceMode {
+    fn label(self) -> &'static str {
+        match self {
+            VoiceMode::Mono => "mono",
+            VoiceMode::Panned => "panned",
+            VoiceMode::Spatial => "spatial, with doppler effect",
+        }
+    }
+}
 
 #[derive(Debug, Serialize, Deserialize, Decode, Encode, Clone)]
 #[serde(default)]
@@ -14,8 +55,12 @@ pub struct AudioSettings {
     pub volume: HashMap<OmniPeerId, f32>,
     pub dropoff: f32,
     pub range: u64,
-    //walls_strength: f32,
-    //max_wall_durability: u32,
+    /// How much terrain between players muffles their voice, 0 disables it.
+    pub walls_strength: f32,
+    /// Materials at least this durable muffle voice the most.
+    pub max_wall_durability: u32,
+    pub voice_mode: VoiceMode,
+    pub voice_quality: VoiceQuality,
     pub player_position: bool,
     pub global: bool,
     pub push_to_talk: bool,
@@ -29,6 +74,14 @@ pub struct AudioSettings {
     pub global_input_volume: f32,
     pub input_device: Option<String>,
     pub output_device: Option<String>,
+    /// Denoise voice before sending it.
+    pub noise_suppression: bool,
+    /// Only send voice while speaking, instead of all the time.
+    pub voice_activity: bool,
+    /// How much louder than background noise speech has to be, in dB.
+    pub voice_activity_threshold: f32,
+    /// How long voice keeps being sent after speaking stops.
+    pub voice_activity_hangover_ms: u32,
     pub input_devices: Vec<String>,
     pub output_devices: Vec<String>,
 }
@@ -40,8 +93,10 @@ impl Default for AudioSettings {
             dropoff: 1.0,
             range: 1024,
             global: false,
-            //walls_strength: 1.0,
-            //max_wall_durability: 14,
+            walls_strength: 1.0,
+            max_wall_durability: 14,
+            voice_mode: VoiceMode::default(),
+            voice_quality: VoiceQuality::default(),
             player_position: true,
             push_to_talk: true,
             mute_out: false,
@@ -51,6 +106,10 @@ impl Default for AudioSettings {
             disabled: false,
             input_device: None,
             output_device: None,
+            noise_suppression: false,
+            voice_activity: false,
+            voice_activity_threshold: 10.0,
+            voice_activity_hangover_ms: 300,
             input_devices: Vec::new(),
             output_devices: Vec::new(),
             global_output_volume: 1.0,
@@ -61,14 +120,51 @@ impl Default for AudioSettings {
 }
 
 impl AudioSettings {
-    pub fn show_ui(&mut self, ui: &mut Ui, main: bool) -> bool {
+    /// `input_level` is the level of the microphone in dBFS and whether it's being sent, if voice is running.
+    pub fn show_ui(&mut self, ui: &mut Ui, main: bool, input_level: Option<(f32, bool)>) -> bool {
         let mut changed = false;
+        if let Some((level_db, sending)) = input_level {
+            ui.add(
+                ProgressBar::new(((level_db + 60.0) / 60.0).clamp(0.0, 1.0)).text(if sending {
+                    "sending"
+                } else {
+                    "not sending"
+                }),
+            );
+            ui.ctx().request_repaint_after(Duration::from_millis(50));
+        }
         ui.label("drop off rate of audio from others");
         changed |= ui
             .add(Slider::new(&mut self.dropoff, 0.0..=128.0))
             .changed();
         ui.label("maximal range of audio");
         changed |= ui.add(Slider::new(&mut self.range, 0..=4096)).changed();
+        ui.label("how much walls muffle audio from others");
+        changed |= ui
+            .add(Slider::new(&mut self.walls_strength, 0.0..=8.0))
+            .changed();
+        ui.label("durability of materials that muffle the most");
+        changed |= ui
+            .add(Slider::new(&mut self.max_wall_durability, 1..=20))
+            .changed();
+        ComboBox::from_label("voice positioning")
+            .selected_text(self.voice_mode.label())
+            .show_ui(ui, |ui| {
+                for mode in [VoiceMode::Mono, VoiceMode::Panned, VoiceMode::Spatial] {
+                    changed |= ui
+                        .selectable_value(&mut self.voice_mode, mode, mode.label())
+                        .changed();
+                }
+            });
+        ComboBox::from_label("voice quality")
+            .selected_text(self.voice_quality.label())
+            .show_ui(ui, |ui| {
+                for quality in [VoiceQuality::Low, VoiceQuality::Normal, VoiceQuality::High] {
+                    changed |= ui
+                        .selectable_value(&mut self.voice_quality, quality, quality.label())
+                        .changed();
+                }
+            });
         ui.label("global input volume");
         changed |= ui
             .add(Slider::new(&mut self.global_input_volume, 0.0..=8.0))
@@ -101,17 +197,38 @@ impl AudioSettings {
             .checkbox(&mut self.mute_in_while_dead, "mute input while dead")
             .changed();
         changed |= ui.checkbox(&mut self.mute_out, "mute output").changed();
+        changed |= ui
+            .checkbox(&mut self.noise_suppression, "suppress background noise")
+            .on_hover_text("Applies when the next game starts")
+            .changed();
+        changed |= ui
+            .checkbox(&mut self.voice_activity, "only send voice while speaking")
+            .on_hover_text("Applies when the next game starts")
+            .changed();
+        if self.voice_activity {
+            ui.label("how much louder than background noise speech has to be, in dB");
+            changed |= ui
+                .add(Slider::new(&mut self.voice_activity_threshold, 0.0..=40.0))
+                .changed();
+            ui.label("how long to keep sending after speaking stops, in ms");
+            changed |= ui
+                .add(Slider::new(&mut self.voice_activity_hangover_ms, 0..=2000))
+                .changed();
+        }
         if main {
             changed |= ui.checkbox(&mut self.disabled, "disabled").changed();
-            if self.input_devices.is_empty() && !self.disabled {
-                #[cfg(target_os = "linux")]
-                let host = cpal::available_hosts()
-                    .into_iter()
-                    .find(|id| *id == cpal::HostId::Jack)
-                    .and_then(|id| cpal::host_from_id(id).ok())
-                    .unwrap_or(cpal::default_host());
-                #[cfg(not(target_os = "linux"))]
-                let host = cpal::default_host();
+        }
+        if !self.disabled {
+            if ui
+                .button("refresh devices")
+                .on_hover_text("Lists devices again, including ones plugged in since")
+                .clicked()
+            {
+                self.input_devices.clear();
+                self.output_devices.clear();
+            }
+            if self.input_devices.is_empty() {
+                let host = audio_host();
                 self.input_devices = host
                     .input_devices()
                     .map(|devices| devices.filter_map(|d| d.name().ok()).collect())
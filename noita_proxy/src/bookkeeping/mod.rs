@@ -2,7 +2,10 @@
 pub mod mod_manager;
 pub mod noita_launcher;
 pub mod releases;
+pub mod save_archive;
+pub mod save_backups;
 pub mod save_paths;
+pub mod save_slots;
 pub mod save_state;
 pub mod self_restart;
 pub mod self_update;
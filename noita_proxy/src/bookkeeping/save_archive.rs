@@ -0,0 +1,269 @@
+use std::{
+    fs::{self, File},
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
+};
+
+use eyre::{Context, bail, eyre};
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+use zip::{ZipArchive, ZipWriter, write::SimpleFileOptions};
+
+use super::{
+    save_backups::is_skipped,
+    save_slots::{DEFAULT_SLOT, MAX_SLOT_NAME_LEN, SaveSlots, SlotInfo},
+    save_state::write_atomic,
+};
+use crate::game_settings::GameSettings;
+
+/// Has to be bumped whenever layout of the archive changes.
+const ARCHIVE_VERSION: u32 = 1;
+const MANIFEST_NAME: &str = "manifest.ron";
+/// Save state files are stored under this directory in the archive.
+const SAVE_STATE_DIR: &str = "save_state";
+/// Settings the run in a save state was started with, written by host.
+const RUN_SETTINGS_NAME: &str = "game_settings.ron";
+/// Room left in slot names for the number added when a name is taken.
+const SLOT_SUFFIX_ROOM: usize = 8;
+
+#[derive(Serialize, Deserialize)]
+struct Manifest {
+    version: u32,
+    game_settings: GameSettings,
+    slot_info: SlotInfo,
+}
+
+/// A run imported into a new slot.
+pub struct Imported {
+    pub slot: String,
+    /// Settings the run was hosted with, to be used when hosting its continuation.
+    pub game_settings: GameSettings,
+}
+
+/// Remembers what the run in `save_state_path` was started with, so that it's exported along with it.
+pub fn write_run_settings(save_state_path: &Path, game_settings: &GameSettings) {
+    let settings = ron::to_string(game_settings).expect("game settings are serializable");
+    if let Err(err) = write_atomic(
+        &save_state_path.join(RUN_SETTINGS_NAME),
+        settings.as_bytes(),
+    ) {
+        warn!("Could not write settings of the run: {err}");
+    }
+}
+
+fn read_run_settings(save_state_path: &Path) -> Option<GameSettings> {
+    let settings = fs::read_to_string(save_state_path.join(RUN_SETTINGS_NAME)).ok()?;
+    ron::from_str(&settings).ok()
+}
+
+/// Packs the run in `save_state_path` along with settings it was started with into a single zip file,
+/// so that it can be continued by a different host. `game_settings` are only used for runs
+/// started before their settings were kept.
+pub fn export(save_state_path: &Path, game_settings: &GameSettings, to: &Path) -> eyre::Result<()> {
+    if !save_state_path.join("run_info.bit").exists() {
+        bail!("There is no run to export in this slot");
+    }
+    let manifest = Manifest {
+        version: ARCHIVE_VERSION,
+        game_settings: read_run_settings(save_state_path).unwrap_or_else(|| game_settings.clone()),
+        slot_info: SlotInfo::read(save_state_path),
+    };
+    let mut tmp = to.as_os_str().to_owned();
+    tmp.push(".tmp");
+    let result = write_archive(save_state_path, &manifest, Path::new(&tmp))
+        .and_then(|()| fs::rename(&tmp, to).wrap_err("Could not move the archive into place"));
+    if result.is_err() {
+        fs::remove_file(&tmp).ok();
+    }
+    result?;
+    info!("Exported {} to {}", save_state_path.display(), to.display());
+    Ok(())
+}
+
+fn write_archive(save_state_path: &Path, manifest: &Manifest, to: &Path) -> eyre::Result<()> {
+    let file = File::create(to).wrap_err("Could not create the archive")?;
+    let mut zip = ZipWriter::new(file);
+    let options = SimpleFileOptions::default();
+    zip.start_file(MANIFEST_NAME, options)?;
+    let manifest = ron::ser::to_string_pretty(manifest, Default::default())
+        .wrap_err("Could not write the manifest")?;
+    zip.write_all(manifest.as_bytes())?;
+    let mut stack = vec![(save_state_path.to_path_buf(), PathBuf::from(SAVE_STATE_DIR))];
+    while let Some((dir, in_archive)) = stack.pop() {
+        for entry in fs::read_dir(&dir)? {
+            let entry = entry?;
+            if dir == save_state_path && is_skipped(&entry.file_name()) {
+                continue;
+            }
+            let name = in_archive.join(entry.file_name());
+            if entry.file_type()?.is_dir() {
+                stack.push((entry.path(), name));
+                continue;
+            }
+            let name = name
+                .to_str()
+                .ok_or_else(|| eyre!("Non-unicode file name in save state"))?
+                .replace('\\', "/");
+            zip.start_file(name, options)?;
+            io::copy(&mut File::open(entry.path())?, &mut zip)?;
+        }
+    }
+    zip.finish()?.sync_all()?;
+    Ok(())
+}
+
+/// Unpacks an exported run into a new slot, named after the archive.
+pub fn import(archive: &Path, save_slots: &SaveSlots) -> eyre::Result<Imported> {
+    let mut zip = ZipArchive::new(File::open(archive).wrap_err("Could not open the archive")?)
+        .wrap_err("Not a save archive")?;
+    let manifest: Manifest = {
+        let mut text = String::new();
+        zip.by_name(MANIFEST_NAME)
+            .wrap_err("Not a save archive")?
+            .read_to_string(&mut text)?;
+        ron::from_str(&text).wrap_err("Could not read the manifest")?
+    };
+    if manifest.version > ARCHIVE_VERSION {
+        bail!(
+            "The archive was exported by a newer version of the proxy (version {}, this one supports {ARCHIVE_VERSION})",
+            manifest.version
+        );
+    }
+    let stem = archive
+        .file_stem()
+        .map(|stem| stem.to_string_lossy())
+        .unwrap_or_default();
+    let slot = free_slot_name(save_slots, &stem);
+    let path = save_slots.create(&slot)?;
+    if let Err(err) = extract(&mut zip, &path) {
+        save_slots.delete(&slot).ok();
+        return Err(err);
+    }
+    info!("Imported {} into slot {slot}", archive.display());
+    Ok(Imported {
+        slot,
+        game_settings: manifest.game_settings,
+    })
+}
+
+fn extract(zip: &mut ZipArchive<File>, to: &Path) -> eyre::Result<()> {
+    for i in 0..zip.len() {
+        let mut file = zip.by_index(i)?;
+        // Entries that would end up outside of the slot are skipped.
+        let Some(name) = file.enclosed_name() else {
+            continue;
+        };
+        let Ok(name) = name.strip_prefix(SAVE_STATE_DIR) else {
+            continue;
+        };
+        let target = to.join(name);
+        if file.is_dir() {
+            fs::create_dir_all(&target)?;
+            continue;
+        }
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        io::copy(&mut file, &mut File::create(&target)?)?;
+    }
+    Ok(())
+}
+
+/// Turns `base` into a valid slot name that isn't taken yet.
+fn free_slot_name(save_slots: &SaveSlots, base: &str) -> String {
+    let base: String = base
+        .chars()
+        .filter(|c| c.is_alphanumeric() || matches!(c, ' ' | '-' | '_'))
+        .collect();
+    let mut len = base.len().min(MAX_SLOT_NAME_LEN - SLOT_SUFFIX_ROOM);
+    while !base.is_char_boundary(len) {
+        len -= 1;
+    }
+    let base = match base[..len].trim() {
+        "" | DEFAULT_SLOT => "imported",
+        base => base,
+    };
+    (1..)
+        .map(|n| {
+            if n == 1 {
+                base.to_owned()
+            } else {
+                format!("{base} {n}")
+            }
+        })
+        .find(|name| !save_slots.exists(name))
+        .unwrap()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn export_and_import() {
+        let dir = std::env::temp_dir().join("ew_save_archive_test");
+        fs::remove_dir_all(&dir).ok();
+        let save = dir.join("save_state");
+        fs::create_dir_all(save.join("des_chunks")).unwrap();
+        fs::create_dir_all(save.join("snapshots")).unwrap();
+        fs::write(save.join("run_info.bit"), [1, 2]).unwrap();
+        fs::write(save.join("des_chunks/0_0.bit"), [3]).unwrap();
+        fs::write(save.join("snapshots/1.bit"), [4]).unwrap();
+        let settings = GameSettings {
+            seed: 1234,
+            ..Default::default()
+        };
+        let archive = dir.join("long run.zip");
+        export(&save, &settings, &archive).unwrap();
+
+        let slots = SaveSlots::new(&save);
+        let imported = import(&archive, &slots).unwrap();
+        assert_eq!(imported.slot, "long run");
+        assert_eq!(imported.game_settings, settings);
+        let path = slots.path(&imported.slot);
+        assert_eq!(fs::read(path.join("run_info.bit")).unwrap(), [1, 2]);
+        assert_eq!(fs::read(path.join("des_chunks/0_0.bit")).unwrap(), [3]);
+        assert!(!path.join("snapshots").exists());
+
+        assert_eq!(import(&archive, &slots).unwrap().slot, "long run 2");
+        assert!(export(&dir.join("empty"), &settings, &archive).is_err());
+    }
+
+    #[test]
+    fn exports_settings_run_was_started_with() {
+        let dir = std::env::temp_dir().join("ew_save_archive_settings_test");
+        fs::remove_dir_all(&dir).ok();
+        let save = dir.join("save_state");
+        fs::create_dir_all(&save).unwrap();
+        fs::write(save.join("run_info.bit"), [1]).unwrap();
+        let started_with = GameSettings {
+            seed: 1,
+            ..Default::default()
+        };
+        write_run_settings(&save, &started_with);
+        let current = GameSettings {
+            seed: 2,
+            ..Default::default()
+        };
+        let archive = dir.join("run.zip");
+        export(&save, &current, &archive).unwrap();
+        let imported = import(&archive, &SaveSlots::new(&save)).unwrap();
+        assert_eq!(imported.game_settings, started_with);
+    }
+
+    #[test]
+    fn long_names_fit_in_slot_names() {
+        let dir = std::env::temp_dir().join("ew_save_archive_names_test");
+        fs::remove_dir_all(&dir).ok();
+        let slots = SaveSlots::new(&dir.join("save_state"));
+        let names = ["забег".repeat(10), "a".repeat(MAX_SLOT_NAME_LEN)];
+        for name in names {
+            let first = free_slot_name(&slots, &name);
+            assert!(first.len() <= MAX_SLOT_NAME_LEN);
+            slots.create(&first).unwrap();
+            let second = free_slot_name(&slots, &name);
+            assert_eq!(second, format!("{first} 2"));
+            slots.create(&second).unwrap();
+        }
+    }
+}
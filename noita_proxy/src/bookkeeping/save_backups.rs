@@ -0,0 +1,151 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use tracing::info;
+
+use super::save_slots::{copy_dir, unix_now};
+
+/// How many backups are kept per slot, older ones are removed.
+pub const BACKUPS_KEPT: usize = 5;
+/// Not worth backing up: snapshots are backups on their own, and the chunk cache is only valid for one session.
+const SKIPPED: &[&str] = &["snapshots", "chunk_cache"];
+
+/// Copies of a whole save state directory, taken by the host before a run is continued.
+/// Backups are named after the unix time they were taken at.
+#[derive(Clone)]
+pub struct SaveBackups {
+    dir: PathBuf,
+}
+
+impl SaveBackups {
+    pub fn new(dir: PathBuf) -> Self {
+        Self { dir }
+    }
+
+    /// Ids of available backups, newest first.
+    pub fn list(&self) -> Vec<u64> {
+        let mut ids: Vec<u64> = fs::read_dir(&self.dir)
+            .map(|entries| {
+                entries
+                    .filter_map(|entry| entry.ok()?.file_name().to_str()?.parse().ok())
+                    .collect()
+            })
+            .unwrap_or_default();
+        ids.sort_unstable_by(|a, b| b.cmp(a));
+        ids
+    }
+
+    /// Backs up `save_state_path` and removes backups over the limit.
+    pub fn create(&self, save_state_path: &Path) -> io::Result<u64> {
+        let id = unix_now();
+        let path = self.dir.join(id.to_string());
+        if path.exists() {
+            return Ok(id);
+        }
+        // Copied under a name `list` ignores first, so that an interrupted backup is never restored.
+        let partial = self.dir.join(format!("{id}.partial"));
+        fs::remove_dir_all(&partial).ok();
+        copy_entries(save_state_path, &partial)?;
+        fs::rename(&partial, &path)?;
+        info!("Backed up {} as {id}", save_state_path.display());
+        for old in self.list().into_iter().skip(BACKUPS_KEPT) {
+            fs::remove_dir_all(self.dir.join(old.to_string())).ok();
+        }
+        Ok(id)
+    }
+
+    /// Replaces contents of `save_state_path` with the backup. Skipped entries are left as they are.
+    pub fn restore(&self, id: u64, save_state_path: &Path) -> io::Result<()> {
+        let path = self.dir.join(id.to_string());
+        if !path.is_dir() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("backup {id} doesn't exist"),
+            ));
+        }
+        // Staged next to the backups, so that a failed copy leaves the save state untouched.
+        let staged = self.dir.join(format!("{id}.restoring"));
+        fs::remove_dir_all(&staged).ok();
+        copy_dir(&path, &staged)?;
+        fs::create_dir_all(save_state_path)?;
+        for entry in fs::read_dir(save_state_path)? {
+            let entry = entry?;
+            if is_skipped(&entry.file_name()) {
+                continue;
+            }
+            if entry.file_type()?.is_dir() {
+                fs::remove_dir_all(entry.path())?;
+            } else {
+                fs::remove_file(entry.path())?;
+            }
+        }
+        for entry in fs::read_dir(&staged)? {
+            let entry = entry?;
+            fs::rename(entry.path(), save_state_path.join(entry.file_name()))?;
+        }
+        fs::remove_dir_all(&staged).ok();
+        info!("Restored backup {id} to {}", save_state_path.display());
+        Ok(())
+    }
+}
+
+pub(crate) fn is_skipped(name: &std::ffi::OsStr) -> bool {
+    name.to_str().is_some_and(|name| SKIPPED.contains(&name))
+}
+
+fn copy_entries(from: &Path, to: &Path) -> io::Result<()> {
+    fs::create_dir_all(to)?;
+    for entry in fs::read_dir(from)? {
+        let entry = entry?;
+        if is_skipped(&entry.file_name()) {
+            continue;
+        }
+        let target = to.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            copy_dir(&entry.path(), &target)?;
+        } else {
+            fs::copy(entry.path(), target)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn backup_and_restore() {
+        let dir = std::env::temp_dir().join("ew_save_backups_test");
+        fs::remove_dir_all(&dir).ok();
+        let save = dir.join("save_state");
+        fs::create_dir_all(save.join("snapshots")).unwrap();
+        fs::write(save.join("run_info.bit"), [1]).unwrap();
+        fs::write(save.join("snapshots/1.bit"), [1]).unwrap();
+        let backups = SaveBackups::new(dir.join("backups"));
+
+        let id = backups.create(&save).unwrap();
+        assert_eq!(backups.list(), [id]);
+        assert!(!dir.join(format!("backups/{id}/snapshots")).exists());
+
+        fs::write(save.join("run_info.bit"), [2]).unwrap();
+        fs::write(save.join("world_chunks.bit"), [2]).unwrap();
+        fs::write(save.join("snapshots/2.bit"), [2]).unwrap();
+        backups.restore(id, &save).unwrap();
+        assert_eq!(fs::read(save.join("run_info.bit")).unwrap(), [1]);
+        assert!(!save.join("world_chunks.bit").exists());
+        assert!(save.join("snapshots/2.bit").exists());
+        assert!(backups.restore(id + 1, &save).is_err());
+
+        for old in 0..BACKUPS_KEPT as u64 {
+            fs::create_dir_all(dir.join(format!("backups/{old}"))).unwrap();
+        }
+        fs::remove_dir_all(dir.join(format!("backups/{id}"))).unwrap();
+        backups.create(&save).unwrap();
+        let ids = backups.list();
+        assert_eq!(ids.len(), BACKUPS_KEPT);
+        assert!(!ids.contains(&0));
+    }
+}
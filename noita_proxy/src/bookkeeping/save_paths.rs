@@ -100,7 +100,6 @@ impl SavePaths {
         }
     }
 
-    #[expect(unused, reason = "Saving is done through Settings directly for now")]
     pub fn save_settings(&self, settings: Settings) {
         match settings.save(&self.settings_path) {
             Ok(()) => (),
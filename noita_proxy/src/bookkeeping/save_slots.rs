@@ -0,0 +1,234 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+use super::{
+    save_backups::SaveBackups,
+    save_state::{SaveState, write_atomic},
+};
+use crate::game_settings::GameMode;
+
+/// Slot that lives in the save state directory itself, where saves were before slots existed.
+pub const DEFAULT_SLOT: &str = "default";
+const SLOT_INFO_NAME: &str = "slot_info.ron";
+/// Longest slot name, in bytes.
+pub const MAX_SLOT_NAME_LEN: usize = 64;
+
+/// Metadata about a save slot, written by the host when the run is saved.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SlotInfo {
+    pub seed: Option<u64>,
+    pub game_mode: Option<GameMode>,
+    pub world_num: Option<u16>,
+    pub players: Vec<String>,
+    /// Unix time, in seconds.
+    pub last_played: Option<u64>,
+}
+
+impl SlotInfo {
+    pub fn read(dir: &Path) -> Self {
+        fs::read_to_string(dir.join(SLOT_INFO_NAME))
+            .ok()
+            .and_then(|s| ron::from_str(&s).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn write(&self, dir: &Path) {
+        let info = ron::to_string(self).expect("slot info is serializable");
+        if let Err(err) = write_atomic(&dir.join(SLOT_INFO_NAME), info.as_bytes()) {
+            warn!("Could not write slot info: {err}");
+        }
+    }
+}
+
+pub fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// Named save states, so that a host can keep several runs around.
+#[derive(Clone)]
+pub struct SaveSlots {
+    default: PathBuf,
+    root: PathBuf,
+}
+
+impl SaveSlots {
+    pub fn new(save_state_path: &Path) -> Self {
+        Self {
+            default: save_state_path.to_path_buf(),
+            root: save_state_path.with_file_name("save_slots"),
+        }
+    }
+
+    pub fn path(&self, name: &str) -> PathBuf {
+        if name == DEFAULT_SLOT {
+            self.default.clone()
+        } else {
+            self.root.join(name)
+        }
+    }
+
+    /// Save state of the slot, backed up to the slot's backups.
+    pub fn save_state(&self, name: &str) -> SaveState {
+        SaveState::new(self.path(name)).with_backups(self.backups(name))
+    }
+
+    /// Backups are kept outside of the slot, so that deleting or resetting it doesn't lose them.
+    pub fn backups(&self, name: &str) -> SaveBackups {
+        SaveBackups::new(self.default.with_file_name("save_backups").join(name))
+    }
+
+    pub fn exists(&self, name: &str) -> bool {
+        name == DEFAULT_SLOT || self.path(name).is_dir()
+    }
+
+    /// Every slot along with its metadata, default slot first.
+    pub fn list(&self) -> Vec<(String, SlotInfo)> {
+        let mut names: Vec<String> = fs::read_dir(&self.root)
+            .map(|entries| {
+                entries
+                    .filter_map(|entry| {
+                        let entry = entry.ok()?;
+                        entry.file_type().ok()?.is_dir().then_some(())?;
+                        entry.file_name().into_string().ok()
+                    })
+                    .filter(|name| name != DEFAULT_SLOT)
+                    .collect()
+            })
+            .unwrap_or_default();
+        names.sort_unstable();
+        names.insert(0, DEFAULT_SLOT.to_owned());
+        names
+            .into_iter()
+            .map(|name| {
+                let info = SlotInfo::read(&self.path(&name));
+                (name, info)
+            })
+            .collect()
+    }
+
+    pub fn create(&self, name: &str) -> io::Result<PathBuf> {
+        check_name(name)?;
+        let path = self.path(name);
+        if path.exists() {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("slot {name} already exists"),
+            ));
+        }
+        fs::create_dir_all(&path)?;
+        info!("Created save slot {name}");
+        Ok(path)
+    }
+
+    pub fn duplicate(&self, from: &str, to: &str) -> io::Result<PathBuf> {
+        let path = self.create(to)?;
+        if let Err(err) = copy_dir(&self.path(from), &path) {
+            fs::remove_dir_all(&path).ok();
+            return Err(err);
+        }
+        info!("Duplicated save slot {from} as {to}");
+        Ok(path)
+    }
+
+    /// Default slot can't be removed, it is emptied instead.
+    pub fn delete(&self, name: &str) -> io::Result<()> {
+        if name != DEFAULT_SLOT {
+            check_name(name)?;
+        }
+        let path = self.path(name);
+        fs::remove_dir_all(&path)?;
+        if name == DEFAULT_SLOT {
+            fs::create_dir_all(&path)?;
+        }
+        info!("Deleted save slot {name}");
+        Ok(())
+    }
+}
+
+/// Slot names are used as directory names, so only allow what's safe everywhere.
+fn check_name(name: &str) -> io::Result<()> {
+    let valid = !name.trim().is_empty()
+        && name != DEFAULT_SLOT
+        && name.len() <= MAX_SLOT_NAME_LEN
+        && name
+            .chars()
+            .all(|c| c.is_alphanumeric() || matches!(c, ' ' | '-' | '_'));
+    if valid {
+        Ok(())
+    } else {
+        Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("invalid slot name: {name:?}"),
+        ))
+    }
+}
+
+pub(crate) fn copy_dir(from: &Path, to: &Path) -> io::Result<()> {
+    fs::create_dir_all(to)?;
+    for entry in fs::read_dir(from)? {
+        let entry = entry?;
+        let target = to.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            copy_dir(&entry.path(), &target)?;
+        } else {
+            fs::copy(entry.path(), target)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn slots(name: &str) -> SaveSlots {
+        let dir = std::env::temp_dir().join(format!("ew_save_slots_test_{name}"));
+        fs::remove_dir_all(&dir).ok();
+        let save_state = dir.join("save_state");
+        fs::create_dir_all(&save_state).unwrap();
+        SaveSlots::new(&save_state)
+    }
+
+    #[test]
+    fn slot_lifecycle() {
+        let slots = slots("lifecycle");
+        let path = slots.create("long run").unwrap();
+        fs::write(path.join("run_info.bit"), [1, 2, 3]).unwrap();
+        SlotInfo {
+            seed: Some(42),
+            world_num: Some(2),
+            players: vec!["a".into(), "b".into()],
+            ..Default::default()
+        }
+        .write(&path);
+        assert!(slots.create("long run").is_err());
+        assert!(slots.create("../escape").is_err());
+        assert!(slots.create(DEFAULT_SLOT).is_err());
+
+        let copy = slots.duplicate("long run", "copy").unwrap();
+        assert_eq!(fs::read(copy.join("run_info.bit")).unwrap(), [1, 2, 3]);
+
+        let list = slots.list();
+        let names: Vec<&str> = list.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, [DEFAULT_SLOT, "copy", "long run"]);
+        assert_eq!(list[1].1.seed, Some(42));
+        assert_eq!(list[1].1.players.len(), 2);
+        assert_eq!(list[0].1.seed, None);
+
+        slots.delete("copy").unwrap();
+        assert!(!slots.exists("copy"));
+        assert!(slots.exists("long run"));
+        slots.delete(DEFAULT_SLOT).unwrap();
+        assert!(slots.path(DEFAULT_SLOT).is_dir());
+    }
+}
@@ -1,20 +1,44 @@
 use std::{
-    fs, io,
+    fs,
+    io::{self, Write},
     path::{Path, PathBuf},
     sync::{
-        Arc,
+        Arc, Mutex,
         atomic::{self, AtomicBool},
     },
 };
 
 use tracing::{error, info, warn};
 
+use super::save_backups::SaveBackups;
+
+/// Every entry file starts with this, followed by version of the entry as little endian u32.
+const HEADER_MAGIC: &[u8; 4] = b"EWSS";
+const HEADER_LEN: usize = HEADER_MAGIC.len() + 4;
+/// Version of files saved before entries had a header.
+pub const LEGACY_VERSION: u32 = 0;
+
 pub trait SaveStateEntry: bitcode::Encode + bitcode::DecodeOwned {
     const FILENAME: &'static str;
+    /// Has to be bumped whenever encoded layout of the entry changes,
+    /// and `migrate` has to learn how to read the previous one.
+    const VERSION: u32;
+
+    /// Reads `data` saved by an older `version` of the entry. `data` is already decompressed.
+    /// `None` means it can't be migrated, in which case the file is kept around and the user is warned.
+    /// By default only files saved before versions were introduced are read, as they're of the current layout.
+    fn migrate(version: u32, data: &[u8]) -> Option<Self> {
+        match version {
+            LEGACY_VERSION => bitcode::decode(data).ok(),
+            _ => None,
+        }
+    }
 }
 
 struct SaveStateInner {
     game_started: AtomicBool,
+    /// Problems while loading, to be shown to the user.
+    warnings: Mutex<Vec<String>>,
 }
 
 /// Allows persisting extra run state (like chunks). Cleared between runs.
@@ -23,6 +47,7 @@ pub struct SaveState {
     inner: Arc<SaveStateInner>,
     path: PathBuf,
     has_savestate: bool,
+    backups: Option<SaveBackups>,
 }
 
 impl SaveState {
@@ -41,8 +66,29 @@ impl SaveState {
             path,
             inner: Arc::new(SaveStateInner {
                 game_started: false.into(),
+                warnings: Default::default(),
             }),
             has_savestate,
+            backups: None,
+        }
+    }
+
+    pub(crate) fn with_backups(mut self, backups: SaveBackups) -> Self {
+        self.backups = Some(backups);
+        self
+    }
+
+    /// Backs up the save state, if there is a run to back up.
+    pub(crate) fn backup(&self) {
+        let Some(backups) = &self.backups else {
+            return;
+        };
+        if !self.path_for_filename("run_info").exists() {
+            info!("Nothing to back up");
+            return;
+        }
+        if let Err(err) = backups.create(&self.path) {
+            error!("Could not back up save state: {err}");
         }
     }
 
@@ -54,9 +100,13 @@ impl SaveState {
 
         let path = self.path_for_filename(D::FILENAME);
         let encoded = bitcode::encode(data);
-        let compressed = lz4_flex::compress_prepend_size(&encoded);
-        if let Err(err) = fs::write(&path, compressed) {
+        let mut file = Vec::with_capacity(HEADER_LEN + encoded.len());
+        file.extend_from_slice(HEADER_MAGIC);
+        file.extend_from_slice(&D::VERSION.to_le_bytes());
+        file.extend_from_slice(&lz4_flex::compress_prepend_size(&encoded));
+        if let Err(err) = write_atomic(&path, &file) {
             error!("Error while saving to {:?}: {err}", D::FILENAME);
+            return;
         }
         info!("Saved {}", path.display());
     }
@@ -72,12 +122,76 @@ impl SaveState {
                 }
             })
             .ok()?;
-        let data = lz4_flex::decompress_size_prepended(&data)
-            .inspect_err(|err| warn!("Could not decompress {:?}: {err}", D::FILENAME))
-            .ok()?;
-        bitcode::decode(&data)
-            .inspect_err(|err| error!("Could not decode {:?}: {err}", D::FILENAME))
-            .ok()
+        let (version, data) = split_header(&data);
+        let Ok(data) = lz4_flex::decompress_size_prepended(data) else {
+            self.keep_unreadable(&path, format!("{} is corrupted", D::FILENAME));
+            return None;
+        };
+        if version == D::VERSION {
+            return match bitcode::decode(&data) {
+                Ok(decoded) => Some(decoded),
+                Err(err) => {
+                    self.keep_unreadable(&path, format!("Could not decode {}: {err}", D::FILENAME));
+                    None
+                }
+            };
+        }
+        if version > D::VERSION {
+            self.keep_unreadable(
+                &path,
+                format!(
+                    "{} was saved by a newer version of the proxy (version {version}, this one supports {})",
+                    D::FILENAME,
+                    D::VERSION
+                ),
+            );
+            return None;
+        }
+        match D::migrate(version, &data) {
+            Some(migrated) => {
+                info!(
+                    "Migrated {} from version {version} to {}",
+                    D::FILENAME,
+                    D::VERSION
+                );
+                Some(migrated)
+            }
+            None => {
+                self.keep_unreadable(
+                    &path,
+                    format!(
+                        "{} can't be migrated from version {version} to {}",
+                        D::FILENAME,
+                        D::VERSION
+                    ),
+                );
+                None
+            }
+        }
+    }
+
+    /// Moves a file that couldn't be loaded out of the way, so that it isn't overwritten by the next save.
+    fn keep_unreadable(&self, path: &Path, problem: String) {
+        let mut kept = path.as_os_str().to_owned();
+        kept.push(".unreadable");
+        let warning = match fs::rename(path, &kept) {
+            Ok(()) => format!(
+                "{problem}. It was not loaded, and is kept at {}",
+                Path::new(&kept).display()
+            ),
+            Err(err) => format!("{problem}. It was not loaded, and could not be moved: {err}"),
+        };
+        error!("{warning}");
+        self.inner.warnings.lock().unwrap().push(warning);
+    }
+
+    /// Takes problems encountered while loading, if any.
+    pub(crate) fn take_warnings(&self) -> Vec<String> {
+        std::mem::take(&mut *self.inner.warnings.lock().unwrap())
+    }
+
+    pub(crate) fn is_game_started(&self) -> bool {
+        self.inner.game_started.load(atomic::Ordering::SeqCst)
     }
 
     pub(crate) fn mark_game_started(&self) {
@@ -96,7 +210,190 @@ impl SaveState {
         self.has_savestate
     }
 
+    /// Directory this save state lives in, for entries that don't fit in a single file.
+    pub(crate) fn path(&self) -> &Path {
+        &self.path
+    }
+
     fn path_for_filename(&self, filename: &str) -> PathBuf {
         self.path.join(format!("{filename}.bit"))
     }
 }
+
+/// Writes to a temporary file first and renames it over `path`,
+/// so that a crash while writing leaves the previous contents intact.
+pub(crate) fn write_atomic(path: &Path, data: &[u8]) -> io::Result<()> {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    let result = fs::File::create(&tmp)
+        .and_then(|mut file| {
+            file.write_all(data)?;
+            file.sync_all()
+        })
+        .and_then(|()| fs::rename(&tmp, path));
+    if result.is_err() {
+        fs::remove_file(&tmp).ok();
+    }
+    result
+}
+
+/// Returns version of the entry and the data following the header.
+fn split_header(file: &[u8]) -> (u32, &[u8]) {
+    match file.split_first_chunk::<HEADER_LEN>() {
+        Some((header, data)) if header.starts_with(HEADER_MAGIC) => {
+            let version = u32::from_le_bytes(header[HEADER_MAGIC.len()..].try_into().unwrap());
+            (version, data)
+        }
+        _ => (LEGACY_VERSION, file),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use bitcode::{Decode, Encode};
+
+    use super::*;
+
+    #[derive(Encode, Decode, Debug, PartialEq)]
+    struct OldEntry {
+        value: u32,
+    }
+
+    #[derive(Encode, Decode, Debug, PartialEq)]
+    struct Entry {
+        value: u64,
+        name: String,
+    }
+
+    impl SaveStateEntry for Entry {
+        const FILENAME: &'static str = "test_entry";
+        const VERSION: u32 = 2;
+
+        fn migrate(version: u32, data: &[u8]) -> Option<Self> {
+            match version {
+                LEGACY_VERSION | 1 => {
+                    let old: OldEntry = bitcode::decode(data).ok()?;
+                    Some(Self {
+                        value: old.value.into(),
+                        name: String::new(),
+                    })
+                }
+                _ => None,
+            }
+        }
+    }
+
+    fn save_state(name: &str) -> SaveState {
+        let path = std::env::temp_dir().join(format!("ew_save_state_test_{name}"));
+        fs::remove_dir_all(&path).ok();
+        let state = SaveState::new(&path);
+        state.mark_game_started();
+        state
+    }
+
+    fn write_raw(state: &SaveState, header: Option<u32>, data: &[u8]) {
+        let mut file = Vec::new();
+        if let Some(version) = header {
+            file.extend_from_slice(HEADER_MAGIC);
+            file.extend_from_slice(&version.to_le_bytes());
+        }
+        file.extend_from_slice(&lz4_flex::compress_prepend_size(data));
+        fs::write(state.path_for_filename(Entry::FILENAME), file).unwrap();
+    }
+
+    #[test]
+    fn round_trip() {
+        let state = save_state("round_trip");
+        let entry = Entry {
+            value: 5,
+            name: "run".into(),
+        };
+        state.save(&entry);
+        assert_eq!(state.load::<Entry>(), Some(entry));
+        assert!(state.take_warnings().is_empty());
+    }
+
+    #[test]
+    fn save_replaces_file_atomically() {
+        let state = save_state("atomic");
+        let path = state.path_for_filename(Entry::FILENAME);
+        let mut tmp = path.as_os_str().to_owned();
+        tmp.push(".tmp");
+        // Left over from a save that crashed halfway.
+        fs::write(&tmp, [1, 2]).unwrap();
+        let entry = Entry {
+            value: 1,
+            name: String::new(),
+        };
+        state.save(&entry);
+        assert_eq!(state.load::<Entry>(), Some(entry));
+        assert!(!Path::new(&tmp).exists());
+    }
+
+    #[test]
+    fn migrates_old_versions() {
+        let state = save_state("migrate");
+        let old = bitcode::encode(&OldEntry { value: 7 });
+        let expected = Some(Entry {
+            value: 7,
+            name: String::new(),
+        });
+        write_raw(&state, None, &old);
+        assert_eq!(state.load::<Entry>(), expected);
+        write_raw(&state, Some(1), &old);
+        assert_eq!(state.load::<Entry>(), expected);
+        assert!(state.take_warnings().is_empty());
+    }
+
+    #[derive(Encode, Decode, Debug, PartialEq)]
+    struct UnchangedEntry {
+        value: u32,
+    }
+
+    impl SaveStateEntry for UnchangedEntry {
+        const FILENAME: &'static str = "test_entry";
+        const VERSION: u32 = 1;
+    }
+
+    #[test]
+    fn reads_legacy_files_by_default() {
+        let state = save_state("legacy");
+        let entry = UnchangedEntry { value: 3 };
+        write_raw(&state, None, &bitcode::encode(&entry));
+        assert_eq!(state.load::<UnchangedEntry>(), Some(entry));
+        assert!(state.take_warnings().is_empty());
+    }
+
+    #[test]
+    fn reset_run_can_be_restored() {
+        let backups_dir = std::env::temp_dir().join("ew_save_state_test_reset_backups");
+        fs::remove_dir_all(&backups_dir).ok();
+        let backups = SaveBackups::new(backups_dir);
+        let state = save_state("reset").with_backups(backups.clone());
+        // Only runs are backed up, and `run_info` is what tells there is one.
+        fs::write(state.path_for_filename("run_info"), [1]).unwrap();
+        let entry = Entry {
+            value: 9,
+            name: "long run".into(),
+        };
+        state.save(&entry);
+        state.backup();
+        state.reset();
+        assert_eq!(state.load::<Entry>(), None);
+
+        let id = backups.list()[0];
+        backups.restore(id, state.path()).unwrap();
+        assert_eq!(state.load::<Entry>(), Some(entry));
+    }
+
+    #[test]
+    fn keeps_unreadable_files() {
+        let state = save_state("unreadable");
+        write_raw(&state, Some(3), &[1, 2, 3]);
+        assert_eq!(state.load::<Entry>(), None);
+        assert_eq!(state.take_warnings().len(), 1);
+        let path = state.path_for_filename(Entry::FILENAME);
+        assert!(!path.exists());
+        assert!(path.with_extension("bit.unreadable").exists());
+    }
+}
@@ -1,15 +1,26 @@
-use std::{net::SocketAddr, path::PathBuf, process::exit, thread::sleep, time::Duration};
+use std::{fs, net::SocketAddr, path::PathBuf, process::exit, thread::sleep, time::Duration};
 
 use argh::{FromArgValue, FromArgs};
+use shared::WorldPos;
 use tangled::Peer;
 
 use crate::{
     AudioSettings,
-    bookkeeping::{save_paths::SavePaths, save_state::SaveState, settings::Settings},
+    bookkeeping::{
+        save_archive,
+        save_paths::SavePaths,
+        save_slots::{SaveSlots, unix_now},
+        settings::Settings,
+    },
     game_settings::GameSettings,
     lobby_code::{LobbyCode, LobbyKind},
     mod_manager,
-    net::{NetManager, NetManagerInit, NetManagerPaths, omni::PeerVariant, steam_networking},
+    net::{
+        NetManager, NetManagerInit, NetManagerPaths,
+        entity_query::{EntityQuery, query_save_state},
+        omni::PeerVariant,
+        steam_networking,
+    },
     paths,
     player_cosmetics::PlayerPngDesc,
     steam_helper,
@@ -46,6 +57,10 @@ pub struct Args {
     #[argh(option)]
     pub save_state_path: Option<PathBuf>,
 
+    /// save slot to host from, instead of the one last picked in the gui
+    #[argh(option)]
+    pub save_slot: Option<String>,
+
     // Used internally.
     /// override lobby mode to use. Options: "Gog", "Steam".
     #[argh(option)]
@@ -57,6 +72,92 @@ pub struct Args {
     /// also run gdbserver when starting noita. Used for development.
     #[argh(switch)]
     pub run_noita_with_gdb: bool,
+
+    #[argh(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(FromArgs, PartialEq, Debug, Clone)]
+#[argh(subcommand)]
+pub enum Command {
+    Entities(EntitiesCommand),
+    Backups(BackupsCommand),
+    Export(ExportCommand),
+    Import(ImportCommand),
+}
+
+#[derive(FromArgs, PartialEq, Debug, Clone)]
+/// list entities in the entity storage of the save state.
+#[argh(subcommand, name = "entities")]
+pub struct EntitiesCommand {
+    /// only entities whose filename contains this.
+    #[argh(option)]
+    pub filename: Option<String>,
+    /// only entities within radius of a point, as "x,y,radius".
+    #[argh(option)]
+    pub region: Option<RegionArg>,
+    /// only entities with at least this much hp.
+    #[argh(option)]
+    pub min_hp: Option<f32>,
+    /// only entities with at most this much hp.
+    #[argh(option)]
+    pub max_hp: Option<f32>,
+    /// only entities that have a wand.
+    #[argh(switch)]
+    pub with_wand: bool,
+    /// only entities that don't have a wand.
+    #[argh(switch)]
+    pub without_wand: bool,
+    /// write results as json to this file instead of printing them.
+    #[argh(option)]
+    pub json: Option<PathBuf>,
+}
+
+#[derive(FromArgs, PartialEq, Debug, Clone)]
+/// list backups of the save slot, or restore one of them.
+#[argh(subcommand, name = "backups")]
+pub struct BackupsCommand {
+    /// id of the backup to restore over the save slot.
+    #[argh(option)]
+    pub restore: Option<u64>,
+}
+
+#[derive(FromArgs, PartialEq, Debug, Clone)]
+/// pack the run in the save slot, along with game settings, into a file another host can import.
+#[argh(subcommand, name = "export")]
+pub struct ExportCommand {
+    /// file to write the run to.
+    #[argh(positional)]
+    pub to: PathBuf,
+}
+
+#[derive(FromArgs, PartialEq, Debug, Clone)]
+/// import an exported run into a new save slot, and host from it from now on.
+#[argh(subcommand, name = "import")]
+pub struct ImportCommand {
+    /// exported run to import.
+    #[argh(positional)]
+    pub from: PathBuf,
+}
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub struct RegionArg(WorldPos, i32);
+
+impl FromArgValue for RegionArg {
+    fn from_arg_value(value: &str) -> Result<Self, String> {
+        let parts: Vec<i32> = value
+            .split(',')
+            .map(|part| {
+                part.trim()
+                    .parse()
+                    .map_err(|_| format!("bad number {part}"))
+            })
+            .collect::<Result<_, _>>()?;
+        match parts[..] {
+            [x, y, radius] => Ok(RegionArg(WorldPos { x, y }, radius)),
+            _ => Err("expected x,y,radius".to_string()),
+        }
+    }
 }
 
 impl FromArgValue for LobbyKind {
@@ -98,6 +199,10 @@ fn cli_setup(
     } = settings;
     paths.proxy_settings = Some(save_paths.settings_path.clone());
     paths.proxy_save_state = Some(save_paths.save_state_path.clone());
+    let slot = args
+        .save_slot
+        .clone()
+        .unwrap_or(saved_state.save_slot.clone());
     let mut state = steam_helper::SteamState::new(saved_state.spacewars).ok();
     let my_nickname = saved_state
         .nickname
@@ -112,7 +217,7 @@ fn cli_setup(
     }
     paths::realize_noita_paths_from_noita_exe(&mut paths);
     mod_manager::try_find_save_path(&mut paths);
-    let run_save_state = SaveState::new(save_paths.save_state_path);
+    let run_save_state = SaveSlots::new(&save_paths.save_state_path).save_state(&slot);
     let mut cosmetics = (false, false, false);
     if let Some(path) = &paths.noita_save {
         let flags = path.join("save00/persistent/flags");
@@ -214,3 +319,105 @@ pub fn host_cli(bind_addr: Option<SocketAddr>, args: Args) {
     *netman.settings.lock().unwrap() = game_settings;
     netman.start_inner(player_path, Some(kind)).unwrap();
 }
+
+/// Queries entity storage of the save state without starting anything.
+pub fn entities_cli(cmd: EntitiesCommand, args: Args) {
+    let save_paths = SavePaths::new_with_maybe_override(args.settings_path, args.save_state_path);
+    let slot = args
+        .save_slot
+        .unwrap_or_else(|| save_paths.load_settings().app.save_slot);
+    let save_state = SaveSlots::new(&save_paths.save_state_path).save_state(&slot);
+    let query = EntityQuery {
+        filename: cmd.filename,
+        region: cmd.region.map(|RegionArg(pos, radius)| (pos, radius)),
+        authority: None,
+        min_hp: cmd.min_hp,
+        max_hp: cmd.max_hp,
+        has_wand: match (cmd.with_wand, cmd.without_wand) {
+            (true, false) => Some(true),
+            (false, true) => Some(false),
+            _ => None,
+        },
+    };
+    let found = query_save_state(&save_state, &query);
+    if let Some(path) = cmd.json {
+        let json = serde_json::to_string_pretty(&found).expect("entity info is serializable");
+        if let Err(err) = fs::write(&path, json) {
+            println!("Could not write {}: {err}", path.display());
+            exit(1)
+        }
+        println!("Wrote {} entities to {}", found.len(), path.display());
+        return;
+    }
+    for info in &found {
+        println!(
+            "{:016x} {:>8} {:>8} hp {:>8.1}{} {}",
+            info.gid,
+            info.x,
+            info.y,
+            info.hp,
+            if info.has_wand { " wand" } else { "" },
+            info.filename.as_deref().unwrap_or("<serialized>")
+        );
+    }
+    println!("{} entities", found.len());
+}
+
+/// Lists or restores backups of the save slot without starting anything.
+pub fn backups_cli(cmd: BackupsCommand, args: Args) {
+    let save_paths = SavePaths::new_with_maybe_override(args.settings_path, args.save_state_path);
+    let slot = args
+        .save_slot
+        .unwrap_or_else(|| save_paths.load_settings().app.save_slot);
+    let save_slots = SaveSlots::new(&save_paths.save_state_path);
+    let backups = save_slots.backups(&slot);
+    if let Some(id) = cmd.restore {
+        if let Err(err) = backups.restore(id, &save_slots.path(&slot)) {
+            println!("Could not restore backup {id}: {err}");
+            exit(1)
+        }
+        println!("Restored backup {id} of slot {slot}");
+        return;
+    }
+    let ids = backups.list();
+    let now = unix_now();
+    for id in &ids {
+        println!("{id} ({} hours ago)", now.saturating_sub(*id) / 3600);
+    }
+    println!("{} backups of slot {slot}", ids.len());
+}
+
+/// Exports the run in the save slot without starting anything.
+pub fn export_cli(cmd: ExportCommand, args: Args) {
+    let save_paths = SavePaths::new_with_maybe_override(args.settings_path, args.save_state_path);
+    let settings = save_paths.load_settings();
+    let slot = args.save_slot.unwrap_or(settings.app.save_slot);
+    let from = SaveSlots::new(&save_paths.save_state_path).path(&slot);
+    if let Err(err) = save_archive::export(&from, &settings.app.game_settings, &cmd.to) {
+        println!("Could not export slot {slot}: {err:#}");
+        exit(1)
+    }
+    println!("Exported slot {slot} to {}", cmd.to.display());
+}
+
+/// Imports a run into a new slot and makes it the one to host from.
+pub fn import_cli(cmd: ImportCommand, args: Args) {
+    let save_paths = SavePaths::new_with_maybe_override(args.settings_path, args.save_state_path);
+    let imported =
+        match save_archive::import(&cmd.from, &SaveSlots::new(&save_paths.save_state_path)) {
+            Ok(imported) => imported,
+            Err(err) => {
+                println!("Could not import {}: {err:#}", cmd.from.display());
+                exit(1)
+            }
+        };
+    let mut settings = save_paths.load_settings();
+    settings.app.game_settings = imported.game_settings;
+    settings.app.save_slot = imported.slot.clone();
+    save_paths.save_settings(settings);
+    println!(
+        "Imported {} as slot {}, it will be used for hosting along with its game settings",
+        cmd.from.display(),
+        imported.slot
+    );
+}
@@ -0,0 +1,198 @@
+use std::{fs, sync::atomic::Ordering, thread};
+
+use eframe::egui::{self, DragValue, ScrollArea, Ui};
+use shared::WorldPos;
+use tracing::{error, info};
+
+use crate::NetManStopOnDrop;
+use crate::net::entity_query::{AuthorityFilter, EntityInfo, EntityQuery, query_save_state};
+
+/// Debug panel for looking into DES entity storage.
+pub struct EntityInspector {
+    filename: String,
+    use_region: bool,
+    center: (i32, i32),
+    radius: i32,
+    authority: Option<AuthorityFilter>,
+    min_hp: Option<f32>,
+    max_hp: Option<f32>,
+    has_wand: Option<bool>,
+    results: Vec<EntityInfo>,
+    waiting_for_live: bool,
+}
+
+impl Default for EntityInspector {
+    fn default() -> Self {
+        Self {
+            filename: String::new(),
+            use_region: false,
+            center: (0, 0),
+            radius: 1024,
+            authority: None,
+            min_hp: None,
+            max_hp: None,
+            has_wand: None,
+            results: Vec::new(),
+            waiting_for_live: false,
+        }
+    }
+}
+
+impl EntityInspector {
+    fn query(&self) -> EntityQuery {
+        EntityQuery {
+            filename: (!self.filename.is_empty()).then(|| self.filename.clone()),
+            region: self.use_region.then_some((
+                WorldPos {
+                    x: self.center.0,
+                    y: self.center.1,
+                },
+                self.radius,
+            )),
+            authority: self.authority,
+            min_hp: self.min_hp,
+            max_hp: self.max_hp,
+            has_wand: self.has_wand,
+        }
+    }
+
+    pub fn ui(&mut self, ui: &mut Ui, netman: &NetManStopOnDrop) {
+        ui.horizontal(|ui| {
+            ui.label("filename contains");
+            ui.text_edit_singleline(&mut self.filename);
+        });
+        ui.horizontal(|ui| {
+            ui.checkbox(&mut self.use_region, "within");
+            ui.add(DragValue::new(&mut self.radius).range(0..=i32::MAX));
+            ui.label("of");
+            ui.add(DragValue::new(&mut self.center.0));
+            ui.add(DragValue::new(&mut self.center.1));
+            if ui.button("my position").clicked() {
+                self.center = (
+                    netman.player_pos.0.load(Ordering::Relaxed),
+                    netman.player_pos.1.load(Ordering::Relaxed),
+                );
+                self.use_region = true;
+            }
+        });
+        ui.horizontal(|ui| {
+            let text = match self.authority {
+                None => "any".to_string(),
+                Some(AuthorityFilter::Free) => "free".to_string(),
+                Some(AuthorityFilter::Peer(peer)) => netman
+                    .nicknames
+                    .lock()
+                    .unwrap()
+                    .get(&peer)
+                    .cloned()
+                    .unwrap_or(peer.to_string()),
+            };
+            egui::ComboBox::from_label("authority")
+                .selected_text(text)
+                .show_ui(ui, |ui| {
+                    ui.selectable_value(&mut self.authority, None, "any");
+                    ui.selectable_value(&mut self.authority, Some(AuthorityFilter::Free), "free");
+                    let nicknames = netman.nicknames.lock().unwrap();
+                    for peer in netman.peer.iter_peer_ids() {
+                        ui.selectable_value(
+                            &mut self.authority,
+                            Some(AuthorityFilter::Peer(peer)),
+                            nicknames.get(&peer).cloned().unwrap_or(peer.to_string()),
+                        );
+                    }
+                });
+            let text = match self.has_wand {
+                None => "any",
+                Some(true) => "with wand",
+                Some(false) => "without wand",
+            };
+            egui::ComboBox::from_label("wand")
+                .selected_text(text)
+                .show_ui(ui, |ui| {
+                    ui.selectable_value(&mut self.has_wand, None, "any");
+                    ui.selectable_value(&mut self.has_wand, Some(true), "with wand");
+                    ui.selectable_value(&mut self.has_wand, Some(false), "without wand");
+                });
+        });
+        ui.horizontal(|ui| {
+            hp_filter(ui, "min hp", &mut self.min_hp);
+            hp_filter(ui, "max hp", &mut self.max_hp);
+        });
+        ui.horizontal(|ui| {
+            if ui.button("query live").clicked() {
+                *netman.entity_query.lock().unwrap() = Some(self.query());
+                self.waiting_for_live = true;
+            }
+            if ui.button("query save state").clicked() {
+                self.results = query_save_state(&netman.init_settings.save_state, &self.query());
+            }
+            if ui.button("export json").clicked() {
+                self.export();
+            }
+            ui.label(format!("{} entities", self.results.len()));
+        });
+        if self.waiting_for_live {
+            if let Some(results) = netman.entity_query_result.lock().unwrap().take() {
+                self.results = results;
+                self.waiting_for_live = false;
+            } else {
+                ui.ctx().request_repaint();
+            }
+        }
+        ScrollArea::vertical().auto_shrink(false).show(ui, |ui| {
+            egui::Grid::new("entity inspector grid")
+                .striped(true)
+                .show(ui, |ui| {
+                    ui.label("gid");
+                    ui.label("filename");
+                    ui.label("position");
+                    ui.label("hp");
+                    ui.label("wand");
+                    ui.label("authority");
+                    ui.end_row();
+                    for info in &self.results {
+                        ui.label(format!("{:016x}", info.gid));
+                        ui.label(info.filename.as_deref().unwrap_or("<serialized>"));
+                        ui.label(format!("{}, {}", info.x, info.y));
+                        ui.label(format!("{:.1}", info.hp));
+                        ui.label(if info.has_wand { "yes" } else { "" });
+                        ui.label(info.authority.as_deref().unwrap_or("free"));
+                        ui.end_row();
+                    }
+                });
+        });
+    }
+
+    fn export(&self) {
+        let json = match serde_json::to_string_pretty(&self.results) {
+            Ok(json) => json,
+            Err(err) => {
+                error!("Could not serialize entities: {err}");
+                return;
+            }
+        };
+        thread::spawn(move || {
+            let Some(path) = rfd::FileDialog::new()
+                .add_filter("json", &["json"])
+                .set_file_name("entities.json")
+                .save_file()
+            else {
+                return;
+            };
+            match fs::write(&path, json) {
+                Ok(()) => info!("Exported entities to {}", path.display()),
+                Err(err) => error!("Could not export entities: {err}"),
+            }
+        });
+    }
+}
+
+fn hp_filter(ui: &mut Ui, label: &str, value: &mut Option<f32>) {
+    let mut enabled = value.is_some();
+    if ui.checkbox(&mut enabled, label).changed() {
+        *value = enabled.then_some(0.0);
+    }
+    if let Some(value) = value {
+        ui.add(DragValue::new(value).speed(1.0));
+    }
+}
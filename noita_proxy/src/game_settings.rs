@@ -32,6 +32,12 @@ pub struct GameSettings {
     pub physics_damage: Option<bool>,
     pub share_gold: Option<bool>,
     pub nice_terraforming: Option<bool>,
+    pub chunk_load_balancing: Option<bool>,
+    pub entity_smoothing: Option<bool>,
+    pub entity_smoothing_delay: Option<u32>,
+    pub entity_max_extrapolation: Option<u32>,
+    pub entity_smoothing_exclude: Option<String>,
+    pub compact_entity_updates: Option<bool>,
     pub same_loadout: Option<bool>,
     pub disable_kummitus: Option<bool>,
     pub give_host_sampo: Option<bool>,
@@ -66,6 +72,12 @@ pub struct DefaultSettings {
     pub physics_damage: bool,
     pub share_gold: bool,
     pub nice_terraforming: bool,
+    pub chunk_load_balancing: bool,
+    pub entity_smoothing: bool,
+    pub entity_smoothing_delay: u32,
+    pub entity_max_extrapolation: u32,
+    pub entity_smoothing_exclude: String,
+    pub compact_entity_updates: bool,
     pub same_loadout: bool,
     pub duplicate: bool,
     pub disable_kummitus: bool,
@@ -103,6 +115,12 @@ impl Default for DefaultSettings {
             physics_damage: true,
             share_gold: false,
             nice_terraforming: true,
+            chunk_load_balancing: false,
+            entity_smoothing: true,
+            entity_smoothing_delay: 50,
+            entity_max_extrapolation: 150,
+            entity_smoothing_exclude: String::new(),
+            compact_entity_updates: true,
             same_loadout: false,
             duplicate: false,
             disable_kummitus: false,
@@ -439,6 +457,79 @@ impl GameSettings {
                     game_settings.nice_terraforming = Some(temp)
                 }
             }
+            {
+                let mut temp = game_settings
+                    .chunk_load_balancing
+                    .unwrap_or(def.chunk_load_balancing);
+                if ui
+                    .checkbox(
+                        &mut temp,
+                        "move chunks away from players with slow pc or connection",
+                    )
+                    .changed()
+                {
+                    game_settings.chunk_load_balancing = Some(temp)
+                }
+            }
+            {
+                let mut temp = game_settings
+                    .entity_smoothing
+                    .unwrap_or(def.entity_smoothing);
+                if ui
+                    .checkbox(&mut temp, "smooth movement of synced entities")
+                    .changed()
+                {
+                    game_settings.entity_smoothing = Some(temp)
+                }
+                if temp {
+                    let mut temp = game_settings
+                        .entity_smoothing_delay
+                        .unwrap_or(def.entity_smoothing_delay);
+                    if ui
+                        .add(Slider::new(&mut temp, 0..=200).text("smoothing delay, ms"))
+                        .changed()
+                    {
+                        game_settings.entity_smoothing_delay = Some(temp)
+                    }
+                    let mut temp = game_settings
+                        .entity_max_extrapolation
+                        .unwrap_or(def.entity_max_extrapolation);
+                    if ui
+                        .add(
+                            Slider::new(&mut temp, 0..=500)
+                                .text("max prediction when updates are late, ms"),
+                        )
+                        .changed()
+                    {
+                        game_settings.entity_max_extrapolation = Some(temp)
+                    }
+                    let mut temp = game_settings
+                        .entity_smoothing_exclude
+                        .clone()
+                        .unwrap_or(def.entity_smoothing_exclude);
+                    ui.label("entity tags to not smooth, comma seperated");
+                    if ui
+                        .add_sized(
+                            [ui.available_width() - 30.0, 20.0],
+                            TextEdit::singleline(&mut temp),
+                        )
+                        .changed()
+                    {
+                        game_settings.entity_smoothing_exclude = Some(temp)
+                    }
+                }
+            }
+            {
+                let mut temp = game_settings
+                    .compact_entity_updates
+                    .unwrap_or(def.compact_entity_updates);
+                if ui
+                    .checkbox(&mut temp, "compact entity updates, uses less bandwidth")
+                    .changed()
+                {
+                    game_settings.compact_entity_updates = Some(temp)
+                }
+            }
             {
                 let mut temp = game_settings
                     .disable_kummitus
@@ -5,7 +5,9 @@ use std::{
 };
 
 pub use app::App;
-pub use cli::{Args, connect_cli, host_cli};
+pub use cli::{
+    Args, Command, backups_cli, connect_cli, entities_cli, export_cli, host_cli, import_cli,
+};
 pub use util::{lang, steam_helper};
 
 use audio_settings::AudioSettings;
@@ -25,6 +27,7 @@ mod util;
 
 mod app;
 mod audio_settings;
+mod entity_inspector;
 mod game_map;
 mod game_settings;
 mod player_settings;
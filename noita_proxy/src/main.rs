@@ -4,7 +4,10 @@ use eframe::{
     NativeOptions,
     egui::{IconData, ViewportBuilder},
 };
-use noita_proxy::{App, Args, connect_cli, host_cli, paths};
+use noita_proxy::{
+    App, Args, Command, backups_cli, connect_cli, entities_cli, export_cli, host_cli, import_cli,
+    paths,
+};
 use std::{
     backtrace, fs,
     fs::File,
@@ -91,7 +94,15 @@ async fn main() {
 
     info!("Launch command: {:?}", args.launch_cmd);
 
-    if let Some(host) = args.clone().host {
+    if let Some(Command::Entities(cmd)) = args.command.clone() {
+        entities_cli(cmd, args)
+    } else if let Some(Command::Backups(cmd)) = args.command.clone() {
+        backups_cli(cmd, args)
+    } else if let Some(Command::Export(cmd)) = args.command.clone() {
+        export_cli(cmd, args)
+    } else if let Some(Command::Import(cmd)) = args.command.clone() {
+        import_cli(cmd, args)
+    } else if let Some(host) = args.clone().host {
         let bind_addr = if host.eq_ignore_ascii_case("steam") {
             None
         } else {
@@ -1,6 +1,7 @@
-use audio::AudioManager;
+use audio::{AudioManager, DeviceStatus, InputMeter, Recorder, VoiceFrames, VoiceModeration};
 use bitcode::{Decode, Encode};
 use des::DesManager;
+use entity_query::{EntityInfo, EntityQuery};
 use image::DynamicImage::ImageRgba8;
 use image::{ImageBuffer, Rgba, RgbaImage};
 use messages::{MessageRequest, NetMsg};
@@ -9,12 +10,13 @@ use proxy_opt::ProxyOpt;
 use rustc_hash::{FxHashMap, FxHashSet};
 use shared::message_socket::MessageSocket;
 use shared::{Destination, NoitaInbound, NoitaOutbound, RemoteMessage, WorldPos};
+use snapshots::{Snapshots, WorldSnapshot};
 use socket2::{Domain, Socket, Type};
 use std::collections::HashMap;
-use std::fs::{File, create_dir, remove_dir_all};
+use std::fs::{self, File, create_dir, remove_dir_all};
 use std::io::Write;
 use std::path::PathBuf;
-use std::sync::atomic::{AtomicBool, AtomicI32, AtomicU16, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicI32, AtomicU16, AtomicU64, Ordering};
 use std::sync::mpsc::Sender;
 use std::sync::{Arc, Mutex};
 use std::{
@@ -25,6 +27,7 @@ use std::{
     thread::{self, JoinHandle},
     time::{Duration, Instant},
 };
+use world::authority_policy::{DistanceOnly, LoadAware};
 use world::{NoitaWorldUpdate, WorldManager};
 
 use crate::lobby_code::LobbyKind;
@@ -36,6 +39,8 @@ use crate::player_cosmetics::{PlayerPngDesc, create_player_png, get_player_skin}
 use crate::steam_helper::LobbyExtraData;
 use crate::{
     AudioSettings, DefaultSettings, GameSettings,
+    bookkeeping::save_archive,
+    bookkeeping::save_slots::{SlotInfo, unix_now},
     bookkeeping::save_state::{SaveState, SaveStateEntry},
     game_settings::{GameMode, LocalHealthMode},
 };
@@ -44,11 +49,15 @@ use tangled::Reliability;
 use tracing::{error, info, warn};
 mod audio;
 mod des;
+pub mod entity_query;
 pub mod messages;
 mod proxy_opt;
+pub mod snapshots;
 pub mod steam_networking;
 pub mod world;
 
+pub(crate) use audio::{DeviceState, MAX_CHANNEL_NAME, host as audio_host};
+
 pub(crate) fn ws_encode_proxy(key: &'static str, value: impl Display) -> NoitaInbound {
     let mut buf = Vec::new();
     buf.push(2);
@@ -79,6 +88,7 @@ pub(crate) struct RunInfo {
 
 impl SaveStateEntry for RunInfo {
     const FILENAME: &'static str = "run_info";
+    const VERSION: u32 = 1;
 }
 
 pub(crate) struct NetInnerState {
@@ -86,9 +96,12 @@ pub(crate) struct NetInnerState {
     world: WorldManager,
     des: DesManager,
     audio: Option<AudioManager>,
+    /// Peers whose connection can't keep up, voice isn't sent to them so that it doesn't delay world sync.
+    saturated: FxHashSet<OmniPeerId>,
     explosion_data: Vec<ExplosionData>,
     had_a_disconnect: bool,
     flags: FxHashSet<String>,
+    snapshots: Snapshots,
 }
 
 impl NetInnerState {
@@ -168,6 +181,7 @@ pub struct NetManagerPaths {
     pub noita_quantew_install: PathBuf,
     pub noita_quantew_player_spritesheet: PathBuf,
     pub noita_save: Option<PathBuf>,
+    pub voice_recordings: PathBuf,
 }
 
 impl NetManagerPaths {
@@ -175,10 +189,17 @@ impl NetManagerPaths {
         let noita_quantew_install = paths.noita_quantew_install.as_ref()?;
         let noita_quantew_player_spritesheet = paths.noita_quantew_player_spritesheet.as_ref()?;
         let noita_save = paths.noita_save.as_ref();
+        let voice_recordings = match &paths.proxy_save_state {
+            Some(save_state) => {
+                save_state.with_file_name(crate::paths::DEFAULT_VOICE_RECORDINGS_NAME)
+            }
+            None => crate::paths::proxy_exe_dir().join(crate::paths::DEFAULT_VOICE_RECORDINGS_NAME),
+        };
         Some(NetManagerPaths {
             noita_quantew_install: noita_quantew_install.clone(),
             noita_quantew_player_spritesheet: noita_quantew_player_spritesheet.clone(),
             noita_save: noita_save.cloned(),
+            voice_recordings,
         })
     }
 }
@@ -223,6 +244,21 @@ pub struct NetManager {
         crossbeam::channel::Receiver<NetMsg>,
     ),
     pub audio: Mutex<AudioSettings>,
+    /// Level of our microphone, updated while voice is running.
+    pub(crate) input_meter: Arc<InputMeter>,
+    /// Audio devices used for voice, and whether they're the picked ones.
+    pub(crate) audio_devices: Arc<DeviceStatus>,
+    /// Voice channel each peer is in, including us. Members of a channel hear each other everywhere.
+    pub(crate) voice_channels: Mutex<HashMap<OmniPeerId, String>>,
+    pub(crate) voice_recorder: Arc<Recorder>,
+    /// Peers that told us they're recording voice.
+    pub(crate) recording_peers: Mutex<FxHashSet<OmniPeerId>>,
+    /// Peers we muted, they don't send us their voice.
+    pub(crate) muted_peers: Mutex<FxHashSet<OmniPeerId>>,
+    /// Peers that muted us, we don't send them our voice.
+    muted_by: Mutex<FxHashSet<OmniPeerId>>,
+    /// Server mutes and priority speakers, set by the host.
+    pub(crate) voice_moderation: Mutex<VoiceModeration>,
     push_to_talk: AtomicBool,
     is_dead: AtomicBool,
     is_polied: AtomicBool,
@@ -234,6 +270,13 @@ pub struct NetManager {
     pub players_sprite: Mutex<FxHashMap<OmniPeerId, (Option<WorldPos>, bool, bool, RgbaImage)>>,
     pub reset_map: AtomicBool,
     colors: Mutex<FxHashMap<u16, u32>>,
+    /// Bytes sent since last upload rate sample.
+    sent_bytes: AtomicU64,
+    /// Snapshot the host wants to roll the world back to.
+    pub rollback_to: Mutex<Option<u64>>,
+    /// Query for live DES entity storage, answered in `entity_query_result`.
+    pub entity_query: Mutex<Option<EntityQuery>>,
+    pub entity_query_result: Mutex<Option<Vec<EntityInfo>>>,
 }
 
 impl NetManager {
@@ -264,6 +307,14 @@ impl NetManager {
             new_desc: Default::default(),
             loopback_channel: crossbeam::channel::unbounded(),
             audio: audio.into(),
+            input_meter: Default::default(),
+            audio_devices: Default::default(),
+            voice_channels: Default::default(),
+            voice_recorder: Default::default(),
+            recording_peers: Default::default(),
+            muted_peers: Default::default(),
+            muted_by: Default::default(),
+            voice_moderation: Default::default(),
             push_to_talk: Default::default(),
             is_dead: Default::default(),
             is_polied: Default::default(),
@@ -276,6 +327,10 @@ impl NetManager {
             no_chunkmap_to_players: AtomicBool::new(true),
             no_chunkmap: AtomicBool::new(true),
             colors: Default::default(),
+            sent_bytes: AtomicU64::new(0),
+            rollback_to: Default::default(),
+            entity_query: Default::default(),
+            entity_query_result: Default::default(),
         }
         .into()
     }
@@ -296,6 +351,7 @@ impl NetManager {
         } else {
             let encoded = lz4_flex::compress_prepend_size(&bitcode::encode(msg));
             let len = encoded.len();
+            self.sent_bytes.fetch_add(len as u64, Ordering::Relaxed);
             if let Err(err) = self.peer.send(peer, encoded.clone(), reliability) {
                 if cfg!(debug_assertions) {
                     warn!(
@@ -309,9 +365,105 @@ impl NetManager {
         }
     }
 
+    /// Moves us to `channel`, or out of our channel if `None`.
+    pub(crate) fn join_voice_channel(&self, channel: Option<String>) {
+        let my_id = self.peer.my_id();
+        let mut channels = self.voice_channels.lock().unwrap();
+        match &channel {
+            Some(channel) => channels.insert(my_id, channel.clone()),
+            None => channels.remove(&my_id),
+        };
+        drop(channels);
+        self.broadcast(&NetMsg::VoiceChannel(channel), Reliability::Reliable);
+    }
+
+    /// Starts recording voice and lets everyone know about it. Returns where it's recorded to.
+    pub(crate) fn start_voice_recording(&self) -> io::Result<PathBuf> {
+        let dir = self
+            .voice_recorder
+            .start(&self.init_settings.paths.voice_recordings)?;
+        // Tracks are named after peer ids.
+        let players: String = self
+            .nicknames
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(peer, nickname)| format!("{} {nickname}\n", peer.as_hex()))
+            .collect();
+        if let Err(err) = fs::write(dir.join("players.txt"), players) {
+            warn!("Could not write player names of the recording: {err}");
+        }
+        self.broadcast(&NetMsg::VoiceRecording(true), Reliability::Reliable);
+        Ok(dir)
+    }
+
+    /// Adds a player who showed up while recording to the player names of the recording.
+    fn add_recorded_player(&self, peer: OmniPeerId, nickname: &str) {
+        let Some(dir) = self.voice_recorder.recording_to() else {
+            return;
+        };
+        let result = fs::OpenOptions::new()
+            .append(true)
+            .create(true)
+            .open(dir.join("players.txt"))
+            .and_then(|mut file| writeln!(file, "{} {nickname}", peer.as_hex()));
+        if let Err(err) = result {
+            warn!("Could not write player names of the recording: {err}");
+        }
+    }
+
+    pub(crate) fn stop_voice_recording(&self) {
+        self.voice_recorder.stop();
+        self.broadcast(&NetMsg::VoiceRecording(false), Reliability::Reliable);
+    }
+
+    /// Mutes or unmutes `peer` for us, they stop sending us their voice while muted.
+    pub(crate) fn set_peer_muted(&self, peer: OmniPeerId, muted: bool) {
+        let mut muted_peers = self.muted_peers.lock().unwrap();
+        if muted {
+            muted_peers.insert(peer);
+        } else {
+            muted_peers.remove(&peer);
+        }
+        drop(muted_peers);
+        self.send(peer, &NetMsg::VoiceMute(muted), Reliability::Reliable);
+    }
+
+    /// Changes voice moderation for everyone, only does anything on the host.
+    pub(crate) fn moderate_voice(&self, change: impl FnOnce(&mut VoiceModeration)) {
+        if !self.is_host() {
+            return;
+        }
+        let mut moderation = self.voice_moderation.lock().unwrap();
+        change(&mut moderation);
+        let msg = NetMsg::VoiceModeration(moderation.clone());
+        drop(moderation);
+        self.broadcast(&msg, Reliability::Reliable);
+    }
+
+    /// Whether the host doesn't let us talk.
+    pub(crate) fn is_server_muted(&self) -> bool {
+        self.voice_moderation
+            .lock()
+            .unwrap()
+            .server_muted
+            .contains(&self.peer.my_id())
+    }
+
+    /// Whether `peer` is in the same voice channel as us.
+    fn shares_voice_channel(&self, peer: OmniPeerId) -> bool {
+        let channels = self.voice_channels.lock().unwrap();
+        channels
+            .get(&self.peer.my_id())
+            .is_some_and(|channel| channels.get(&peer) == Some(channel))
+    }
+
     pub(crate) fn broadcast(&self, msg: &NetMsg, reliability: Reliability) {
         let encoded = lz4_flex::compress_prepend_size(&bitcode::encode(msg));
         let len = encoded.len();
+        let peers = self.peer.iter_peer_ids().len().saturating_sub(1);
+        self.sent_bytes
+            .fetch_add((len * peers) as u64, Ordering::Relaxed);
         if let Err(err) = self.peer.broadcast(encoded, reliability) {
             warn!("Error while broadcasting message of len {}: {}", len, err)
         }
@@ -403,19 +555,29 @@ impl NetManager {
 
         let is_host = self.is_host();
         info!("Is host: {is_host}");
+        if is_host {
+            // Before anything of this session gets saved over it.
+            self.init_settings.save_state.backup();
+        }
 
         let audio_settings = self.audio.lock().unwrap().clone();
         let audio_state = if !audio_settings.disabled {
-            Some(AudioManager::new(audio_settings))
+            Some(AudioManager::new(
+                audio_settings,
+                self.input_meter.clone(),
+                self.voice_recorder.clone(),
+                self.audio_devices.clone(),
+            ))
         } else {
             None
         };
 
-        let (world, rx, recv, sendm, tx) = WorldManager::new(
+        let (mut world, rx, recv, sendm, tx) = WorldManager::new(
             is_host,
             self.peer.my_id(),
             self.init_settings.save_state.clone(),
         );
+        world.host_id = self.peer.host_id();
         let mut state = NetInnerState {
             ms: None,
             world,
@@ -424,8 +586,11 @@ impl NetManager {
             had_a_disconnect: false,
             flags: self.init_settings.save_state.load().unwrap_or_default(),
             audio: audio_state,
+            saturated: Default::default(),
+            snapshots: Snapshots::new(self.init_settings.save_state.path()),
         };
         let mut last_iter = Instant::now();
+        let mut last_upload_sample = Instant::now();
         let path = crate::player_path(self.init_settings.paths.noita_quantew_install.clone());
         let player_image = if path.exists() {
             image::open(path)
@@ -469,6 +634,15 @@ impl NetManager {
                 self.end_run(&mut state);
                 self.end_run.store(false, Ordering::Relaxed);
             }
+            let rollback_to = self.rollback_to.lock().unwrap().take();
+            if let Some(id) = rollback_to
+                && self.is_host()
+            {
+                self.rollback(&mut state, id);
+            }
+            if let Some(query) = self.entity_query.lock().unwrap().take() {
+                *self.entity_query_result.lock().unwrap() = Some(state.des.query(&query));
+            }
             self.local_connected
                 .store(state.ms.is_some(), Ordering::Relaxed);
             if state.ms.is_none() && self.accept_local.load(Ordering::SeqCst) {
@@ -562,6 +736,31 @@ impl NetManager {
                 self.do_message_request(msg)
             }
             state.world.update();
+            if self.is_host() && state.snapshots.is_due() {
+                state.snapshots.take(WorldSnapshot {
+                    world_num: state.world.world_num(),
+                    chunks: state.world.snapshot_chunks(),
+                    entities: state.des.snapshot_entities(),
+                });
+            }
+            let since_sample = last_upload_sample.elapsed();
+            if since_sample >= Duration::from_secs(1) {
+                let sent = self.sent_bytes.swap(0, Ordering::Relaxed);
+                state
+                    .world
+                    .set_upload_rate((sent as f32 / since_sample.as_secs_f32()) as u32);
+                let links = self.peer.link_quality();
+                state.saturated = links
+                    .iter()
+                    .filter(|(_, link)| link.saturated)
+                    .map(|(peer, _)| *peer)
+                    .collect();
+                if let Some(audio) = &state.audio {
+                    let quality = self.audio.lock().unwrap().voice_quality;
+                    audio.adapt(quality, links.into_values());
+                }
+                last_upload_sample = Instant::now();
+            }
 
             let updates = state.world.get_noita_updates();
             for update in updates {
@@ -589,6 +788,7 @@ impl NetManager {
             {
                 audio_data.push(data)
             }
+            let mut sent_frames = Vec::new();
             if !audio_data.is_empty() {
                 let audio = self.audio.lock().unwrap();
                 if !audio.mute_in
@@ -599,6 +799,7 @@ impl NetManager {
                     && (!audio.push_to_talk || self.push_to_talk.load(Ordering::Relaxed))
                     && !self.is_cess.load(Ordering::Relaxed)
                     && audio.global_input_volume != 0.0
+                    && !self.is_server_muted()
                 {
                     let (x, y) = if audio.player_position {
                         (
@@ -611,19 +812,39 @@ impl NetManager {
                             self.camera_pos.1.load(Ordering::Relaxed),
                         )
                     };
-                    let data = NetMsg::AudioData(
-                        audio_data,
-                        audio.global,
-                        x,
-                        y,
-                        audio.global_input_volume,
-                    );
+                    let voice = VoiceFrames {
+                        seq: audio_data[0].0,
+                        frames: audio_data.into_iter().map(|(_, frame)| frame).collect(),
+                        global: audio.global,
+                        pos: (x, y),
+                        volume: audio.global_input_volume,
+                    };
+                    sent_frames.clone_from(&voice.frames);
+                    let data = NetMsg::AudioData(voice);
+                    // Receivers conceal lost frames, resending them would only make them late.
                     if audio.loopback {
-                        self.send(self.peer.my_id(), &data, Reliability::Reliable)
+                        self.send(self.peer.my_id(), &data, Reliability::Unreliable)
+                    }
+                    let muted_by = self.muted_by.lock().unwrap();
+                    if state.saturated.is_empty() && muted_by.is_empty() {
+                        self.broadcast(&data, Reliability::Unreliable);
+                    } else {
+                        for peer in self.peer.iter_peer_ids() {
+                            if peer != self.peer.my_id()
+                                && !state.saturated.contains(&peer)
+                                && !muted_by.contains(&peer)
+                            {
+                                self.send(peer, &data, Reliability::Unreliable);
+                            }
+                        }
                     }
-                    self.broadcast(&data, Reliability::Reliable);
                 }
             }
+            if let Some(audio) = &mut state.audio {
+                audio.record_sent(&sent_frames);
+                audio.update_devices(&self.audio.lock().unwrap());
+                audio.set_priority_speakers(&self.voice_moderation.lock().unwrap().priority);
+            }
             let mut map = FxHashMap::default();
             while let Ok((ch, img)) = rx.try_recv() {
                 map.insert(ch, img);
@@ -688,6 +909,34 @@ impl NetManager {
                         id == self.peer.host_id(),
                         &mut self.players_sprite.lock().unwrap(),
                     );
+                    let channel = self
+                        .voice_channels
+                        .lock()
+                        .unwrap()
+                        .get(&self.peer.my_id())
+                        .cloned();
+                    if channel.is_some() {
+                        self.send(id, &NetMsg::VoiceChannel(channel), Reliability::Reliable);
+                    }
+                    if self.voice_recorder.is_recording() {
+                        self.send(id, &NetMsg::VoiceRecording(true), Reliability::Reliable);
+                        // Otherwise it's added once their name arrives.
+                        let nickname = self.nicknames.lock().unwrap().get(&id).cloned();
+                        if let Some(nickname) = nickname {
+                            self.add_recorded_player(id, &nickname);
+                        }
+                    }
+                    if self.muted_peers.lock().unwrap().contains(&id) {
+                        self.send(id, &NetMsg::VoiceMute(true), Reliability::Reliable);
+                    }
+                    if self.is_host() {
+                        let moderation = self.voice_moderation.lock().unwrap().clone();
+                        self.send(
+                            id,
+                            &NetMsg::VoiceModeration(moderation),
+                            Reliability::Reliable,
+                        );
+                    }
                     info!("Sending PlayerColor to {id}");
                     self.send(
                         id,
@@ -718,6 +967,9 @@ impl NetManager {
                 state.try_ms_write(&ws_encode_proxy("leave", id.as_hex()));
                 state.world.handle_peer_left(id);
                 state.des.noita_disconnected(id);
+                self.voice_channels.lock().unwrap().remove(&id);
+                self.recording_peers.lock().unwrap().remove(&id);
+                self.muted_by.lock().unwrap().remove(&id);
                 state.try_ms_write(&NoitaInbound::ProxyToDes(ProxyToDes::RemoveEntities(
                     id.into(),
                 )));
@@ -748,11 +1000,19 @@ impl NetManager {
         sendm: &Sender<FxHashMap<u16, u32>>,
     ) {
         match net_msg {
-            NetMsg::AudioData(data, global, tx, ty, vol) => {
+            NetMsg::AudioData(voice) => {
                 let Some(state_audio) = &mut state.audio else {
                     return;
                 };
-                if self.is_cess.load(Ordering::Relaxed) {
+                if self.is_cess.load(Ordering::Relaxed)
+                    || self.muted_peers.lock().unwrap().contains(&src)
+                    || self
+                        .voice_moderation
+                        .lock()
+                        .unwrap()
+                        .server_muted
+                        .contains(&src)
+                {
                     return;
                 }
                 let audio = self.audio.lock().unwrap().clone();
@@ -767,7 +1027,39 @@ impl NetManager {
                         self.camera_pos.1.load(Ordering::Relaxed),
                     )
                 };
-                state_audio.play_audio(audio, pos, src, data, global, (tx, ty), vol);
+                let max_durability = audio.max_wall_durability;
+                let in_channel = self.shares_voice_channel(src);
+                state_audio.play_audio(audio, pos, src, voice, in_channel, |from, to| {
+                    state.world.material_between(from, to, max_durability)
+                });
+            }
+            NetMsg::VoiceRecording(recording) => {
+                let mut peers = self.recording_peers.lock().unwrap();
+                if recording {
+                    peers.insert(src);
+                } else {
+                    peers.remove(&src);
+                }
+            }
+            NetMsg::VoiceMute(muted) => {
+                let mut muted_by = self.muted_by.lock().unwrap();
+                if muted {
+                    muted_by.insert(src);
+                } else {
+                    muted_by.remove(&src);
+                }
+            }
+            NetMsg::VoiceModeration(moderation) => {
+                if src == self.peer.host_id() {
+                    *self.voice_moderation.lock().unwrap() = moderation;
+                }
+            }
+            NetMsg::VoiceChannel(channel) => {
+                let mut channels = self.voice_channels.lock().unwrap();
+                match channel.as_deref().and_then(audio::channel_name) {
+                    Some(channel) => channels.insert(src, channel),
+                    None => channels.remove(&src),
+                };
             }
             NetMsg::PlayerPosition(x, y, is_dead, does_exist) => {
                 let map = &mut self.players_sprite.lock().unwrap();
@@ -835,7 +1127,10 @@ impl NetManager {
                     host,
                     &mut self.players_sprite.lock().unwrap(),
                 );
-                self.nicknames.lock().unwrap().insert(src, name);
+                let old_name = self.nicknames.lock().unwrap().insert(src, name.clone());
+                if old_name.as_ref() != Some(&name) {
+                    self.add_recorded_player(src, &name);
+                }
                 self.minas
                     .lock()
                     .unwrap()
@@ -975,6 +1270,9 @@ impl NetManager {
         info!("New stream connected");
 
         let settings = self.settings.lock().unwrap();
+        if self.is_host() {
+            save_archive::write_run_settings(self.init_settings.save_state.path(), &settings);
+        }
         let def = DefaultSettings::default();
         state.try_ms_write(&ws_encode_proxy("seed", settings.seed));
         let my_id = self.peer.my_id();
@@ -1136,7 +1434,47 @@ impl NetManager {
             "host_sampo",
             settings.give_host_sampo.unwrap_or(def.give_host_sampo),
         );
+        state.try_ws_write_option(
+            "entity_smoothing",
+            settings.entity_smoothing.unwrap_or(def.entity_smoothing),
+        );
+        state.try_ws_write_option(
+            "entity_smoothing_delay",
+            settings
+                .entity_smoothing_delay
+                .unwrap_or(def.entity_smoothing_delay),
+        );
+        state.try_ws_write_option(
+            "entity_max_extrapolation",
+            settings
+                .entity_max_extrapolation
+                .unwrap_or(def.entity_max_extrapolation),
+        );
+        state.try_ws_write_option(
+            "entity_smoothing_exclude",
+            settings
+                .entity_smoothing_exclude
+                .clone()
+                .unwrap_or(def.entity_smoothing_exclude)
+                .as_str(),
+        );
+        state.try_ws_write_option(
+            "compact_entity_updates",
+            settings
+                .compact_entity_updates
+                .unwrap_or(def.compact_entity_updates),
+        );
         state.world.nice_terraforming = settings.nice_terraforming.unwrap_or(def.nice_terraforming);
+        state.world.set_authority_policy(
+            if settings
+                .chunk_load_balancing
+                .unwrap_or(def.chunk_load_balancing)
+            {
+                Box::new(LoadAware::default())
+            } else {
+                Box::new(DistanceOnly)
+            },
+        );
         let rgb = self
             .new_desc
             .lock()
@@ -1510,6 +1848,7 @@ impl NetManager {
     }
 
     fn end_run(&self, state: &mut NetInnerState) {
+        self.init_settings.save_state.backup();
         self.init_settings.save_state.reset();
         {
             let mut settings = self.pending_settings.lock().unwrap();
@@ -1526,6 +1865,7 @@ impl NetManager {
                 settings.world_num += 1
             }
             *self.settings.lock().unwrap() = settings.clone();
+            save_archive::write_run_settings(self.init_settings.save_state.path(), &settings);
             state.world.reset();
             state.des.reset();
             state.flags.clear();
@@ -1534,6 +1874,23 @@ impl NetManager {
         }
         self.resend_game_settings();
     }
+
+    fn rollback(&self, state: &mut NetInnerState, id: u64) {
+        let Some(snapshot) = state.snapshots.load(id) else {
+            return;
+        };
+        if snapshot.world_num != state.world.world_num() {
+            warn!(
+                "Snapshot {id} is from world {}, not rolling back",
+                snapshot.world_num
+            );
+            return;
+        }
+        info!("Rolling back to snapshot {id}");
+        state.world.restore_snapshot(snapshot.chunks);
+        state.des.restore_entities(snapshot.entities);
+        self.reset_map.store(true, Ordering::Relaxed);
+    }
 }
 
 #[derive(Clone, Copy)]
@@ -1623,6 +1980,21 @@ impl Drop for NetManager {
             };
             self.init_settings.save_state.save(&run_info);
             info!("Saved run info");
+            if self.init_settings.save_state.is_game_started() {
+                let settings = self.settings.lock().unwrap();
+                SlotInfo {
+                    seed: Some(settings.seed),
+                    game_mode: Some(
+                        settings
+                            .game_mode
+                            .unwrap_or(DefaultSettings::default().game_mode),
+                    ),
+                    world_num: Some(settings.world_num),
+                    players: self.nicknames.lock().unwrap().values().cloned().collect(),
+                    last_played: Some(unix_now()),
+                }
+                .write(self.init_settings.save_state.path());
+            }
         } else {
             info!("Skip saving run info: not a host");
         }
@@ -1631,6 +2003,7 @@ impl Drop for NetManager {
 
 impl SaveStateEntry for FxHashSet<String> {
     const FILENAME: &'static str = "flags";
+    const VERSION: u32 = 1;
 }
 
 impl Drop for NetInnerState {
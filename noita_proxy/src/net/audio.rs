@@ -1,257 +1,342 @@
 use crate::AudioSettings;
-use crate::net::omni::OmniPeerId;
-use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
-use opus::{Application, Channels, Decoder, Encoder};
-use rodio::buffer::SamplesBuffer;
-use rodio::{OutputStream, OutputStreamBuilder, Sink};
-use rubato::{FftFixedIn, Resampler};
+use crate::audio_settings::{VoiceMode, VoiceQuality};
+use crate::net::omni::{LinkQuality, OmniPeerId};
+use backend::{
+    Capture, CpalCapture, FileCapture, MixerPlayback, NullCapture, Playback, RodioPlayback,
+    VoiceOutput,
+};
+use bitcode::{Decode, Encode};
+use capture::CapturePipeline;
+use input::InputProcessor;
+use jitter::JitterBuffer;
+use opus::{Channels, Decoder};
+use playback::{PeerVoice, VoiceControls};
+use quality::EncoderTarget;
+use recorder::Track;
+use rustc_hash::FxHashSet;
+use shared::WorldPos;
 use std::collections::HashMap;
+use std::env;
 use std::ops::Mul;
+use std::path::Path;
 use std::sync::mpsc::Receiver;
 use std::sync::mpsc::{self, TryRecvError};
+use std::sync::{Arc, Mutex};
 use std::thread;
-use std::time::Duration;
-use tracing::error;
+use std::time::{Duration, Instant};
 use tracing::log::warn;
 
+mod backend;
+mod capture;
+mod input;
+mod jitter;
+mod playback;
+mod quality;
+mod recorder;
+
+pub(crate) use backend::{DeviceState, DeviceStatus, host};
+pub(crate) use input::InputMeter;
+pub(crate) use recorder::Recorder;
+
 pub const SAMPLE_RATE: usize = 48000;
 pub const FRAME_SIZE: usize = 960;
 pub const CHANNELS: Channels = Channels::Mono;
+/// Most samples a single packet can hold.
+pub(crate) const MAX_PACKET_SIZE: usize = FRAME_SIZE * 3;
+/// Low-pass cutoff for voice that isn't muffled, it's played unfiltered.
+pub(crate) const OPEN_CUTOFF: f32 = 20_000.0;
+/// Low-pass cutoff for voice behind as much terrain as it gets.
+const MUFFLED_CUTOFF: f32 = 300.0;
+/// At `walls_strength` of 1, this many pixels of rock muffle voice by about two thirds.
+const WALL_THICKNESS: f32 = 64.0;
+/// Muffled voice is also quieter, by at most this much.
+const MAX_MUFFLED_ATTENUATION: f32 = 0.7;
+/// Terrain between players is checked again at most this often.
+const OCCLUSION_INTERVAL: Duration = Duration::from_millis(100);
+/// Volume of everyone else while a priority speaker talks.
+const PRIORITY_DUCKING: f32 = 0.3;
+/// Others stay ducked for this long after a priority speaker was last heard.
+const PRIORITY_HOLD: Duration = Duration::from_millis(300);
+/// Longest name of a voice channel, in characters.
+pub(crate) const MAX_CHANNEL_NAME: usize = 32;
+
+/// Voice channel name as it's shown, `None` if there's nothing left of it.
+pub(crate) fn channel_name(name: &str) -> Option<String> {
+    let name: String = name.trim().chars().take(MAX_CHANNEL_NAME).collect();
+    (!name.is_empty()).then_some(name)
+}
 
-/*/// For reference, Mina is 14 pixels high.
+/// Voice frames captured since the last tick.
+#[derive(Debug, Decode, Encode, Clone)]
+pub(crate) struct VoiceFrames {
+    /// Sequence number of the first frame, the rest follow it without gaps.
+    pub(crate) seq: u32,
+    pub(crate) frames: Vec<Vec<u8>>,
+    pub(crate) global: bool,
+    pub(crate) pos: (i32, i32),
+    pub(crate) volume: f32,
+}
+
+/// Voice moderation of the host, everyone enforces it.
+#[derive(Debug, Decode, Encode, Clone, Default)]
+pub(crate) struct VoiceModeration {
+    /// Peers that aren't allowed to talk, they don't send voice at all.
+    pub(crate) server_muted: FxHashSet<OmniPeerId>,
+    /// Peers whose voice makes everyone else quieter.
+    pub(crate) priority: FxHashSet<OmniPeerId>,
+}
+
+/// For reference, Mina is 14 pixels high.
 const PIXELS_PER_METER: f32 = 14.0 / 1.7;
 /// In m/s.
 const SPEED_OF_SOUND: f32 = 343.0;
+/// Pitch is shifted by at most this factor either way, so that voice stays intelligible.
+const MAX_DOPPLER_SHIFT: f32 = 1.15;
+/// Voice this many pixels to the side is played on one channel only.
+const FULL_PAN_DISTANCE: f32 = 256.0;
 
 const SAMPLE_COUNT: usize = 4;
+const SAMPLE_INTERVAL: Duration = Duration::from_millis(16 * 4);
+/// Positions are only known while the peer talks, older ones are from before a pause.
+const STALE_AFTER: Duration = Duration::from_secs(1);
 
 struct VelocityTracker {
-    prev_pos_push: Instant,
+    times: [Instant; SAMPLE_COUNT],
     source_pos: [WorldPos; SAMPLE_COUNT],
     player_pos: [WorldPos; SAMPLE_COUNT],
 }
 
-impl Default for VelocityTracker {
-    fn default() -> Self {
+impl VelocityTracker {
+    fn new(source: WorldPos, player: WorldPos) -> Self {
         Self {
-            prev_pos_push: Instant::now(),
-            source_pos: Default::default(),
-            player_pos: Default::default(),
+            times: [Instant::now(); SAMPLE_COUNT],
+            source_pos: [source; SAMPLE_COUNT],
+            player_pos: [player; SAMPLE_COUNT],
         }
     }
-}
 
-impl VelocityTracker {
     fn push_new_pos(&mut self, source: WorldPos, player: WorldPos) {
-        if self.prev_pos_push.elapsed() > Duration::from_millis(16 * 4) {
+        let elapsed = self.times[0].elapsed();
+        if elapsed > STALE_AFTER {
+            *self = Self::new(source, player);
+        } else if elapsed > SAMPLE_INTERVAL {
+            self.times.rotate_right(1);
+            self.times[0] = Instant::now();
             self.source_pos.rotate_right(1);
             self.source_pos[0] = source;
             self.player_pos.rotate_right(1);
             self.player_pos[0] = player;
-            self.prev_pos_push = Instant::now();
         }
     }
+
+    /// In m/s, positive while the source and the player get closer.
     fn calc_speed_toward_player(&self) -> f32 {
+        let secs = self.times[0]
+            .duration_since(self.times[SAMPLE_COUNT - 1])
+            .as_secs_f32();
         let vel_source = self.source_pos[0] - self.source_pos[SAMPLE_COUNT - 1];
         let vel_player = self.player_pos[0] - self.player_pos[SAMPLE_COUNT - 1];
         let vel = vel_source - vel_player;
         let source_to_player = self.player_pos[0] - self.source_pos[0];
-        let ret = vel.dot(source_to_player) / source_to_player.hypot() / PIXELS_PER_METER;
-        if ret.is_nan() {
-            0.0
-        } else {
-            ret
-        }
+        let ret = vel.dot(source_to_player) / source_to_player.hypot() / PIXELS_PER_METER / secs;
+        if ret.is_finite() { ret } else { 0.0 }
     }
 }
 
-const DSP_PITCH_MULT: usize = 100;
-
-fn make_dsp() -> (Arc<AtomicUsize>, BigBlockAdapter) {
-    let pitch_control = Arc::new(AtomicUsize::new(DSP_PITCH_MULT));
+/// Pitch factor of voice from a source moving towards the listener at `speed` m/s.
+fn doppler_pitch(speed: f32) -> f32 {
+    let speed = speed.clamp(-SPEED_OF_SOUND, SPEED_OF_SOUND * 0.5);
+    (SPEED_OF_SOUND / (SPEED_OF_SOUND - speed)).clamp(1.0 / MAX_DOPPLER_SHIFT, MAX_DOPPLER_SHIFT)
+}
 
-    let node = resynth::<U1, U1, _>(1 << 13, {
-        let pitch_control = pitch_control.clone();
-        move |fft| {
-            let p = pitch_control.load(std::sync::atomic::Ordering::Relaxed);
-            for i in 0..fft.bins() {
-                let j = i * p / DSP_PITCH_MULT;
-                if (0..fft.bins()).contains(&j) {
-                    fft.set(0, i, fft.at(0, j));
-                }
-            }
-        }
-    });
-    (pitch_control, BigBlockAdapter::new(Box::new(node)))
-}*/
 struct PlayerInfo {
-    //tracker: VelocityTracker,
-    sink: Sink,
-    //dsp: BigBlockAdapter,
-    //pitch_control: Arc<AtomicUsize>,
+    /// Only tracked for proximity voice in spatial mode.
+    tracker: Option<VelocityTracker>,
+    output: Box<dyn VoiceOutput>,
+    buffer: Arc<Mutex<JitterBuffer>>,
+    /// Parameters of the playing `PeerVoice`.
+    controls: Arc<VoiceControls>,
+    /// Terrain between the peer and us, along with when it was checked.
+    occlusion: Option<(Instant, f32)>,
 }
 
 pub(crate) struct AudioManager {
     per_player: HashMap<OmniPeerId, PlayerInfo>,
-    stream_handle: Option<OutputStream>,
-    decoder: Decoder,
-    rx: Receiver<Vec<u8>>,
+    playback: Box<dyn Playback>,
+    /// Encoded frames along with their sequence numbers.
+    rx: Receiver<(u32, Vec<u8>)>,
+    /// Picked up by the capture thread before every packet.
+    target: Arc<Mutex<EncoderTarget>>,
+    recorder: Arc<Recorder>,
+    /// Input device the capture thread should use, if it uses one.
+    input_device: Option<Arc<Mutex<Option<String>>>>,
+    /// Same for the output device.
+    output_device: Option<Arc<Mutex<Option<String>>>>,
+    /// Our own voice, as it was sent.
+    own_track: Track,
+    own_decoder: Decoder,
+    priority_speakers: FxHashSet<OmniPeerId>,
+    /// When a priority speaker was last heard.
+    priority_heard: Option<Instant>,
 }
 
 impl AudioManager {
-    pub fn new(audio: AudioSettings) -> Self {
-        #[cfg(target_os = "linux")]
-        let host = cpal::available_hosts()
-            .into_iter()
-            .find(|id| *id == cpal::HostId::Jack)
-            .and_then(|id| cpal::host_from_id(id).ok())
-            .unwrap_or(cpal::default_host());
-        #[cfg(not(target_os = "linux"))]
-        let host = cpal::default_host();
-
-        let device = {
-            let input = audio.input_device.clone();
-            if audio.disabled {
-                None
-            } else if input.is_none() {
-                host.default_input_device()
-            } else if let Some(d) = host
-                .input_devices()
-                .map(|mut d| d.find(|d| d.name().ok() == input))
-                .ok()
-                .flatten()
-            {
-                Some(d)
-            } else {
-                host.default_input_device()
+    /// Uses the devices picked in `audio`, unless `NP_VOICE_HEADLESS` or `NP_VOICE_INPUT_FILE` say otherwise.
+    /// Devices in use are reported to `devices`.
+    pub fn new(
+        audio: AudioSettings,
+        input_meter: Arc<InputMeter>,
+        recorder: Arc<Recorder>,
+        devices: Arc<DeviceStatus>,
+    ) -> Self {
+        let headless = env::var_os("NP_VOICE_HEADLESS").is_some();
+        let mut input_device = None;
+        let mut output_device = None;
+        let capture: Box<dyn Capture> = if let Some(path) = env::var_os("NP_VOICE_INPUT_FILE") {
+            match FileCapture::open(Path::new(&path)) {
+                Ok(capture) => Box::new(capture),
+                Err(err) => {
+                    warn!("Could not open voice input file: {err}");
+                    Box::new(NullCapture)
+                }
             }
+        } else if headless {
+            Box::new(NullCapture)
+        } else {
+            let picked = Arc::new(Mutex::new(audio.input_device.clone()));
+            input_device = Some(picked.clone());
+            Box::new(CpalCapture::new(picked, devices.clone()))
+        };
+        let playback: Box<dyn Playback> = if headless {
+            Box::new(MixerPlayback::default())
+        } else {
+            let picked = Arc::new(Mutex::new(audio.output_device.clone()));
+            output_device = Some(picked.clone());
+            Box::new(RodioPlayback::new(picked, devices))
         };
-        let decoder = Decoder::new(SAMPLE_RATE as u32, CHANNELS).unwrap();
-        let (tx, rx) = mpsc::channel::<Vec<u8>>();
+        let mut manager = Self::with_backends(audio, input_meter, recorder, capture, playback);
+        manager.input_device = input_device;
+        manager.output_device = output_device;
+        manager
+    }
+
+    pub(crate) fn with_backends(
+        audio: AudioSettings,
+        input_meter: Arc<InputMeter>,
+        recorder: Arc<Recorder>,
+        capture: Box<dyn Capture>,
+        playback: Box<dyn Playback>,
+    ) -> Self {
+        let (tx, rx) = mpsc::channel::<(u32, Vec<u8>)>();
+        let processor = InputProcessor::new(&audio, input_meter);
+        let target = Arc::new(Mutex::new(quality::target(audio.voice_quality, [])));
+        let mut pipeline = CapturePipeline::new(processor, target.clone(), tx);
         thread::spawn(move || {
-            if let Some(device) = device {
-                if let Ok(cfg) = device.default_input_config() {
-                    let sample = cfg.sample_rate();
-                    let channels = cfg.channels();
-                    let config = cpal::SupportedStreamConfig::new(
-                        if channels <= 2 { cfg.channels() } else { 2 },
-                        sample,
-                        *cfg.buffer_size(),
-                        cpal::SampleFormat::F32,
-                    );
-                    if let Ok(mut resamp) =
-                        FftFixedIn::<f32>::new(sample.0 as usize, SAMPLE_RATE, FRAME_SIZE, 8, 1)
-                    {
-                        let mut encoder =
-                            Encoder::new(SAMPLE_RATE as u32, CHANNELS, Application::Audio).unwrap();
-                        let mut extra = Vec::new();
-                        match device.build_input_stream(
-                            &config.into(),
-                            move |data: &[f32], _| {
-                                if channels == 1 {
-                                    extra.extend(data);
-                                } else {
-                                    extra.extend(
-                                        data.chunks(2)
-                                            .map(|a| (a[0] + a[1]) * 0.5)
-                                            .collect::<Vec<f32>>(),
-                                    )
-                                }
-                                let mut v = Vec::new();
-                                while extra.len() >= FRAME_SIZE {
-                                    let mut compressed = vec![0u8; 1024];
-                                    if let Ok(len) = encoder.encode_float(
-                                        &resamp.process(&[&extra[..FRAME_SIZE]], None).unwrap()[0],
-                                        &mut compressed,
-                                    ) && len != 0
-                                    {
-                                        v.push(compressed[..len].to_vec())
-                                    }
-                                    extra.drain(..FRAME_SIZE);
-                                }
-                                for v in v {
-                                    let _ = tx.send(v);
-                                }
-                            },
-                            |err| error!("Stream error: {}", err),
-                            Some(Duration::from_millis(10)),
-                        ) {
-                            Ok(stream) => {
-                                if let Ok(_s) = stream.play() {
-                                    loop {
-                                        thread::sleep(Duration::from_millis(10))
-                                    }
-                                } else {
-                                    error!("failed to play stream")
-                                }
-                            }
-                            Err(s) => {
-                                error!(
-                                    "no stream {}, {}, {}, {}",
-                                    s,
-                                    cfg.channels(),
-                                    cfg.sample_rate().0,
-                                    cfg.sample_format()
-                                )
-                            }
-                        }
-                    } else {
-                        warn!("resamp not found")
-                    }
-                } else {
-                    warn!("input config not found")
-                }
-            } else {
-                warn!("input device not found")
-            }
+            capture.run(Box::new(move |format, data| pipeline.push(format, data)))
         });
-        let stream_handle: Option<OutputStream> = OutputStreamBuilder::open_default_stream().ok();
-        let sink: HashMap<OmniPeerId, PlayerInfo> = Default::default();
         Self {
-            decoder,
-            stream_handle,
-            per_player: sink,
+            playback,
+            per_player: HashMap::new(),
             rx,
+            target,
+            own_track: Track::new(recorder.clone(), "me".to_owned()),
+            own_decoder: Decoder::new(SAMPLE_RATE as u32, CHANNELS).unwrap(),
+            recorder,
+            input_device: None,
+            output_device: None,
+            priority_speakers: Default::default(),
+            priority_heard: None,
         }
     }
 
-    pub fn recv_audio(&mut self) -> Result<Vec<u8>, TryRecvError> {
+    /// Passes devices picked in `audio` to the threads that open them. Called every tick.
+    pub(crate) fn update_devices(&mut self, audio: &AudioSettings) {
+        let devices = [
+            (&self.input_device, &audio.input_device),
+            (&self.output_device, &audio.output_device),
+        ];
+        for (device, wanted) in devices {
+            if let Some(device) = device {
+                let mut picked = device.lock().unwrap();
+                if *picked != *wanted {
+                    picked.clone_from(wanted);
+                }
+            }
+        }
+    }
+
+    /// Records frames we sent this tick. Has to be called every tick, even without frames.
+    pub(crate) fn record_sent(&mut self, frames: &[Vec<u8>]) {
+        if !self.recorder.is_recording() {
+            self.own_track.write(&[]);
+            return;
+        }
+        let mut out = vec![0.0; MAX_PACKET_SIZE];
+        for frame in frames {
+            match self.own_decoder.decode_float(frame, &mut out, false) {
+                Ok(len) => self.own_track.write(&out[..len]),
+                Err(err) => warn!("Could not decode own voice frame: {err}"),
+            }
+        }
+        if frames.is_empty() {
+            self.own_track.write(&[]);
+        }
+    }
+
+    pub(crate) fn set_priority_speakers(&mut self, peers: &FxHashSet<OmniPeerId>) {
+        if self.priority_speakers != *peers {
+            self.priority_speakers.clone_from(peers);
+        }
+    }
+
+    /// Adapts outgoing voice to the quality of `links` it's sent over.
+    pub(crate) fn adapt(
+        &self,
+        quality: VoiceQuality,
+        links: impl IntoIterator<Item = LinkQuality>,
+    ) {
+        *self.target.lock().unwrap() = quality::target(quality, links);
+    }
+
+    pub fn recv_audio(&mut self) -> Result<(u32, Vec<u8>), TryRecvError> {
         self.rx.try_recv()
     }
 
-    #[allow(clippy::too_many_arguments)]
+    /// Voice of peers in our voice channel is played as if it was global.
+    /// While a priority speaker is heard, everyone else is quieter.
     pub fn play_audio(
         &mut self,
         audio: AudioSettings,
         player_pos: (i32, i32),
         src: OmniPeerId,
-        data: Vec<Vec<u8>>,
-        global: bool,
-        sound_pos: (i32, i32),
-        iv: f32,
+        voice: VoiceFrames,
+        in_channel: bool,
+        material_between: impl FnOnce((i32, i32), (i32, i32)) -> f32,
     ) {
-        if let std::collections::hash_map::Entry::Vacant(e) = self.per_player.entry(src)
-            && let Some(stream_handle) = &self.stream_handle
-        {
-            //let (pitch_control, dsp) = make_dsp();
+        if let std::collections::hash_map::Entry::Vacant(e) = self.per_player.entry(src) {
+            let buffer = Arc::new(Mutex::new(JitterBuffer::default()));
+            let controls = Arc::new(VoiceControls::default());
+            let track = Track::new(self.recorder.clone(), src.as_hex());
+            let output =
+                self.playback
+                    .play(PeerVoice::new(buffer.clone(), controls.clone(), track));
             e.insert(PlayerInfo {
-                sink: Sink::connect_new(stream_handle.mixer()),
-                //tracker: VelocityTracker::default(),
-                //pitch_control,
-                //dsp,
+                tracker: None,
+                output,
+                buffer,
+                controls,
+                occlusion: None,
             });
         }
+        let global = voice.global || in_channel;
         self.per_player.entry(src).and_modify(|player_info| {
-            /*player_info
-            .tracker
-            .push_new_pos(sound_pos.into(), player_pos.into());*/
             let vol = {
                 if global {
                     *audio.volume.get(&src).unwrap_or(&1.0)
                 } else {
                     let (mx, my) = (player_pos.0, player_pos.1);
-                    let dx = mx.abs_diff(sound_pos.0) as u64;
-                    let dy = my.abs_diff(sound_pos.1) as u64;
+                    let dx = mx.abs_diff(voice.pos.0) as u64;
+                    let dy = my.abs_diff(voice.pos.1) as u64;
                     let dist = dx * dx + dy * dy;
                     if dist > audio.range.pow(2) {
                         0.0
@@ -261,40 +346,219 @@ impl AudioManager {
                     }
                 }
             };
-            if vol > 0.0 && !audio.mute_out {
-                player_info
-                    .sink
-                    .set_volume(vol * iv * audio.global_output_volume);
-                let mut dec: Vec<f32> = Vec::new();
-                for data in data {
-                    let mut out = vec![0f32; FRAME_SIZE];
-                    if let Ok(len) = self.decoder.decode_float(&data, &mut out, false)
-                        && len != 0
-                    {
-                        dec.extend(&out[..len])
+            let muffling = if global || vol == 0.0 || audio.walls_strength <= 0.0 {
+                player_info.occlusion = None;
+                0.0
+            } else {
+                let material = match player_info.occlusion {
+                    Some((checked, material)) if checked.elapsed() < OCCLUSION_INTERVAL => material,
+                    _ => {
+                        let material = material_between(voice.pos, player_pos);
+                        player_info.occlusion = Some((Instant::now(), material));
+                        material
                     }
+                };
+                muffling(material, audio.walls_strength)
+            };
+            let cutoff = OPEN_CUTOFF * (MUFFLED_CUTOFF / OPEN_CUTOFF).powf(muffling);
+            let pan = if global || audio.voice_mode == VoiceMode::Mono {
+                0.0
+            } else {
+                (voice.pos.0 - player_pos.0) as f32 / FULL_PAN_DISTANCE
+            };
+            let pitch = if global || audio.voice_mode != VoiceMode::Spatial {
+                player_info.tracker = None;
+                1.0
+            } else {
+                let (source, player) = (voice.pos.into(), player_pos.into());
+                let tracker = player_info
+                    .tracker
+                    .get_or_insert_with(|| VelocityTracker::new(source, player));
+                tracker.push_new_pos(source, player);
+                doppler_pitch(tracker.calc_speed_toward_player())
+            };
+            player_info.controls.set(cutoff, pan, pitch);
+            let vol = if audio.mute_out {
+                0.0
+            } else {
+                vol * (1.0 - MAX_MUFFLED_ATTENUATION * muffling)
+            };
+            let vol = if self.priority_speakers.contains(&src) {
+                if vol > 0.0 && !voice.frames.is_empty() {
+                    self.priority_heard = Some(Instant::now());
                 }
-                if !dec.is_empty() {
-                    /*let speed = player_info.tracker.calc_speed_toward_player();
-                    let pitch_change = (1.0 / (1.0 + speed / SPEED_OF_SOUND)).clamp(0.01, 3.00);
-                    player_info.pitch_control.store(
-                        (pitch_change * DSP_PITCH_MULT as f32) as usize,
-                        std::sync::atomic::Ordering::Relaxed,
-                    );
-
-                    let mut dsp_out = vec![0f32; dec.len()];
-
-                    player_info.dsp.process_big(
-                        dec.len(),
-                        &[dec.as_slice()],
-                        &mut [dsp_out.as_mut_slice()],
-                    );
-                    let source = SamplesBuffer::new(1, SAMPLE_RATE as u32, dsp_out);*/
-                    let source = SamplesBuffer::new(1, SAMPLE_RATE as u32, dec);
-                    player_info.sink.append(source);
-                    player_info.sink.play();
-                }
+                vol
+            } else if self
+                .priority_heard
+                .is_some_and(|heard| heard.elapsed() < PRIORITY_HOLD)
+            {
+                vol * PRIORITY_DUCKING
+            } else {
+                vol
+            };
+            player_info
+                .output
+                .set_volume(vol * voice.volume * audio.global_output_volume);
+            // Inaudible frames are still decoded, so that the decoder doesn't have to conceal them later.
+            let mut buffer = player_info.buffer.lock().unwrap();
+            for (i, frame) in voice.frames.into_iter().enumerate() {
+                buffer.push(voice.seq.wrapping_add(i as u32), frame);
             }
         });
     }
 }
+
+/// How muffled voice behind `material` worth of terrain is, from 0 to 1.
+fn muffling(material: f32, walls_strength: f32) -> f32 {
+    1.0 - (-material * walls_strength / WALL_THICKNESS).exp()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Speech fixture as it's sent, captured and encoded by a manager playing into `playback`.
+    fn encoded_speech(playback: &MixerPlayback) -> (AudioManager, Vec<(u32, Vec<u8>)>) {
+        let path = format!(
+            "{}/src/net/audio/fixtures/speech.wav",
+            env!("CARGO_MANIFEST_DIR")
+        );
+        let capture = FileCapture::open(Path::new(&path)).unwrap();
+        let manager = AudioManager::with_backends(
+            AudioSettings::default(),
+            Default::default(),
+            Default::default(),
+            Box::new(capture),
+            Box::new(playback.clone()),
+        );
+        let mut frames = Vec::new();
+        while let Ok(frame) = manager.rx.recv_timeout(Duration::from_secs(5)) {
+            frames.push(frame);
+        }
+        // Two seconds of audio, only the tail stays in the resampler.
+        assert!(frames.len() > 90, "{}", frames.len());
+        (manager, frames)
+    }
+
+    fn voice_at(tick: &[(u32, Vec<u8>)], pos: (i32, i32)) -> VoiceFrames {
+        VoiceFrames {
+            seq: tick[0].0,
+            frames: tick.iter().map(|(_, frame)| frame.clone()).collect(),
+            global: false,
+            pos,
+            volume: 1.0,
+        }
+    }
+
+    fn mixed_energy(playback: &MixerPlayback, len: usize) -> f32 {
+        playback
+            .mix(len)
+            .iter()
+            .map(|[left, right]| left * left + right * right)
+            .sum()
+    }
+
+    /// Sends the speech fixture through capture, encoding, decoding and playback of a peer at `pos`.
+    fn played_energy(pos: (i32, i32), in_channel: bool) -> f32 {
+        let playback = MixerPlayback::default();
+        let (mut manager, frames) = encoded_speech(&playback);
+        let mut energy = 0.0;
+        for tick in frames.chunks(3) {
+            manager.play_audio(
+                AudioSettings::default(),
+                (0, 0),
+                OmniPeerId(1),
+                voice_at(tick, pos),
+                in_channel,
+                |_, _| 0.0,
+            );
+            energy += mixed_energy(&playback, FRAME_SIZE * tick.len());
+        }
+        energy
+    }
+
+    #[test]
+    fn doppler_raises_pitch_of_approaching_voice() {
+        assert_eq!(doppler_pitch(0.0), 1.0);
+        assert!(doppler_pitch(20.0) > 1.0);
+        assert!(doppler_pitch(-20.0) < 1.0);
+        // Approaching at a tenth of the speed of sound.
+        assert!((doppler_pitch(SPEED_OF_SOUND / 10.0) - 10.0 / 9.0).abs() < 1e-4);
+        assert_eq!(doppler_pitch(1000.0), MAX_DOPPLER_SHIFT);
+        assert_eq!(doppler_pitch(-1000.0), 1.0 / MAX_DOPPLER_SHIFT);
+    }
+
+    /// Tracker that saw the source move from `from` to `to` over a second, with the player at the origin.
+    fn tracker_moving(from: i32, to: i32) -> VelocityTracker {
+        let now = Instant::now();
+        let mut tracker = VelocityTracker::new(WorldPos::from((from, 0)), WorldPos::from((0, 0)));
+        tracker.times = [now, now, now, now - Duration::from_secs(1)];
+        tracker.source_pos[0] = WorldPos::from((to, 0));
+        tracker
+    }
+
+    #[test]
+    fn speed_toward_player() {
+        let approaching = tracker_moving(1000, 1000 - 10 * PIXELS_PER_METER as i32);
+        let speed = approaching.calc_speed_toward_player();
+        assert!((speed - 10.0).abs() < 0.2, "{speed}");
+        let leaving = tracker_moving(1000, 1000 + 10 * PIXELS_PER_METER as i32);
+        assert!((leaving.calc_speed_toward_player() + 10.0).abs() < 0.2);
+        // No time passed, or the source is right at the player.
+        let now = VelocityTracker::new(WorldPos::from((10, 0)), WorldPos::from((0, 0)));
+        assert_eq!(now.calc_speed_toward_player(), 0.0);
+        assert_eq!(tracker_moving(50, 0).calc_speed_toward_player(), 0.0);
+    }
+
+    #[test]
+    fn voice_round_trip() {
+        assert!(played_energy((10, 0), false) > 100.0);
+        // Out of range.
+        assert_eq!(played_energy((5000, 0), false), 0.0);
+    }
+
+    #[test]
+    fn channel_voice_is_heard_anywhere() {
+        let far = played_energy((5000, 0), true);
+        assert!(far > 100.0, "{far}");
+        let near = played_energy((10, 0), true);
+        // Played as global, so it's not any quieter far away.
+        assert!((far - near).abs() < near * 0.1, "far {far}, near {near}");
+    }
+
+    #[test]
+    fn channel_names_are_limited() {
+        assert_eq!(channel_name(" party "), Some("party".to_owned()));
+        assert_eq!(channel_name("   "), None);
+        let long = "ä".repeat(MAX_CHANNEL_NAME * 2);
+        assert_eq!(
+            channel_name(&long).unwrap().chars().count(),
+            MAX_CHANNEL_NAME
+        );
+    }
+
+    #[test]
+    fn priority_speaker_ducks_others() {
+        let speaker = OmniPeerId(2);
+        let others_energy = |priority: bool| {
+            let playback = MixerPlayback::default();
+            let (mut manager, frames) = encoded_speech(&playback);
+            if priority {
+                manager.set_priority_speakers(&[speaker].into_iter().collect());
+            }
+            let mut audio = AudioSettings::default();
+            // Barely audible, so that it's the voice of the other peer that's measured.
+            audio.volume.insert(speaker, 1e-6);
+            let mut energy = 0.0;
+            for tick in frames.chunks(3) {
+                for peer in [speaker, OmniPeerId(1)] {
+                    let voice = voice_at(tick, (10, 0));
+                    manager.play_audio(audio.clone(), (0, 0), peer, voice, false, |_, _| 0.0);
+                }
+                energy += mixed_energy(&playback, FRAME_SIZE * tick.len());
+            }
+            energy
+        };
+        assert!(others_energy(true) < others_energy(false) * 0.2);
+    }
+}
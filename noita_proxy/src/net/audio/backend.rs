@@ -0,0 +1,534 @@
+use std::{
+    path::Path,
+    sync::{
+        Arc, Mutex, Weak,
+        atomic::{AtomicBool, AtomicU32, Ordering},
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use rodio::{ChannelCount, OutputStreamBuilder, SampleRate, Source};
+use tracing::{error, info, warn};
+
+use super::{SAMPLE_RATE, playback::PeerVoice};
+
+/// While not on the picked device, this often it's checked whether it's back.
+const RETRY_INTERVAL: Duration = Duration::from_secs(2);
+/// Samples mixed at once for the output device, in stereo samples.
+const MIX_CHUNK: usize = 480;
+
+/// Format of captured samples.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Format {
+    pub(crate) sample_rate: u32,
+    pub(crate) channels: u16,
+}
+
+/// Receives interleaved captured samples, returns false once it doesn't want any more.
+pub(crate) type OnCaptured = Box<dyn FnMut(Format, &[f32]) -> bool + Send>;
+
+/// Where our voice comes from.
+pub(crate) trait Capture: Send + 'static {
+    /// Runs on its own thread, passes captured samples to `on_captured` until either of them is done.
+    fn run(self: Box<Self>, on_captured: OnCaptured);
+}
+
+/// Where voice of others goes.
+pub(crate) trait Playback {
+    /// Starts playing voice of a peer, it's stopped when the returned output is dropped.
+    fn play(&self, voice: PeerVoice) -> Box<dyn VoiceOutput>;
+}
+
+pub(crate) trait VoiceOutput {
+    fn set_volume(&self, volume: f32);
+}
+
+/// What a device in use is, shown in the audio settings.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) enum DeviceState {
+    /// Not using a device.
+    #[default]
+    Headless,
+    Active(String),
+    /// Picked device isn't available, the default one is used until it's back.
+    Fallback {
+        active: String,
+        picked: String,
+    },
+    /// No device is available.
+    Missing,
+}
+
+/// State of devices used for voice.
+#[derive(Default)]
+pub(crate) struct DeviceStatus {
+    pub(crate) input: Mutex<DeviceState>,
+    pub(crate) output: Mutex<DeviceState>,
+}
+
+pub(crate) fn host() -> cpal::Host {
+    #[cfg(target_os = "linux")]
+    let host = cpal::available_hosts()
+        .into_iter()
+        .find(|id| *id == cpal::HostId::Jack)
+        .and_then(|id| cpal::host_from_id(id).ok())
+        .unwrap_or(cpal::default_host());
+    #[cfg(not(target_os = "linux"))]
+    let host = cpal::default_host();
+    host
+}
+
+/// State a device picked as `picked` ends up in, given names of the `available` devices and of the `default` one.
+fn device_state(picked: Option<&str>, available: &[String], default: Option<&str>) -> DeviceState {
+    if let Some(picked) = picked
+        && available.iter().any(|name| name == picked)
+    {
+        return DeviceState::Active(picked.to_owned());
+    }
+    match (default, picked) {
+        (Some(default), Some(picked)) if picked != default => DeviceState::Fallback {
+            active: default.to_owned(),
+            picked: picked.to_owned(),
+        },
+        (Some(default), _) => DeviceState::Active(default.to_owned()),
+        (None, _) => DeviceState::Missing,
+    }
+}
+
+/// Whether a device in `state` should be reopened, as a better one is available now.
+fn should_reopen(state: &DeviceState, available: &[String], default: Option<&str>) -> bool {
+    match state {
+        DeviceState::Missing => default.is_some() || !available.is_empty(),
+        DeviceState::Fallback { picked, .. } => available.contains(picked),
+        DeviceState::Headless | DeviceState::Active(_) => false,
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Direction {
+    Input,
+    Output,
+}
+
+impl Direction {
+    fn devices(self, host: &cpal::Host) -> Vec<cpal::Device> {
+        match self {
+            Self::Input => host.input_devices().into_iter().flatten().collect(),
+            Self::Output => host.output_devices().into_iter().flatten().collect(),
+        }
+    }
+
+    fn default_device(self, host: &cpal::Host) -> Option<cpal::Device> {
+        match self {
+            Self::Input => host.default_input_device(),
+            Self::Output => host.default_output_device(),
+        }
+    }
+
+    /// Names of available devices and of the default one.
+    fn names(self, host: &cpal::Host) -> (Vec<String>, Option<String>) {
+        let names = self
+            .devices(host)
+            .iter()
+            .filter_map(|d| d.name().ok())
+            .collect();
+        let default = self.default_device(host).and_then(|d| d.name().ok());
+        (names, default)
+    }
+
+    /// Device named `picked`, or the default one if there's none such. Also returns the state it'd be in.
+    fn pick(self, host: &cpal::Host, picked: Option<&str>) -> (Option<cpal::Device>, DeviceState) {
+        let devices = self.devices(host);
+        let default = self.default_device(host);
+        let names: Vec<String> = devices.iter().filter_map(|d| d.name().ok()).collect();
+        let default_name = default.as_ref().and_then(|d| d.name().ok());
+        let state = device_state(picked, &names, default_name.as_deref());
+        let device = match &state {
+            DeviceState::Active(name) if Some(name) != default_name.as_ref() => devices
+                .into_iter()
+                .find(|d| d.name().ok().as_ref() == Some(name)),
+            DeviceState::Active(_) | DeviceState::Fallback { .. } => default,
+            DeviceState::Headless | DeviceState::Missing => None,
+        };
+        (device, state)
+    }
+}
+
+/// Keeps a stream open on the picked device until `stop` says otherwise,
+/// reopening it when another device is picked, it fails, or a better one shows up.
+/// `open` returns the stream, which is kept until then, and sets the flag it's given once the stream fails.
+fn supervise<S>(
+    direction: Direction,
+    picked: &Mutex<Option<String>>,
+    status: &Mutex<DeviceState>,
+    mut open: impl FnMut(cpal::Device, Arc<AtomicBool>) -> Option<S>,
+    stop: impl Fn() -> bool,
+) {
+    let kind = match direction {
+        Direction::Input => "Input",
+        Direction::Output => "Output",
+    };
+    let host = host();
+    while !stop() {
+        let now_picked = picked.lock().unwrap().clone();
+        let (device, state) = direction.pick(&host, now_picked.as_deref());
+        let failed = Arc::new(AtomicBool::new(false));
+        // Only one stream at a time, some backends can't open a device that's still in use.
+        let stream = device.and_then(|device| open(device, failed.clone()));
+        let state = if stream.is_some() {
+            state
+        } else {
+            DeviceState::Missing
+        };
+        {
+            let mut current = status.lock().unwrap();
+            if *current != state {
+                info!("{kind} device: {state:?}");
+                *current = state.clone();
+            }
+        }
+        let opened = Instant::now();
+        let mut checked = Instant::now();
+        while !stop() {
+            thread::sleep(Duration::from_millis(10));
+            if failed.load(Ordering::Relaxed) {
+                warn!("{kind} device failed, reopening it");
+                // Give an unplugged device a moment to disappear from the list.
+                thread::sleep(Duration::from_millis(500).saturating_sub(opened.elapsed()));
+                break;
+            }
+            if *picked.lock().unwrap() != now_picked {
+                break;
+            }
+            if checked.elapsed() > RETRY_INTERVAL {
+                checked = Instant::now();
+                let (available, default) = direction.names(&host);
+                if should_reopen(&state, &available, default.as_deref()) {
+                    info!("Better {} device is available", kind.to_lowercase());
+                    break;
+                }
+            }
+        }
+        drop(stream);
+    }
+}
+
+/// Captures from a microphone, switching devices as they're picked, unplugged and plugged back in.
+pub(crate) struct CpalCapture {
+    /// Name of the picked device, `None` for the default one. Shared with whoever picks it.
+    picked: Arc<Mutex<Option<String>>>,
+    status: Arc<DeviceStatus>,
+}
+
+impl CpalCapture {
+    pub(crate) fn new(picked: Arc<Mutex<Option<String>>>, status: Arc<DeviceStatus>) -> Self {
+        Self { picked, status }
+    }
+}
+
+impl Capture for CpalCapture {
+    fn run(self: Box<Self>, on_captured: OnCaptured) {
+        let on_captured = Arc::new(Mutex::new(on_captured));
+        let done = Arc::new(AtomicBool::new(false));
+        let open = |device: cpal::Device, failed: Arc<AtomicBool>| {
+            let cfg = device.default_input_config().ok()?;
+            let config = cpal::SupportedStreamConfig::new(
+                cfg.channels().min(2),
+                cfg.sample_rate(),
+                *cfg.buffer_size(),
+                cpal::SampleFormat::F32,
+            );
+            let format = Format {
+                sample_rate: config.sample_rate().0,
+                channels: config.channels(),
+            };
+            let on_captured = on_captured.clone();
+            let stream_done = done.clone();
+            let stream = device.build_input_stream(
+                &config.clone().into(),
+                move |data: &[f32], _| {
+                    if !on_captured.lock().unwrap()(format, data) {
+                        stream_done.store(true, Ordering::Relaxed);
+                    }
+                },
+                move |err| {
+                    error!("Stream error: {}", err);
+                    failed.store(true, Ordering::Relaxed);
+                },
+                Some(Duration::from_millis(10)),
+            );
+            match stream {
+                Ok(stream) if stream.play().is_ok() => Some(stream),
+                Ok(_) => {
+                    error!("failed to play stream");
+                    None
+                }
+                Err(s) => {
+                    error!(
+                        "no stream {}, {}, {}",
+                        s,
+                        config.channels(),
+                        format.sample_rate
+                    );
+                    None
+                }
+            }
+        };
+        // Nobody picks devices anymore once the audio manager is gone.
+        supervise(
+            Direction::Input,
+            &self.picked,
+            &self.status.input,
+            open,
+            || done.load(Ordering::Relaxed) || Arc::strong_count(&self.picked) <= 1,
+        );
+    }
+}
+
+/// Plays on an output device, switching devices as they're picked, unplugged and plugged back in.
+/// Voices are mixed by a `MixerPlayback`, so that they keep playing when the device changes.
+pub(crate) struct RodioPlayback {
+    mixer: MixerPlayback,
+}
+
+impl RodioPlayback {
+    /// Devices are opened on a thread of their own, which runs until `picked` is only held by it.
+    pub(crate) fn new(picked: Arc<Mutex<Option<String>>>, status: Arc<DeviceStatus>) -> Self {
+        let mixer = MixerPlayback::default();
+        let source_mixer = mixer.clone();
+        thread::spawn(move || {
+            let open = |device: cpal::Device, failed: Arc<AtomicBool>| {
+                let stream = OutputStreamBuilder::from_device(device)
+                    .and_then(|builder| {
+                        builder
+                            .with_error_callback(move |err| {
+                                error!("Stream error: {}", err);
+                                failed.store(true, Ordering::Relaxed);
+                            })
+                            .open_stream()
+                    })
+                    .inspect_err(|err| warn!("Could not open output device: {err}"))
+                    .ok()?;
+                stream.mixer().add(MixerSource {
+                    mixer: source_mixer.clone(),
+                    buffer: Vec::new(),
+                    pos: 0,
+                });
+                Some(stream)
+            };
+            supervise(Direction::Output, &picked, &status.output, open, || {
+                Arc::strong_count(&picked) <= 1
+            });
+        });
+        Self { mixer }
+    }
+}
+
+impl Playback for RodioPlayback {
+    fn play(&self, voice: PeerVoice) -> Box<dyn VoiceOutput> {
+        self.mixer.play(voice)
+    }
+}
+
+/// Feeds voices mixed by a `MixerPlayback` to an output device.
+struct MixerSource {
+    mixer: MixerPlayback,
+    buffer: Vec<[f32; 2]>,
+    /// Position in `buffer`, in samples of both channels.
+    pos: usize,
+}
+
+impl Iterator for MixerSource {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        if self.pos >= self.buffer.len() * 2 {
+            self.buffer = self.mixer.mix(MIX_CHUNK);
+            self.pos = 0;
+        }
+        let sample = self.buffer[self.pos / 2][self.pos % 2];
+        self.pos += 1;
+        Some(sample)
+    }
+}
+
+impl Source for MixerSource {
+    fn current_span_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> ChannelCount {
+        2
+    }
+
+    fn sample_rate(&self) -> SampleRate {
+        SAMPLE_RATE as SampleRate
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        None
+    }
+}
+
+/// Captures nothing, for when there's no microphone.
+pub(crate) struct NullCapture;
+
+impl Capture for NullCapture {
+    fn run(self: Box<Self>, _on_captured: OnCaptured) {}
+}
+
+/// "Captures" samples of a WAV file, as fast as they're processed.
+pub(crate) struct FileCapture {
+    samples: Vec<f32>,
+    format: Format,
+}
+
+impl FileCapture {
+    pub(crate) fn open(path: &Path) -> hound::Result<Self> {
+        let mut reader = hound::WavReader::open(path)?;
+        let spec = reader.spec();
+        let samples = match spec.sample_format {
+            hound::SampleFormat::Float => reader.samples::<f32>().collect::<Result<_, _>>()?,
+            hound::SampleFormat::Int => {
+                let max = (1i64 << (spec.bits_per_sample - 1)) as f32;
+                reader
+                    .samples::<i32>()
+                    .map(|s| s.map(|s| s as f32 / max))
+                    .collect::<Result<_, _>>()?
+            }
+        };
+        Ok(Self {
+            samples,
+            format: Format {
+                sample_rate: spec.sample_rate,
+                channels: spec.channels,
+            },
+        })
+    }
+}
+
+impl Capture for FileCapture {
+    fn run(self: Box<Self>, mut on_captured: OnCaptured) {
+        // Delivered in 10ms chunks, like a device would.
+        let chunk = (self.format.sample_rate / 100) as usize * self.format.channels as usize;
+        for samples in self.samples.chunks(chunk.max(1)) {
+            if !on_captured(self.format, samples) {
+                break;
+            }
+        }
+    }
+}
+
+/// Plays into nothing until mixed on demand, for headless hosts and tests.
+#[derive(Default, Clone)]
+pub(crate) struct MixerPlayback {
+    voices: Arc<Mutex<Vec<(PeerVoice, Weak<AtomicU32>)>>>,
+}
+
+impl MixerPlayback {
+    /// Mixes the next `len` stereo samples of all voices.
+    pub(crate) fn mix(&self, len: usize) -> Vec<[f32; 2]> {
+        let mut out = vec![[0.0; 2]; len];
+        let mut voices = self.voices.lock().unwrap();
+        voices.retain(|(_, volume)| volume.strong_count() > 0);
+        for (voice, volume) in voices.iter_mut() {
+            let Some(volume) = volume.upgrade() else {
+                continue;
+            };
+            let volume = f32::from_bits(volume.load(Ordering::Relaxed));
+            for sample in &mut out {
+                for channel in sample {
+                    *channel += voice.next().unwrap_or(0.0) * volume;
+                }
+            }
+        }
+        out
+    }
+}
+
+struct MixerOutput {
+    volume: Arc<AtomicU32>,
+}
+
+impl VoiceOutput for MixerOutput {
+    fn set_volume(&self, volume: f32) {
+        self.volume.store(volume.to_bits(), Ordering::Relaxed);
+    }
+}
+
+impl Playback for MixerPlayback {
+    fn play(&self, voice: PeerVoice) -> Box<dyn VoiceOutput> {
+        let volume = Arc::new(AtomicU32::new(1.0f32.to_bits()));
+        self.voices
+            .lock()
+            .unwrap()
+            .push((voice, Arc::downgrade(&volume)));
+        Box::new(MixerOutput { volume })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn names(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    #[test]
+    fn picked_device_is_used_while_available() {
+        let available = names(&["headset", "speakers"]);
+        assert_eq!(
+            device_state(Some("headset"), &available, Some("speakers")),
+            DeviceState::Active("headset".into())
+        );
+        assert_eq!(
+            device_state(None, &available, Some("speakers")),
+            DeviceState::Active("speakers".into())
+        );
+        assert_eq!(device_state(None, &[], None), DeviceState::Missing);
+    }
+
+    #[test]
+    fn falls_back_to_default_device() {
+        let available = names(&["speakers"]);
+        let state = device_state(Some("headset"), &available, Some("speakers"));
+        assert_eq!(
+            state,
+            DeviceState::Fallback {
+                active: "speakers".into(),
+                picked: "headset".into(),
+            }
+        );
+        assert_eq!(
+            device_state(Some("headset"), &[], None),
+            DeviceState::Missing
+        );
+
+        assert!(!should_reopen(&state, &available, Some("speakers")));
+        let available = names(&["speakers", "headset"]);
+        assert!(should_reopen(&state, &available, Some("speakers")));
+        assert_eq!(
+            device_state(Some("headset"), &available, Some("speakers")),
+            DeviceState::Active("headset".into())
+        );
+    }
+
+    #[test]
+    fn missing_device_is_reopened_once_any_is_back() {
+        assert!(!should_reopen(&DeviceState::Missing, &[], None));
+        assert!(should_reopen(
+            &DeviceState::Missing,
+            &names(&["speakers"]),
+            Some("speakers")
+        ));
+        let active = DeviceState::Active("speakers".into());
+        assert!(!should_reopen(
+            &active,
+            &names(&["speakers", "headset"]),
+            Some("headset")
+        ));
+    }
+}
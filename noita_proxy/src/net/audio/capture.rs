@@ -0,0 +1,126 @@
+use std::sync::{Arc, Mutex, mpsc::Sender};
+
+use opus::{Application, Bitrate, Encoder};
+use rubato::{FftFixedIn, Resampler};
+use tracing::warn;
+
+use super::{
+    CHANNELS, FRAME_SIZE, SAMPLE_RATE, backend::Format, input::InputProcessor,
+    quality::EncoderTarget,
+};
+
+/// Turns captured samples into encoded packets, along with their sequence numbers.
+pub(crate) struct CapturePipeline {
+    /// Resampler for the format samples come in, recreated when the device changes.
+    resampler: Option<(Format, FftFixedIn<f32>)>,
+    processor: InputProcessor,
+    encoder: Encoder,
+    target: Arc<Mutex<EncoderTarget>>,
+    applied: Option<EncoderTarget>,
+    /// Mono samples at the capture sample rate.
+    extra: Vec<f32>,
+    resampled: Vec<f32>,
+    packet: Vec<f32>,
+    seq: u32,
+    tx: Sender<(u32, Vec<u8>)>,
+}
+
+impl CapturePipeline {
+    pub(crate) fn new(
+        processor: InputProcessor,
+        target: Arc<Mutex<EncoderTarget>>,
+        tx: Sender<(u32, Vec<u8>)>,
+    ) -> Self {
+        Self {
+            resampler: None,
+            processor,
+            encoder: Encoder::new(SAMPLE_RATE as u32, CHANNELS, Application::Voip).unwrap(),
+            target,
+            applied: None,
+            extra: Vec::new(),
+            resampled: Vec::new(),
+            packet: Vec::new(),
+            seq: 0,
+            tx,
+        }
+    }
+
+    /// Takes interleaved samples, returns false once nobody receives the packets anymore.
+    pub(crate) fn push(&mut self, format: Format, data: &[f32]) -> bool {
+        if self
+            .resampler
+            .as_ref()
+            .is_none_or(|(current, _)| *current != format)
+        {
+            self.extra.clear();
+            let Ok(resampler) =
+                FftFixedIn::<f32>::new(format.sample_rate as usize, SAMPLE_RATE, FRAME_SIZE, 8, 1)
+            else {
+                warn!("resamp not found");
+                return true;
+            };
+            self.resampler = Some((format, resampler));
+        }
+        let Some((_, resampler)) = &mut self.resampler else {
+            return true;
+        };
+        if format.channels == 1 {
+            self.extra.extend(data);
+        } else {
+            let channels = format.channels as usize;
+            self.extra.extend(
+                data.chunks(channels)
+                    .map(|a| a.iter().sum::<f32>() / channels as f32),
+            )
+        }
+        while self.extra.len() >= FRAME_SIZE {
+            self.resampled.extend(
+                resampler
+                    .process(&[&self.extra[..FRAME_SIZE]], None)
+                    .unwrap()
+                    .swap_remove(0),
+            );
+            self.extra.drain(..FRAME_SIZE);
+        }
+        let target = *self.target.lock().unwrap();
+        if self.applied != Some(target) {
+            self.applied = Some(target);
+            if let Err(err) = self
+                .encoder
+                .set_bitrate(Bitrate::Bits(target.bitrate))
+                .and_then(|()| self.encoder.set_inband_fec(target.fec))
+                .and_then(|()| self.encoder.set_packet_loss_perc(target.loss_percent))
+            {
+                warn!("Could not configure voice encoder: {err}")
+            }
+        }
+        while self.resampled.len() >= FRAME_SIZE {
+            let mut frame: Vec<f32> = self.resampled.drain(..FRAME_SIZE).collect();
+            let open = self.processor.process(&mut frame);
+            if open {
+                self.packet.extend(frame);
+            }
+            // Sent once it's long enough, or right away when voice stops.
+            if self.packet.is_empty()
+                || open && self.packet.len() < target.packet_frames * FRAME_SIZE
+            {
+                continue;
+            }
+            let mut compressed = vec![0u8; 1024];
+            if let Ok(len) = self.encoder.encode_float(&self.packet, &mut compressed)
+                && len != 0
+            {
+                if self
+                    .tx
+                    .send((self.seq, compressed[..len].to_vec()))
+                    .is_err()
+                {
+                    return false;
+                }
+                self.seq = self.seq.wrapping_add(1);
+            }
+            self.packet.clear();
+        }
+        true
+    }
+}
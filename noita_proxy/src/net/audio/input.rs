@@ -0,0 +1,228 @@
+use std::{
+    sync::{
+        Arc,
+        atomic::{AtomicBool, AtomicU32, Ordering},
+    },
+    time::Duration,
+};
+
+use nnnoiseless::DenoiseState;
+
+use super::FRAME_SIZE;
+use crate::AudioSettings;
+
+/// Input quieter than this is silent, no matter how quiet the background is. In dBFS.
+const SILENCE_DB: f32 = -60.0;
+/// How fast the noise floor follows louder input, in dB per frame. It follows quieter input immediately.
+const FLOOR_RISE_DB: f32 = 0.05;
+/// Length of a frame, the gate decides about whole frames.
+const FRAME_DURATION: Duration = Duration::from_millis(20);
+
+/// Level of the microphone input, shown in the audio settings.
+pub(crate) struct InputMeter {
+    /// In dBFS, as bits of a f32.
+    level: AtomicU32,
+    open: AtomicBool,
+}
+
+impl Default for InputMeter {
+    fn default() -> Self {
+        Self {
+            level: AtomicU32::new(SILENCE_DB.to_bits()),
+            open: AtomicBool::new(false),
+        }
+    }
+}
+
+impl InputMeter {
+    pub(crate) fn level_db(&self) -> f32 {
+        f32::from_bits(self.level.load(Ordering::Relaxed))
+    }
+
+    /// Whether the last frame was sent.
+    pub(crate) fn is_open(&self) -> bool {
+        self.open.load(Ordering::Relaxed)
+    }
+
+    fn set(&self, level_db: f32, open: bool) {
+        self.level.store(level_db.to_bits(), Ordering::Relaxed);
+        self.open.store(open, Ordering::Relaxed);
+    }
+}
+
+/// Voice activity detection: lets frames through while they're loud enough compared to the background noise,
+/// and for a while after, so that ends of words aren't cut off.
+pub(crate) struct VoiceGate {
+    threshold_db: f32,
+    hangover_frames: u32,
+    floor_db: Option<f32>,
+    remaining: u32,
+}
+
+impl VoiceGate {
+    pub(crate) fn new(threshold_db: f32, hangover: Duration) -> Self {
+        Self {
+            threshold_db,
+            hangover_frames: (hangover.as_millis() / FRAME_DURATION.as_millis()) as u32,
+            floor_db: None,
+            remaining: 0,
+        }
+    }
+
+    /// Returns true if the frame should be sent.
+    pub(crate) fn process(&mut self, level_db: f32) -> bool {
+        let floor = match self.floor_db {
+            Some(floor) if floor < level_db => floor + FLOOR_RISE_DB,
+            _ => level_db,
+        };
+        self.floor_db = Some(floor);
+        if level_db > floor.max(SILENCE_DB) + self.threshold_db {
+            self.remaining = self.hangover_frames;
+            true
+        } else if self.remaining > 0 {
+            self.remaining -= 1;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+// Denoiser frames have to fit in our frames exactly.
+const _: () = assert!(FRAME_SIZE % DenoiseState::FRAME_SIZE == 0);
+
+/// RNNoise based denoiser, works on frames of its own size.
+pub(crate) struct NoiseSuppressor {
+    state: Box<DenoiseState<'static>>,
+    input: Vec<f32>,
+    output: Vec<f32>,
+}
+
+impl Default for NoiseSuppressor {
+    fn default() -> Self {
+        Self {
+            state: DenoiseState::new(),
+            input: vec![0.0; DenoiseState::FRAME_SIZE],
+            output: vec![0.0; DenoiseState::FRAME_SIZE],
+        }
+    }
+}
+
+impl NoiseSuppressor {
+    pub(crate) fn process(&mut self, frame: &mut [f32]) {
+        // RNNoise expects samples in the range of i16.
+        for chunk in frame.chunks_exact_mut(DenoiseState::FRAME_SIZE) {
+            for (input, sample) in self.input.iter_mut().zip(chunk.iter()) {
+                *input = sample * i16::MAX as f32;
+            }
+            self.state.process_frame(&mut self.output, &self.input);
+            for (sample, output) in chunk.iter_mut().zip(&self.output) {
+                *sample = output / i16::MAX as f32;
+            }
+        }
+    }
+}
+
+/// Everything done to captured frames before they're encoded.
+pub(crate) struct InputProcessor {
+    suppressor: Option<NoiseSuppressor>,
+    gate: Option<VoiceGate>,
+    meter: Arc<InputMeter>,
+}
+
+impl InputProcessor {
+    pub(crate) fn new(audio: &AudioSettings, meter: Arc<InputMeter>) -> Self {
+        Self {
+            suppressor: audio.noise_suppression.then(NoiseSuppressor::default),
+            gate: audio.voice_activity.then(|| {
+                VoiceGate::new(
+                    audio.voice_activity_threshold,
+                    Duration::from_millis(audio.voice_activity_hangover_ms.into()),
+                )
+            }),
+            meter,
+        }
+    }
+
+    /// Returns true if the frame should be sent.
+    pub(crate) fn process(&mut self, frame: &mut [f32]) -> bool {
+        if let Some(suppressor) = &mut self.suppressor {
+            suppressor.process(frame);
+        }
+        let level = level_db(frame);
+        let open = self.gate.as_mut().is_none_or(|gate| gate.process(level));
+        self.meter.set(level, open);
+        open
+    }
+}
+
+fn level_db(frame: &[f32]) -> f32 {
+    let sum: f32 = frame.iter().map(|s| s * s).sum();
+    let rms = (sum / frame.len().max(1) as f32).sqrt();
+    (20.0 * rms.max(1e-9).log10()).max(SILENCE_DB * 2.0)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn fixture(name: &str) -> Vec<Vec<f32>> {
+        let path = format!(
+            "{}/src/net/audio/fixtures/{name}",
+            env!("CARGO_MANIFEST_DIR")
+        );
+        let samples: Vec<f32> = hound::WavReader::open(path)
+            .unwrap()
+            .samples::<i16>()
+            .map(|s| s.unwrap() as f32 / i16::MAX as f32)
+            .collect();
+        samples
+            .chunks_exact(FRAME_SIZE)
+            .map(|frame| frame.to_vec())
+            .collect()
+    }
+
+    fn gate(frames: &[Vec<f32>]) -> Vec<bool> {
+        let mut gate = VoiceGate::new(10.0, Duration::from_millis(200));
+        frames
+            .iter()
+            .map(|frame| gate.process(level_db(frame)))
+            .collect()
+    }
+
+    #[test]
+    fn gate_stays_closed_on_background_noise() {
+        let open = gate(&fixture("noise.wav"));
+        assert!(open.iter().filter(|open| **open).count() <= 2, "{open:?}");
+    }
+
+    #[test]
+    fn gate_opens_for_speech_with_hangover() {
+        // Speech is between 0.5s and 1s, and between 1.3s and 1.6s.
+        let open = gate(&fixture("speech.wav"));
+        let frame = |secs: f32| (secs / FRAME_DURATION.as_secs_f32()) as usize;
+        assert!(open[frame(0.1)..frame(0.5)].iter().all(|open| !open));
+        assert!(open[frame(0.5) + 1..frame(1.0)].iter().all(|open| *open));
+        // Kept open for the hangover after speech ends.
+        assert!(open[frame(1.0)..frame(1.2)].iter().all(|open| *open));
+        assert!(open[frame(1.2) + 1..frame(1.3)].iter().all(|open| !open));
+        assert!(open[frame(1.3) + 1..frame(1.6)].iter().all(|open| *open));
+        assert!(!open[frame(1.9)]);
+    }
+
+    #[test]
+    fn suppressor_removes_background_noise() {
+        let frames = fixture("noise.wav");
+        let mut suppressor = NoiseSuppressor::default();
+        let mut before = Vec::new();
+        let mut after = Vec::new();
+        for mut frame in frames {
+            before.push(level_db(&frame));
+            suppressor.process(&mut frame);
+            after.push(level_db(&frame));
+        }
+        // Denoiser needs a moment to learn what the noise sounds like.
+        let mean = |levels: &[f32]| levels[50..].iter().sum::<f32>() / levels[50..].len() as f32;
+        assert!(mean(&after) < mean(&before) - 6.0);
+    }
+}
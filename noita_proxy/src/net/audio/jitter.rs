@@ -0,0 +1,165 @@
+use std::collections::BTreeMap;
+
+/// Playback doesn't start until this many frames are buffered, adjusted as frames arrive late.
+const INITIAL_TARGET: usize = 2;
+const MAX_TARGET: usize = 10;
+/// After this many frames played without a late one, the target is lowered again.
+const SHRINK_AFTER: usize = 250;
+/// Missing frames in a row that are concealed before treating the speaker as silent.
+const MAX_CONCEALED: u32 = 3;
+/// Frames over the target buffered at most, older ones are dropped to keep the latency down.
+const MAX_EXCESS: usize = 4;
+
+/// What to play next.
+#[derive(Debug, PartialEq)]
+pub(crate) enum Pull {
+    Frame(Vec<u8>),
+    /// Frame is missing but the next one arrived, lost frame can be decoded from its FEC data.
+    Recover(Vec<u8>),
+    /// Frame is missing, decoder has to make something up.
+    Conceal,
+    /// Nothing to play.
+    Idle,
+}
+
+/// Reorders voice frames of a single peer by sequence number and smooths out their arrival times.
+pub(crate) struct JitterBuffer {
+    frames: BTreeMap<u32, Vec<u8>>,
+    /// Sequence number of the frame to play next, `None` while buffering.
+    next: Option<u32>,
+    target: usize,
+    concealed: u32,
+    played_since_late: usize,
+}
+
+impl Default for JitterBuffer {
+    fn default() -> Self {
+        Self {
+            frames: BTreeMap::new(),
+            next: None,
+            target: INITIAL_TARGET,
+            concealed: 0,
+            played_since_late: 0,
+        }
+    }
+}
+
+impl JitterBuffer {
+    pub(crate) fn push(&mut self, seq: u32, frame: Vec<u8>) {
+        if let Some(next) = self.next
+            && seq < next
+        {
+            if next - seq <= MAX_CONCEALED {
+                // Arrived after it had to be concealed.
+                self.target = (self.target + 1).min(MAX_TARGET);
+                self.played_since_late = 0;
+            }
+            return;
+        }
+        self.frames.insert(seq, frame);
+        while self.frames.len() > self.target + MAX_EXCESS {
+            self.frames.pop_first();
+        }
+    }
+
+    pub(crate) fn pull(&mut self) -> Pull {
+        let next = match self.next {
+            Some(next) => next,
+            None if self.frames.len() >= self.target => *self.frames.first_key_value().unwrap().0,
+            None => return Pull::Idle,
+        };
+        if let Some((&first, _)) = self.frames.first_key_value()
+            && first.wrapping_sub(next) > MAX_CONCEALED
+        {
+            // Either too far behind, or the speaker stopped and started talking again.
+            self.next = Some(first);
+            return self.pull();
+        }
+        self.next = Some(next.wrapping_add(1));
+        if let Some(frame) = self.frames.remove(&next) {
+            self.concealed = 0;
+            self.played_since_late += 1;
+            if self.played_since_late >= SHRINK_AFTER && self.target > 1 {
+                self.target -= 1;
+                self.played_since_late = 0;
+            }
+            return Pull::Frame(frame);
+        }
+        if self.frames.is_empty() {
+            self.concealed += 1;
+            if self.concealed > MAX_CONCEALED {
+                self.concealed = 0;
+                self.next = None;
+                return Pull::Idle;
+            }
+            return Pull::Conceal;
+        }
+        match self.frames.get(&next.wrapping_add(1)) {
+            Some(frame) => Pull::Recover(frame.clone()),
+            None => Pull::Conceal,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn frame(seq: u32) -> Vec<u8> {
+        vec![seq as u8]
+    }
+
+    #[test]
+    fn reorders_and_waits_for_target() {
+        let mut buffer = JitterBuffer::default();
+        buffer.push(11, frame(11));
+        assert_eq!(buffer.pull(), Pull::Idle);
+        buffer.push(10, frame(10));
+        buffer.push(12, frame(12));
+        assert_eq!(buffer.pull(), Pull::Frame(frame(10)));
+        assert_eq!(buffer.pull(), Pull::Frame(frame(11)));
+        assert_eq!(buffer.pull(), Pull::Frame(frame(12)));
+    }
+
+    #[test]
+    fn recovers_and_conceals_lost_frames() {
+        let mut buffer = JitterBuffer::default();
+        buffer.push(0, frame(0));
+        buffer.push(1, frame(1));
+        buffer.push(3, frame(3));
+        buffer.push(6, frame(6));
+        assert_eq!(buffer.pull(), Pull::Frame(frame(0)));
+        assert_eq!(buffer.pull(), Pull::Frame(frame(1)));
+        assert_eq!(buffer.pull(), Pull::Recover(frame(3)));
+        assert_eq!(buffer.pull(), Pull::Frame(frame(3)));
+        assert_eq!(buffer.pull(), Pull::Conceal);
+        assert_eq!(buffer.pull(), Pull::Recover(frame(6)));
+        assert_eq!(buffer.pull(), Pull::Frame(frame(6)));
+        for _ in 0..MAX_CONCEALED {
+            assert_eq!(buffer.pull(), Pull::Conceal);
+        }
+        assert_eq!(buffer.pull(), Pull::Idle);
+    }
+
+    #[test]
+    fn late_frames_grow_the_buffer() {
+        let mut buffer = JitterBuffer::default();
+        buffer.push(0, frame(0));
+        buffer.push(1, frame(1));
+        buffer.pull();
+        buffer.pull();
+        assert_eq!(buffer.pull(), Pull::Conceal);
+        buffer.push(2, frame(2));
+        assert_eq!(buffer.target, INITIAL_TARGET + 1);
+        // Arrived after the speaker was silent for a while, playback restarts from it.
+        buffer.push(100, frame(100));
+        buffer.push(101, frame(101));
+        buffer.push(102, frame(102));
+        assert_eq!(buffer.pull(), Pull::Frame(frame(100)));
+        for seq in 103..103 + SHRINK_AFTER as u32 {
+            buffer.push(seq, frame(seq));
+            buffer.pull();
+        }
+        assert_eq!(buffer.target, INITIAL_TARGET);
+    }
+}
@@ -0,0 +1,357 @@
+use std::{
+    f32::consts::{FRAC_PI_4, PI, SQRT_2, TAU},
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicU32, Ordering},
+    },
+    time::Duration,
+};
+
+use opus::Decoder;
+use rodio::{ChannelCount, SampleRate, Source};
+use tracing::warn;
+
+use super::{
+    CHANNELS, FRAME_SIZE, MAX_PACKET_SIZE, OPEN_CUTOFF, SAMPLE_RATE,
+    jitter::{JitterBuffer, Pull},
+    recorder::Track,
+};
+
+/// Length of the windows the pitch shifter crossfades between, in samples.
+const PITCH_WINDOW: usize = 1024;
+/// Pitch factors closer to 1 than this are played unshifted.
+const PITCH_EPSILON: f32 = 0.002;
+/// How much the shifted signal is faded in or out per sample, when shifting starts or stops.
+const PITCH_FADE_STEP: f32 = 1.0 / 480.0;
+
+/// Parameters of a playing `PeerVoice`, changed as the peer moves around. Picked up once per frame.
+pub(crate) struct VoiceControls {
+    /// Low-pass cutoff in Hz, used to muffle voice behind walls.
+    cutoff: AtomicU32,
+    /// From -1 (left) to 1 (right).
+    pan: AtomicU32,
+    /// Pitch factor for the doppler effect. Playback speed stays the same, otherwise the jitter buffer
+    /// would run dry or overflow while a peer keeps moving.
+    pitch: AtomicU32,
+}
+
+impl Default for VoiceControls {
+    fn default() -> Self {
+        Self {
+            cutoff: AtomicU32::new(OPEN_CUTOFF.to_bits()),
+            pan: AtomicU32::new(0.0f32.to_bits()),
+            pitch: AtomicU32::new(1.0f32.to_bits()),
+        }
+    }
+}
+
+impl VoiceControls {
+    pub(crate) fn set(&self, cutoff: f32, pan: f32, pitch: f32) {
+        self.cutoff.store(cutoff.to_bits(), Ordering::Relaxed);
+        self.pan.store(pan.to_bits(), Ordering::Relaxed);
+        self.pitch.store(pitch.to_bits(), Ordering::Relaxed);
+    }
+
+    fn get(atomic: &AtomicU32) -> f32 {
+        f32::from_bits(atomic.load(Ordering::Relaxed))
+    }
+}
+
+/// Endless stereo source playing voice of a single peer, pulling frames from its jitter buffer as they're needed.
+/// Every peer has its own decoder, as opus decoders keep state between frames.
+pub(crate) struct PeerVoice {
+    buffer: Arc<Mutex<JitterBuffer>>,
+    controls: Arc<VoiceControls>,
+    low_pass: LowPass,
+    pitch_shifter: PitchShifter,
+    decoder: Decoder,
+    /// Decoded voice, before it's positioned.
+    track: Track,
+    /// Length of the last decoded packet, lost packets are assumed to be as long.
+    packet_len: usize,
+    out: Vec<f32>,
+    /// Position in `out`.
+    pos: usize,
+    gains: [f32; 2],
+    /// Right channel of the current sample, if the left one was already played.
+    right: Option<f32>,
+}
+
+impl PeerVoice {
+    pub(crate) fn new(
+        buffer: Arc<Mutex<JitterBuffer>>,
+        controls: Arc<VoiceControls>,
+        track: Track,
+    ) -> Self {
+        Self {
+            buffer,
+            controls,
+            low_pass: LowPass::default(),
+            pitch_shifter: PitchShifter::default(),
+            decoder: Decoder::new(SAMPLE_RATE as u32, CHANNELS).unwrap(),
+            track,
+            packet_len: FRAME_SIZE,
+            out: Vec::new(),
+            pos: 0,
+            gains: pan_gains(0.0),
+            right: None,
+        }
+    }
+
+    fn decode_next(&mut self) {
+        let pull = self.buffer.lock().unwrap().pull();
+        let is_frame = matches!(pull, Pull::Frame(_));
+        let len = match pull {
+            Pull::Frame(_) => MAX_PACKET_SIZE,
+            Pull::Idle => FRAME_SIZE,
+            Pull::Recover(_) | Pull::Conceal => self.packet_len,
+        };
+        self.out.clear();
+        self.out.resize(len, 0.0);
+        let decoded = match pull {
+            Pull::Frame(frame) => self.decoder.decode_float(&frame, &mut self.out, false),
+            Pull::Recover(next) => self.decoder.decode_float(&next, &mut self.out, true),
+            // Empty packet makes the decoder conceal the loss.
+            Pull::Conceal => self.decoder.decode_float(&[], &mut self.out, false),
+            Pull::Idle => Ok(FRAME_SIZE),
+        };
+        match decoded {
+            Ok(len) if len != 0 => {
+                self.out.truncate(len);
+                if is_frame {
+                    self.packet_len = len;
+                }
+            }
+            Ok(_) => {}
+            Err(err) => {
+                warn!("Could not decode voice frame: {err}");
+                self.out.fill(0.0);
+            }
+        }
+        self.track.write(&self.out);
+        let cutoff = VoiceControls::get(&self.controls.cutoff);
+        self.low_pass.process(&mut self.out, cutoff);
+        let pitch = VoiceControls::get(&self.controls.pitch);
+        self.pitch_shifter.process(&mut self.out, pitch);
+        self.gains = pan_gains(VoiceControls::get(&self.controls.pan));
+    }
+
+    fn next_mono(&mut self) -> f32 {
+        while self.pos >= self.out.len() {
+            self.pos = 0;
+            self.decode_next();
+        }
+        let sample = self.out[self.pos];
+        self.pos += 1;
+        sample
+    }
+}
+
+/// Gains of the left and right channel. Loudness stays the same as mono played on both channels wherever it's panned.
+fn pan_gains(pan: f32) -> [f32; 2] {
+    let angle = (pan.clamp(-1.0, 1.0) + 1.0) * FRAC_PI_4;
+    [angle.cos() * SQRT_2, angle.sin() * SQRT_2]
+}
+
+/// Two one-pole low-pass filters in a row. Cutoff can change between frames without clicks.
+#[derive(Default)]
+struct LowPass {
+    first: f32,
+    second: f32,
+}
+
+impl LowPass {
+    fn process(&mut self, samples: &mut [f32], cutoff: f32) {
+        if cutoff >= OPEN_CUTOFF {
+            if let Some(&last) = samples.last() {
+                (self.first, self.second) = (last, last);
+            }
+            return;
+        }
+        let alpha = 1.0 - (-TAU * cutoff / SAMPLE_RATE as f32).exp();
+        for sample in samples {
+            self.first += alpha * (*sample - self.first);
+            self.second += alpha * (self.first - self.second);
+            *sample = self.second;
+        }
+    }
+}
+
+/// Shifts pitch without changing duration, by reading a delay line faster or slower than it's written.
+/// Two read heads half a window apart are crossfaded, so that the jump back when a head
+/// wraps around isn't heard.
+struct PitchShifter {
+    history: Vec<f32>,
+    /// Where the next sample is written to in `history`.
+    write: usize,
+    /// Delay of the first read head, from 0 to `PITCH_WINDOW`.
+    delay: f32,
+    /// How much of the shifted signal is heard, faded so that starting or stopping to shift doesn't click.
+    wet: f32,
+}
+
+impl Default for PitchShifter {
+    fn default() -> Self {
+        Self {
+            history: vec![0.0; PITCH_WINDOW * 2],
+            write: 0,
+            delay: 0.0,
+            wet: 0.0,
+        }
+    }
+}
+
+impl PitchShifter {
+    fn process(&mut self, samples: &mut [f32], pitch: f32) {
+        let shifting = (pitch - 1.0).abs() > PITCH_EPSILON;
+        if !shifting && self.wet == 0.0 {
+            // Still keep history, so that shifting starts smoothly.
+            for &sample in samples.iter() {
+                self.push(sample);
+            }
+            return;
+        }
+        let window = PITCH_WINDOW as f32;
+        for sample in samples {
+            self.push(*sample);
+            let mut shifted = 0.0;
+            for head in [0.0, 0.5] {
+                let delay = (self.delay + head * window) % window;
+                // Squared sine windows of heads half a window apart add up to 1.
+                let gain = (PI * delay / window).sin().powi(2);
+                shifted += self.read(delay) * gain;
+            }
+            self.delay = (self.delay + 1.0 - pitch).rem_euclid(window);
+            let target = if shifting { 1.0 } else { 0.0 };
+            self.wet = if self.wet < target {
+                (self.wet + PITCH_FADE_STEP).min(target)
+            } else {
+                (self.wet - PITCH_FADE_STEP).max(target)
+            };
+            *sample += (shifted - *sample) * self.wet;
+        }
+    }
+
+    fn push(&mut self, sample: f32) {
+        self.history[self.write] = sample;
+        self.write = (self.write + 1) % self.history.len();
+    }
+
+    /// Sample `delay` samples before the last one written, interpolated.
+    fn read(&self, delay: f32) -> f32 {
+        let len = self.history.len();
+        let pos = (self.write + len - 1) as f32 - delay;
+        let i = pos.floor() as usize;
+        let fract = pos.fract();
+        let a = self.history[i % len];
+        let b = self.history[(i + 1) % len];
+        a + (b - a) * fract
+    }
+}
+
+impl Iterator for PeerVoice {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        if let Some(right) = self.right.take() {
+            return Some(right);
+        }
+        let sample = self.next_mono();
+        self.right = Some(sample * self.gains[1]);
+        Some(sample * self.gains[0])
+    }
+}
+
+impl Source for PeerVoice {
+    fn current_span_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> ChannelCount {
+        2
+    }
+
+    fn sample_rate(&self) -> SampleRate {
+        SAMPLE_RATE as SampleRate
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn peak_after_filter(frequency: f32, cutoff: f32) -> f32 {
+        let mut samples: Vec<f32> = (0..FRAME_SIZE * 4)
+            .map(|i| (TAU * frequency * i as f32 / SAMPLE_RATE as f32).sin())
+            .collect();
+        LowPass::default().process(&mut samples, cutoff);
+        samples[FRAME_SIZE..]
+            .iter()
+            .fold(0.0, |peak, s| s.abs().max(peak))
+    }
+
+    #[test]
+    fn low_pass_muffles_high_frequencies() {
+        assert!(peak_after_filter(4000.0, OPEN_CUTOFF) > 0.99);
+        let low = peak_after_filter(200.0, 500.0);
+        let high = peak_after_filter(4000.0, 500.0);
+        assert!(low > 0.7, "{low}");
+        assert!(high < 0.05, "{high}");
+    }
+
+    /// Energy of `samples` at `frequency`, using the Goertzel algorithm.
+    fn energy_at(samples: &[f32], frequency: f32) -> f32 {
+        let coeff = 2.0 * (TAU * frequency / SAMPLE_RATE as f32).cos();
+        let (mut prev, mut prev2) = (0.0, 0.0);
+        for &sample in samples {
+            let s = sample + coeff * prev - prev2;
+            prev2 = prev;
+            prev = s;
+        }
+        prev2 * prev2 + prev * prev - coeff * prev * prev2
+    }
+
+    fn shifted_sine(frequency: f32, pitch: f32) -> Vec<f32> {
+        let mut samples: Vec<f32> = (0..FRAME_SIZE * 10)
+            .map(|i| (TAU * frequency * i as f32 / SAMPLE_RATE as f32).sin())
+            .collect();
+        let mut shifter = PitchShifter::default();
+        for frame in samples.chunks_mut(FRAME_SIZE) {
+            shifter.process(frame, pitch);
+        }
+        samples
+    }
+
+    #[test]
+    fn pitch_shift_keeps_duration() {
+        let samples = shifted_sine(400.0, 1.1);
+        assert_eq!(samples.len(), FRAME_SIZE * 10);
+        // Past the fade in.
+        let tail = &samples[FRAME_SIZE * 2..];
+        assert!(energy_at(tail, 440.0) > energy_at(tail, 400.0) * 4.0);
+
+        let samples = shifted_sine(400.0, 1.0 / 1.1);
+        let tail = &samples[FRAME_SIZE * 2..];
+        assert!(energy_at(tail, 400.0 / 1.1) > energy_at(tail, 400.0) * 4.0);
+
+        // Unshifted voice is left alone.
+        let samples = shifted_sine(400.0, 1.0);
+        assert!((samples[100] - (TAU * 400.0 * 100.0 / SAMPLE_RATE as f32).sin()).abs() < 1e-6);
+    }
+
+    #[test]
+    fn panning_keeps_loudness() {
+        for pan in [-1.0, -0.3, 0.0, 0.5, 1.0] {
+            let [left, right] = pan_gains(pan);
+            assert!((left * left + right * right - 2.0).abs() < 1e-4);
+        }
+        let [left, right] = pan_gains(0.0);
+        assert!((left - 1.0).abs() < 1e-4 && (right - 1.0).abs() < 1e-4);
+        let [left, right] = pan_gains(1.0);
+        assert!(left.abs() < 1e-4 && right > 1.4);
+    }
+}
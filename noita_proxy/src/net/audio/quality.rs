@@ -0,0 +1,136 @@
+use super::{FRAME_SIZE, MAX_PACKET_SIZE};
+use crate::{audio_settings::VoiceQuality, net::omni::LinkQuality};
+
+/// Bitrate never goes below this, voice gets hard to understand under it. In bits per second.
+const MIN_BITRATE: i32 = 8_000;
+/// Loss assumed when it isn't measured, in percent.
+const ASSUMED_LOSS_PERCENT: i32 = 10;
+/// Over this much loss, bitrate is lowered to make room for error correction and longer packets are sent.
+const HIGH_LOSS: f32 = 0.1;
+/// Longest packet sent, in frames.
+const MAX_PACKET_FRAMES: usize = MAX_PACKET_SIZE / FRAME_SIZE;
+
+/// How outgoing voice should be encoded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct EncoderTarget {
+    /// In bits per second.
+    pub(crate) bitrate: i32,
+    /// Frames sent in a single packet. Longer packets have less overhead, but add latency.
+    pub(crate) packet_frames: usize,
+    pub(crate) fec: bool,
+    /// Loss opus should expect, it decides how much error correction data to send from it.
+    pub(crate) loss_percent: i32,
+}
+
+impl VoiceQuality {
+    fn bitrate(self) -> i32 {
+        match self {
+            VoiceQuality::Low => 12_000,
+            VoiceQuality::Normal => 24_000,
+            VoiceQuality::High => 48_000,
+        }
+    }
+
+    fn packet_frames(self) -> usize {
+        match self {
+            VoiceQuality::Low => 2,
+            VoiceQuality::Normal | VoiceQuality::High => 1,
+        }
+    }
+}
+
+/// Adapts `quality` to the worst of the `links` voice is sent over.
+pub(crate) fn target(
+    quality: VoiceQuality,
+    links: impl IntoIterator<Item = LinkQuality>,
+) -> EncoderTarget {
+    let mut loss = None::<f32>;
+    let mut saturated = false;
+    for link in links {
+        loss = Some(loss.unwrap_or(0.0).max(link.loss));
+        saturated |= link.saturated;
+    }
+    let loss_percent = match loss {
+        Some(loss) => (loss * 100.0).ceil() as i32,
+        None => ASSUMED_LOSS_PERCENT,
+    }
+    .clamp(0, 100);
+    let high_loss = loss.is_some_and(|loss| loss > HIGH_LOSS);
+    let mut bitrate = quality.bitrate();
+    let mut packet_frames = quality.packet_frames();
+    if high_loss {
+        bitrate = bitrate * 3 / 4;
+        packet_frames += 1;
+    }
+    if saturated {
+        bitrate /= 2;
+        packet_frames += 1;
+    }
+    EncoderTarget {
+        bitrate: bitrate.max(MIN_BITRATE),
+        packet_frames: packet_frames.min(MAX_PACKET_FRAMES),
+        fec: loss_percent > 0,
+        loss_percent,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn link(loss: f32, saturated: bool) -> LinkQuality {
+        LinkQuality { loss, saturated }
+    }
+
+    #[test]
+    fn adapts_to_worst_link() {
+        let unmeasured = target(VoiceQuality::Normal, []);
+        assert_eq!(unmeasured.bitrate, VoiceQuality::Normal.bitrate());
+        assert_eq!(unmeasured.loss_percent, ASSUMED_LOSS_PERCENT);
+        assert!(unmeasured.fec);
+
+        let clean = target(VoiceQuality::High, [link(0.0, false)]);
+        assert_eq!(clean.bitrate, VoiceQuality::High.bitrate());
+        assert_eq!(clean.packet_frames, 1);
+        assert!(!clean.fec);
+
+        let lossy = target(VoiceQuality::High, [link(0.0, false), link(0.2, false)]);
+        assert!(lossy.bitrate < clean.bitrate);
+        assert_eq!(lossy.packet_frames, 2);
+        assert_eq!(lossy.loss_percent, 20);
+
+        let saturated = target(VoiceQuality::Low, [link(0.3, true)]);
+        assert_eq!(saturated.bitrate, MIN_BITRATE);
+        assert_eq!(saturated.packet_frames, MAX_PACKET_FRAMES);
+    }
+
+    #[test]
+    fn adapts_to_tangled_links() {
+        let stats = |sent, lost, congestion_events| tangled::LinkStats {
+            sent_packets: sent,
+            lost_packets: lost,
+            congestion_events,
+            ..Default::default()
+        };
+        let clean = target(
+            VoiceQuality::Normal,
+            [LinkQuality::from_tangled(&stats(100, 0, 0))],
+        );
+        assert_eq!(clean.bitrate, VoiceQuality::Normal.bitrate());
+        assert!(!clean.fec);
+
+        let lossy = target(
+            VoiceQuality::Normal,
+            [LinkQuality::from_tangled(&stats(100, 20, 0))],
+        );
+        assert!(lossy.bitrate < clean.bitrate);
+        assert_eq!(lossy.loss_percent, 20);
+        assert!(lossy.fec);
+
+        let congested = LinkQuality::from_tangled(&stats(100, 0, 2));
+        assert!(congested.saturated);
+        assert!(target(VoiceQuality::Normal, [congested]).bitrate < clean.bitrate);
+        // Nothing was sent, so nothing could be lost.
+        assert_eq!(LinkQuality::from_tangled(&stats(0, 0, 0)).loss, 0.0);
+    }
+}
@@ -0,0 +1,253 @@
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    io::{self, BufWriter},
+    path::{Path, PathBuf},
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, Receiver, Sender},
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use hound::{SampleFormat, WavSpec, WavWriter};
+use tracing::{info, warn};
+
+use super::{FRAME_SIZE, SAMPLE_RATE};
+use crate::bookkeeping::save_slots::unix_now;
+
+/// Tracks are padded with silence once they fall behind by more than this many samples,
+/// so that all of them line up with the start of the recording.
+const MAX_LAG: u64 = FRAME_SIZE as u64 * 5;
+
+enum Command {
+    Start(PathBuf),
+    /// Sends back once every file is finished.
+    Stop(Sender<()>),
+    Samples {
+        track: Arc<str>,
+        samples: Vec<f32>,
+        /// When the samples were played, or sent for our own voice.
+        at: Instant,
+    },
+    /// Track won't get any more samples.
+    Close(Arc<str>),
+}
+
+/// Opt-in recording of voice, every peer and our own microphone go to a separate WAV file.
+/// Each recording is a directory named after the unix time it was started at.
+///
+/// Files are written on a thread of their own, as tracks are written to from the audio output thread.
+pub(crate) struct Recorder {
+    tx: Sender<Command>,
+    /// Checked by tracks before every write, so that nothing is sent while not recording.
+    recording: AtomicBool,
+    dir: Mutex<Option<PathBuf>>,
+}
+
+impl Default for Recorder {
+    fn default() -> Self {
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || write_tracks(rx));
+        Self {
+            tx,
+            recording: AtomicBool::new(false),
+            dir: Mutex::new(None),
+        }
+    }
+}
+
+impl Recorder {
+    /// Starts a new recording in `dir`, returns the directory it's written to.
+    pub(crate) fn start(&self, dir: &Path) -> io::Result<PathBuf> {
+        let dir = dir.join(unix_now().to_string());
+        fs::create_dir_all(&dir)?;
+        *self.dir.lock().unwrap() = Some(dir.clone());
+        self.tx.send(Command::Start(dir.clone())).ok();
+        self.recording.store(true, Ordering::Relaxed);
+        info!("Recording voice to {}", dir.display());
+        Ok(dir)
+    }
+
+    /// Stops recording, returns once the files are finished.
+    pub(crate) fn stop(&self) {
+        if self.dir.lock().unwrap().take().is_none() {
+            return;
+        }
+        self.recording.store(false, Ordering::Relaxed);
+        let (done_tx, done_rx) = mpsc::channel();
+        self.tx.send(Command::Stop(done_tx)).ok();
+        if done_rx.recv_timeout(Duration::from_secs(5)).is_err() {
+            warn!("Voice recording took too long to finish");
+        }
+        info!("Stopped recording voice");
+    }
+
+    /// Directory of the current recording.
+    pub(crate) fn recording_to(&self) -> Option<PathBuf> {
+        self.dir.lock().unwrap().clone()
+    }
+
+    pub(crate) fn is_recording(&self) -> bool {
+        self.recording.load(Ordering::Relaxed)
+    }
+}
+
+struct OpenTrack {
+    writer: WavWriter<BufWriter<File>>,
+    written: u64,
+}
+
+/// Runs until the recorder and all of its tracks are gone.
+fn write_tracks(rx: Receiver<Command>) {
+    let mut session: Option<(PathBuf, Instant)> = None;
+    let mut tracks: HashMap<Arc<str>, OpenTrack> = HashMap::new();
+    for command in rx {
+        match command {
+            Command::Start(dir) => {
+                finish_all(&mut tracks);
+                session = Some((dir, Instant::now()));
+            }
+            Command::Stop(done) => {
+                finish_all(&mut tracks);
+                session = None;
+                done.send(()).ok();
+            }
+            Command::Samples { track, samples, at } => {
+                let Some((dir, started)) = &session else {
+                    continue;
+                };
+                if !tracks.contains_key(&track) {
+                    let Some(open) = open_file(dir, &track) else {
+                        continue;
+                    };
+                    tracks.insert(track.clone(), open);
+                }
+                let open = tracks.get_mut(&track).unwrap();
+                let expected = (at.saturating_duration_since(*started).as_secs_f64()
+                    * SAMPLE_RATE as f64) as u64;
+                let silence = expected.saturating_sub(open.written + samples.len() as u64);
+                let silence = if silence > MAX_LAG { silence } else { 0 };
+                let result = (0..silence)
+                    .map(|_| 0.0)
+                    .chain(samples.iter().copied())
+                    .try_for_each(|sample| {
+                        open.writer
+                            .write_sample((sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16)
+                    });
+                open.written += silence + samples.len() as u64;
+                if let Err(err) = result {
+                    warn!("Could not record voice of {track}: {err}");
+                    tracks.remove(&track);
+                }
+            }
+            Command::Close(track) => {
+                if let Some(open) = tracks.remove(&track) {
+                    finish(&track, open);
+                }
+            }
+        }
+    }
+    finish_all(&mut tracks);
+}
+
+fn open_file(dir: &Path, track: &str) -> Option<OpenTrack> {
+    let path = dir.join(format!("{track}.wav"));
+    let spec = WavSpec {
+        channels: 1,
+        sample_rate: SAMPLE_RATE as u32,
+        bits_per_sample: 16,
+        sample_format: SampleFormat::Int,
+    };
+    match WavWriter::create(&path, spec) {
+        Ok(writer) => Some(OpenTrack { writer, written: 0 }),
+        Err(err) => {
+            warn!("Could not create {}: {err}", path.display());
+            None
+        }
+    }
+}
+
+fn finish(track: &str, open: OpenTrack) {
+    if let Err(err) = open.writer.finalize() {
+        warn!("Could not finish recording of {track}: {err}");
+    }
+}
+
+fn finish_all(tracks: &mut HashMap<Arc<str>, OpenTrack>) {
+    for (track, open) in tracks.drain() {
+        finish(&track, open);
+    }
+}
+
+/// Voice of a single peer, written to its file while recording is on.
+pub(crate) struct Track {
+    recorder: Arc<Recorder>,
+    name: Arc<str>,
+}
+
+impl Track {
+    pub(crate) fn new(recorder: Arc<Recorder>, name: String) -> Self {
+        Self {
+            recorder,
+            name: name.into(),
+        }
+    }
+
+    /// Queues samples to be written if recording is on. Called with no samples, it only opens the file.
+    /// Doesn't block, so it's fine to call from the audio output thread.
+    pub(crate) fn write(&mut self, samples: &[f32]) {
+        if !self.recorder.is_recording() {
+            return;
+        }
+        self.recorder
+            .tx
+            .send(Command::Samples {
+                track: self.name.clone(),
+                samples: samples.to_vec(),
+                at: Instant::now(),
+            })
+            .ok();
+    }
+}
+
+impl Drop for Track {
+    fn drop(&mut self) {
+        self.recorder
+            .tx
+            .send(Command::Close(self.name.clone()))
+            .ok();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn records_tracks_while_on() {
+        let dir = std::env::temp_dir().join("ew_voice_recorder_test");
+        fs::remove_dir_all(&dir).ok();
+        let recorder = Arc::new(Recorder::default());
+        let mut track = Track::new(recorder.clone(), "peer".into());
+        track.write(&[0.5; FRAME_SIZE]);
+        assert!(!dir.exists());
+
+        let session = recorder.start(&dir).unwrap();
+        track.write(&[0.5; FRAME_SIZE]);
+        track.write(&[-0.5; FRAME_SIZE]);
+        recorder.stop();
+        track.write(&[]);
+        assert!(!recorder.is_recording());
+
+        let samples: Vec<i16> = hound::WavReader::open(session.join("peer.wav"))
+            .unwrap()
+            .samples::<i16>()
+            .map(|s| s.unwrap())
+            .collect();
+        assert_eq!(samples.len(), FRAME_SIZE * 2);
+        assert!(samples[0] > 0 && samples[FRAME_SIZE] < 0);
+    }
+}
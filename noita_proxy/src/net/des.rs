@@ -3,15 +3,24 @@ use std::mem;
 use bitcode::{Decode, Encode};
 use rstar::{RTree, primitives::GeomWithData};
 use rustc_hash::FxHashMap;
-use shared::des::{
-    DesToProxy, FullEntityData, Gid, ProxyToDes, REQUEST_AUTHORITY_RADIUS, UpdateOrUpload,
-    UpdatePosition,
+use shared::{
+    WorldPos,
+    des::{
+        DesToProxy, FullEntityData, Gid, INTEREST_REQUEST_RADIUS, ProxyToDes,
+        REQUEST_AUTHORITY_RADIUS, UpdateOrUpload, UpdatePosition,
+    },
 };
 use tracing::{info, warn};
 
 use crate::bookkeeping::save_state::{SaveState, SaveStateEntry};
 
-use super::omni::OmniPeerId;
+use super::{
+    entity_query::{EntityInfo, EntityQuery},
+    omni::OmniPeerId,
+};
+
+/// Entities freed because someone's Noita disconnected are given right away to the nearest peer within that distance.
+const HANDOFF_RADIUS: i32 = INTEREST_REQUEST_RADIUS;
 
 #[derive(Encode, Decode, Default)]
 struct EntityStorage {
@@ -20,6 +29,14 @@ struct EntityStorage {
 
 impl SaveStateEntry for EntityStorage {
     const FILENAME: &'static str = "des_entity_storage";
+    const VERSION: u32 = 1;
+}
+
+pub(super) fn load_entities(save_state: &SaveState) -> FxHashMap<Gid, FullEntityData> {
+    save_state
+        .load::<EntityStorage>()
+        .unwrap_or_default()
+        .entities
 }
 
 pub(crate) struct DesManager {
@@ -29,6 +46,10 @@ pub(crate) struct DesManager {
     authority: FxHashMap<Gid, OmniPeerId>,
     pending_messages: Vec<(OmniPeerId, ProxyToDes)>,
     save_state: SaveState,
+    /// Amount of rejected messages per peer, used to avoid flooding the log.
+    violations: FxHashMap<OmniPeerId, u32>,
+    /// Last position peers requested authority at.
+    peer_pos: FxHashMap<OmniPeerId, WorldPos>,
 }
 
 impl DesManager {
@@ -52,6 +73,74 @@ impl DesManager {
             pending_messages: Vec::new(),
             save_state,
             is_host,
+            violations: Default::default(),
+            peer_pos: Default::default(),
+        }
+    }
+
+    /// Checks that `source` is allowed to change the entity.
+    ///
+    /// With `claim_free`, entities that are stored, but aren't under anyone's authority are given to `source`,
+    /// as it obviously still has them in its world. Otherwise they would be spawned a second time
+    /// once someone else requests authority over them. Only updates may claim them, deleting or
+    /// transferring a free entity would let anyone take over entities anywhere on the map.
+    fn check_authority(
+        &mut self,
+        source: OmniPeerId,
+        gid: Gid,
+        what: &str,
+        claim_free: bool,
+    ) -> bool {
+        match self.authority.get(&gid) {
+            Some(&holder) if holder == source => true,
+            Some(&holder) => {
+                self.report_violation(source, gid, what, Some(holder));
+                false
+            }
+            None if claim_free && self.entity_storage.entities.contains_key(&gid) => {
+                info!("{source} sent {what} for free entity {gid:?}, giving it authority");
+                self.remove_gid_from_tree(gid);
+                self.authority.insert(gid, source);
+                true
+            }
+            None => {
+                self.report_violation(source, gid, what, None);
+                false
+            }
+        }
+    }
+
+    fn report_violation(
+        &mut self,
+        source: OmniPeerId,
+        gid: Gid,
+        what: &str,
+        holder: Option<OmniPeerId>,
+    ) {
+        // Source still thinks it's in charge of its copy, which would go on fighting the rightful one.
+        if holder.is_some()
+            && !self.pending_messages.iter().any(|(peer, msg)| {
+                *peer == source && matches!(msg, ProxyToDes::LostAuthority(lost) if *lost == gid)
+            })
+        {
+            self.pending_messages
+                .push((source, ProxyToDes::LostAuthority(gid)));
+        }
+        let count = self.violations.entry(source).or_default();
+        *count += 1;
+        // Messages sent right before authority got transferred are expected to be rejected, so don't log all of them.
+        if *count == 1 || count.is_multiple_of(100) {
+            match holder {
+                Some(holder) => warn!(
+                    "Rejected {what} from {source} for entity {gid:?} under authority of {holder} ({count} rejected so far)"
+                ),
+                None if self.entity_storage.entities.contains_key(&gid) => warn!(
+                    "Rejected {what} from {source} for free entity {gid:?} ({count} rejected so far)"
+                ),
+                None => warn!(
+                    "Rejected {what} from {source} for unknown entity {gid:?} ({count} rejected so far)"
+                ),
+            }
         }
     }
 
@@ -73,6 +162,12 @@ impl DesManager {
     fn handle_update(&mut self, update: UpdateOrUpload, source: OmniPeerId) {
         match update {
             UpdateOrUpload::Upload(full_entity_data) => {
+                if let Some(&holder) = self.authority.get(&full_entity_data.gid)
+                    && holder != source
+                {
+                    self.report_violation(source, full_entity_data.gid, "Upload", Some(holder));
+                    return;
+                }
                 self.authority.insert(full_entity_data.gid, source);
                 self.entity_storage
                     .entities
@@ -88,6 +183,9 @@ impl DesManager {
                     phys,
                     synced_var,
                 } = update;
+                if !self.check_authority(source, gid, "UpdatePosition", true) {
+                    return;
+                }
                 self.remove_gid_from_tree(gid);
                 if let Some(entity) = self.entity_storage.entities.get_mut(&gid) {
                     entity.pos = pos;
@@ -104,9 +202,11 @@ impl DesManager {
     }
 
     pub(crate) fn handle_noita_msg(&mut self, source: OmniPeerId, msg: DesToProxy) {
-        // TODO maybe check that authorities are correct?
         match msg {
             DesToProxy::UpdateWand(gid, wand) => {
+                if !self.check_authority(source, gid, "UpdateWand", true) {
+                    return;
+                }
                 self.entity_storage
                     .entities
                     .entry(gid)
@@ -114,6 +214,9 @@ impl DesManager {
             }
             DesToProxy::DeleteEntity(gid, ent) => {
                 if self.entity_storage.entities.contains_key(&gid) {
+                    if !self.check_authority(source, gid, "DeleteEntity", false) {
+                        return;
+                    }
                     self.authority.remove(&gid);
                     self.entity_storage.entities.remove(&gid);
                     self.remove_gid_from_tree(gid);
@@ -123,10 +226,20 @@ impl DesManager {
                 }
             }
             DesToProxy::ReleaseAuthority(gid) => {
+                match self.authority.get(&gid) {
+                    Some(&holder) if holder == source => {}
+                    Some(&holder) => {
+                        self.report_violation(source, gid, "ReleaseAuthority", Some(holder));
+                        return;
+                    }
+                    // Already free.
+                    None => return,
+                }
                 self.authority.remove(&gid);
                 self.add_gid_to_tree(gid);
             }
             DesToProxy::RequestAuthority { pos } => {
+                self.peer_pos.insert(source, pos);
                 // drain_within_distance panics without this check. Funny.
                 if self.rtree.size() == 0 {
                     return;
@@ -162,6 +275,9 @@ impl DesManager {
                 }
             }
             DesToProxy::TransferAuthorityTo(gid, peer_id) => {
+                if !self.check_authority(source, gid, "TransferAuthorityTo", false) {
+                    return;
+                }
                 if let Some(entity) = self.entity_storage.entities.get(&gid).cloned() {
                     //info!("Transferring authority over entity from {source:?} to {peer_id:?}");
                     self.authority.insert(gid, peer_id.into());
@@ -175,8 +291,8 @@ impl DesManager {
     }
 
     pub(crate) fn noita_disconnected(&mut self, source: OmniPeerId) {
-        // TODO also remove entities from affected clients faster.
         info!("Peer {source} disconnected, freeing entities that were under authority");
+        self.peer_pos.remove(&source);
         let mut free_again = Vec::new();
         self.authority.retain(|gid, authority| {
             let remove = source == *authority;
@@ -185,20 +301,83 @@ impl DesManager {
             }
             !remove
         });
+        // Entities near other players would freeze until they get close enough to request authority,
+        // so hand them over to whoever is closest.
+        let mut handoff: FxHashMap<OmniPeerId, Vec<FullEntityData>> = FxHashMap::default();
         for gid in free_again {
-            self.add_gid_to_tree(gid);
+            let Some(entity) = self.entity_storage.entities.get(&gid) else {
+                continue;
+            };
+            if let Some(peer) = self.nearest_peer(entity.pos) {
+                self.authority.insert(gid, peer);
+                handoff.entry(peer).or_default().push(entity.clone());
+            } else {
+                self.add_gid_to_tree(gid);
+            }
         }
+        for (peer, entities) in handoff {
+            info!("Handing {} entities over to {peer}", entities.len());
+            // Stale copies have to be gone before the new ones are spawned, and this can arrive before
+            // peer gets notified of the disconnect.
+            self.pending_messages
+                .push((peer, ProxyToDes::RemoveEntities(source.into())));
+            self.pending_messages
+                .push((peer, ProxyToDes::GotAuthoritys(entities)));
+        }
+    }
+
+    fn nearest_peer(&self, pos: WorldPos) -> Option<OmniPeerId> {
+        self.peer_pos
+            .iter()
+            .map(|(peer, peer_pos)| (*peer, peer_pos.dist(&pos).0))
+            .filter(|(_, dist)| *dist <= i64::from(HANDOFF_RADIUS).pow(2) as u64)
+            .min_by_key(|(_, dist)| *dist)
+            .map(|(peer, _)| peer)
     }
 
     pub(crate) fn pending_messages(&mut self) -> Vec<(OmniPeerId, ProxyToDes)> {
         mem::take(&mut self.pending_messages)
     }
 
+    pub(crate) fn query(&self, query: &EntityQuery) -> Vec<EntityInfo> {
+        query.run(&self.entity_storage.entities, &self.rtree, &self.authority)
+    }
+
+    pub(crate) fn snapshot_entities(&self) -> FxHashMap<Gid, FullEntityData> {
+        self.entity_storage.entities.clone()
+    }
+
+    /// Replaces stored entities with ones from a snapshot.
+    /// Entities that are currently under someone's authority are alive in their game, so these are kept as is,
+    /// otherwise they would get duplicated once someone requests authority over the restored copy.
+    pub(crate) fn restore_entities(&mut self, mut entities: FxHashMap<Gid, FullEntityData>) {
+        for gid in self.authority.keys() {
+            entities.remove(gid);
+            if let Some(entity) = self.entity_storage.entities.remove(gid) {
+                entities.insert(*gid, entity);
+            }
+        }
+        let elements: Vec<_> = entities
+            .iter()
+            .filter(|(gid, _)| !self.authority.contains_key(gid))
+            .map(|(&gid, ent)| GeomWithData::new(ent.pos.as_array(), gid))
+            .collect();
+        info!(
+            "Restored {} entities, {} are free",
+            entities.len(),
+            elements.len()
+        );
+        self.rtree = RTree::bulk_load(elements);
+        self.entity_storage.entities = entities;
+    }
+
     pub(crate) fn reset(&mut self) {
         self.entity_storage = Default::default();
         self.rtree = RTree::default();
         self.authority.clear();
         self.pending_messages.clear();
+        self.violations.clear();
+        self.peer_pos.clear();
     }
 }
 
@@ -209,3 +388,152 @@ impl Drop for DesManager {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use shared::des::EntitySpawnInfo;
+
+    use super::*;
+
+    fn upload(gid: u64, x: i32) -> DesToProxy {
+        DesToProxy::UpdatePosition(UpdateOrUpload::Upload(FullEntityData {
+            gid: Gid(gid),
+            pos: WorldPos { x, y: 0 },
+            data: EntitySpawnInfo::Filename("data/entities/animals/zombie.xml".into()),
+            wand: None,
+            hp: 10.0,
+            drops_gold: true,
+            is_charmed: false,
+            counter: 0,
+            phys: Vec::new(),
+            synced_var: Vec::new(),
+        }))
+    }
+
+    #[test]
+    fn handoff_on_disconnect() {
+        let save_state = SaveState::new(std::env::temp_dir().join("ew_des_handoff_test"));
+        let mut des = DesManager::new(false, save_state);
+        let (crashed, near, far) = (OmniPeerId(1), OmniPeerId(2), OmniPeerId(3));
+        des.handle_noita_msg(crashed, upload(10, 100));
+        des.handle_noita_msg(crashed, upload(11, 20000));
+        des.handle_noita_msg(
+            near,
+            DesToProxy::RequestAuthority {
+                pos: WorldPos { x: 700, y: 0 },
+            },
+        );
+        des.handle_noita_msg(
+            far,
+            DesToProxy::RequestAuthority {
+                pos: WorldPos { x: 5000, y: 0 },
+            },
+        );
+        des.noita_disconnected(crashed);
+
+        let pending = des.pending_messages();
+        assert_eq!(pending.len(), 2);
+        assert!(matches!(
+            pending[0],
+            (peer, ProxyToDes::RemoveEntities(removed)) if peer == near && removed == shared::PeerId::from(crashed)
+        ));
+        let (peer, ProxyToDes::GotAuthoritys(entities)) = &pending[1] else {
+            panic!("expected GotAuthoritys");
+        };
+        assert_eq!(*peer, near);
+        assert_eq!(entities.len(), 1);
+        assert_eq!(entities[0].gid, Gid(10));
+        assert_eq!(des.authority.get(&Gid(10)), Some(&near));
+        // Nobody is near this one, so it just goes back to storage.
+        assert!(!des.authority.contains_key(&Gid(11)));
+        assert_eq!(des.rtree.size(), 1);
+    }
+
+    fn update(gid: u64, x: i32) -> DesToProxy {
+        DesToProxy::UpdatePosition(UpdateOrUpload::Update(UpdatePosition {
+            gid: Gid(gid),
+            pos: WorldPos { x, y: 0 },
+            counter: 0,
+            is_charmed: false,
+            hp: 10.0,
+            phys: Vec::new(),
+            synced_var: Vec::new(),
+        }))
+    }
+
+    fn des_manager(name: &str) -> DesManager {
+        DesManager::new(false, SaveState::new(std::env::temp_dir().join(name)))
+    }
+
+    #[test]
+    fn holder_changes_are_accepted() {
+        let mut des = des_manager("ew_des_accepted_test");
+        let (holder, other) = (OmniPeerId(1), OmniPeerId(2));
+        des.handle_noita_msg(holder, upload(10, 100));
+        des.handle_noita_msg(holder, update(10, 200));
+        assert_eq!(des.entity_storage.entities[&Gid(10)].pos.x, 200);
+
+        des.handle_noita_msg(
+            holder,
+            DesToProxy::TransferAuthorityTo(Gid(10), other.into()),
+        );
+        assert_eq!(des.authority.get(&Gid(10)), Some(&other));
+        let pending = des.pending_messages();
+        assert!(matches!(
+            &pending[..],
+            [(peer, ProxyToDes::GotAuthority(entity))] if *peer == other && entity.gid == Gid(10)
+        ));
+
+        des.handle_noita_msg(other, DesToProxy::DeleteEntity(Gid(10), None));
+        assert!(!des.entity_storage.entities.contains_key(&Gid(10)));
+        assert!(des.violations.is_empty());
+    }
+
+    #[test]
+    fn others_changes_are_rejected() {
+        let mut des = des_manager("ew_des_rejected_test");
+        let (holder, other) = (OmniPeerId(1), OmniPeerId(2));
+        des.handle_noita_msg(holder, upload(10, 100));
+        des.handle_noita_msg(other, update(10, 200));
+        des.handle_noita_msg(other, update(10, 300));
+        des.handle_noita_msg(
+            other,
+            DesToProxy::TransferAuthorityTo(Gid(10), other.into()),
+        );
+        des.handle_noita_msg(other, DesToProxy::DeleteEntity(Gid(10), None));
+
+        assert_eq!(des.entity_storage.entities[&Gid(10)].pos.x, 100);
+        assert_eq!(des.authority.get(&Gid(10)), Some(&holder));
+        assert_eq!(des.violations[&other], 4);
+        // Violator is told once to drop its copy.
+        let pending = des.pending_messages();
+        assert!(matches!(
+            &pending[..],
+            [(peer, ProxyToDes::LostAuthority(gid))] if *peer == other && *gid == Gid(10)
+        ));
+    }
+
+    #[test]
+    fn free_entities_are_only_claimed_by_updates() {
+        let mut des = des_manager("ew_des_free_test");
+        let (holder, other) = (OmniPeerId(1), OmniPeerId(2));
+        des.handle_noita_msg(holder, upload(10, 100));
+        des.handle_noita_msg(holder, DesToProxy::ReleaseAuthority(Gid(10)));
+        assert!(!des.authority.contains_key(&Gid(10)));
+
+        des.handle_noita_msg(other, DesToProxy::DeleteEntity(Gid(10), None));
+        des.handle_noita_msg(
+            other,
+            DesToProxy::TransferAuthorityTo(Gid(10), other.into()),
+        );
+        assert!(des.entity_storage.entities.contains_key(&Gid(10)));
+        assert!(!des.authority.contains_key(&Gid(10)));
+        assert_eq!(des.rtree.size(), 1);
+        assert!(des.pending_messages().is_empty());
+
+        des.handle_noita_msg(other, update(10, 200));
+        assert_eq!(des.authority.get(&Gid(10)), Some(&other));
+        assert_eq!(des.entity_storage.entities[&Gid(10)].pos.x, 200);
+        assert_eq!(des.rtree.size(), 0);
+    }
+}
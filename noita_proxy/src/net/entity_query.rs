@@ -0,0 +1,214 @@
+use rstar::{RTree, primitives::GeomWithData};
+use rustc_hash::FxHashMap;
+use serde::Serialize;
+use shared::{
+    WorldPos,
+    des::{EntitySpawnInfo, FullEntityData, Gid},
+};
+
+use crate::bookkeeping::save_state::SaveState;
+
+use super::omni::OmniPeerId;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AuthorityFilter {
+    /// Not under anyone's authority, only stored.
+    Free,
+    Peer(OmniPeerId),
+}
+
+/// Filters for entities in DES entity storage. Every filter that is set has to match.
+#[derive(Debug, Clone, Default)]
+pub struct EntityQuery {
+    /// Part of the entity filename.
+    pub filename: Option<String>,
+    /// Center and radius.
+    pub region: Option<(WorldPos, i32)>,
+    pub authority: Option<AuthorityFilter>,
+    pub min_hp: Option<f32>,
+    pub max_hp: Option<f32>,
+    pub has_wand: Option<bool>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct EntityInfo {
+    pub gid: u64,
+    /// `None` for serialized entities.
+    pub filename: Option<String>,
+    pub x: i32,
+    pub y: i32,
+    pub hp: f32,
+    pub has_wand: bool,
+    pub is_charmed: bool,
+    pub authority: Option<String>,
+}
+
+impl EntityQuery {
+    /// `rtree` only needs to contain free entities, ones under authority are checked separately.
+    pub(crate) fn run(
+        &self,
+        entities: &FxHashMap<Gid, FullEntityData>,
+        rtree: &RTree<GeomWithData<[i64; 2], Gid>>,
+        authority: &FxHashMap<Gid, OmniPeerId>,
+    ) -> Vec<EntityInfo> {
+        let candidates: Vec<Gid> = match self.region {
+            Some((center, radius)) => {
+                let radius_sq = i64::from(radius).pow(2);
+                rtree
+                    .locate_within_distance(center.as_array(), radius_sq)
+                    .map(|point| point.data)
+                    .chain(authority.keys().copied().filter(|gid| {
+                        entities
+                            .get(gid)
+                            .is_some_and(|ent| ent.pos.dist(&center).0 <= radius_sq as u64)
+                    }))
+                    .collect()
+            }
+            None => entities.keys().copied().collect(),
+        };
+        let mut found: Vec<EntityInfo> = candidates
+            .into_iter()
+            .filter_map(|gid| {
+                let ent = entities.get(&gid)?;
+                let holder = authority.get(&gid).copied();
+                self.matches(ent, holder).then(|| EntityInfo {
+                    gid: gid.0,
+                    filename: filename(ent).map(str::to_owned),
+                    x: ent.pos.x,
+                    y: ent.pos.y,
+                    hp: ent.hp,
+                    has_wand: ent.wand.is_some(),
+                    is_charmed: ent.is_charmed,
+                    authority: holder.map(|peer| peer.as_hex()),
+                })
+            })
+            .collect();
+        found.sort_unstable_by_key(|info| info.gid);
+        found
+    }
+
+    fn matches(&self, ent: &FullEntityData, holder: Option<OmniPeerId>) -> bool {
+        if let Some(name) = &self.filename
+            && !filename(ent).is_some_and(|f| f.contains(name.as_str()))
+        {
+            return false;
+        }
+        match self.authority {
+            Some(AuthorityFilter::Free) if holder.is_some() => return false,
+            Some(AuthorityFilter::Peer(peer)) if holder != Some(peer) => return false,
+            _ => {}
+        }
+        if self.min_hp.is_some_and(|min| ent.hp < min)
+            || self.max_hp.is_some_and(|max| ent.hp > max)
+        {
+            return false;
+        }
+        self.has_wand.is_none_or(|wand| wand == ent.wand.is_some())
+    }
+}
+
+fn filename(ent: &FullEntityData) -> Option<&str> {
+    match &ent.data {
+        EntitySpawnInfo::Filename(name) => Some(name),
+        EntitySpawnInfo::Serialized { .. } => None,
+    }
+}
+
+/// Runs the query against entity storage of a save state, nothing is under authority there.
+pub fn query_save_state(save_state: &SaveState, query: &EntityQuery) -> Vec<EntityInfo> {
+    let entities = super::des::load_entities(save_state);
+    let rtree = RTree::bulk_load(
+        entities
+            .iter()
+            .map(|(&gid, ent)| GeomWithData::new(ent.pos.as_array(), gid))
+            .collect(),
+    );
+    query.run(&entities, &rtree, &Default::default())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn entity(gid: u64, name: &str, x: i32, hp: f32, wand: bool) -> (Gid, FullEntityData) {
+        (
+            Gid(gid),
+            FullEntityData {
+                gid: Gid(gid),
+                pos: WorldPos { x, y: 0 },
+                data: EntitySpawnInfo::Filename(name.to_owned()),
+                wand: wand.then(Vec::new),
+                hp,
+                drops_gold: false,
+                is_charmed: false,
+                counter: 0,
+                phys: Vec::new(),
+                synced_var: Vec::new(),
+            },
+        )
+    }
+
+    #[test]
+    fn query_filters() {
+        let entities: FxHashMap<Gid, FullEntityData> = [
+            entity(1, "data/entities/animals/zombie.xml", 0, 10.0, false),
+            entity(2, "data/entities/animals/shotgunner.xml", 100, 30.0, true),
+            entity(3, "data/entities/animals/zombie.xml", 5000, 50.0, false),
+        ]
+        .into_iter()
+        .collect();
+        let authority: FxHashMap<Gid, OmniPeerId> = [(Gid(3), OmniPeerId(7))].into_iter().collect();
+        let rtree = RTree::bulk_load(
+            entities
+                .iter()
+                .filter(|(gid, _)| !authority.contains_key(gid))
+                .map(|(&gid, ent)| GeomWithData::new(ent.pos.as_array(), gid))
+                .collect(),
+        );
+        let gids = |query: EntityQuery| -> Vec<u64> {
+            query
+                .run(&entities, &rtree, &authority)
+                .into_iter()
+                .map(|info| info.gid)
+                .collect()
+        };
+
+        assert_eq!(gids(EntityQuery::default()), vec![1, 2, 3]);
+        let zombies = EntityQuery {
+            filename: Some("zombie".into()),
+            ..Default::default()
+        };
+        assert_eq!(gids(zombies), vec![1, 3]);
+        let near = EntityQuery {
+            region: Some((WorldPos { x: 0, y: 0 }, 200)),
+            ..Default::default()
+        };
+        assert_eq!(gids(near), vec![1, 2]);
+        let far = EntityQuery {
+            region: Some((WorldPos { x: 5000, y: 0 }, 10)),
+            ..Default::default()
+        };
+        assert_eq!(gids(far), vec![3]);
+        let held = EntityQuery {
+            authority: Some(AuthorityFilter::Peer(OmniPeerId(7))),
+            ..Default::default()
+        };
+        assert_eq!(gids(held), vec![3]);
+        let free = EntityQuery {
+            authority: Some(AuthorityFilter::Free),
+            ..Default::default()
+        };
+        assert_eq!(gids(free), vec![1, 2]);
+        let armed = EntityQuery {
+            has_wand: Some(true),
+            min_hp: Some(20.0),
+            ..Default::default()
+        };
+        assert_eq!(gids(armed), vec![2]);
+        let weak = EntityQuery {
+            max_hp: Some(20.0),
+            ..Default::default()
+        };
+        assert_eq!(gids(weak), vec![1]);
+    }
+}
@@ -1,4 +1,8 @@
-use super::{omni::OmniPeerId, world::WorldNetMessage};
+use super::{
+    audio::{VoiceFrames, VoiceModeration},
+    omni::OmniPeerId,
+    world::WorldNetMessage,
+};
 use crate::net::world::world_model::{ChunkCoord, ChunkData};
 use crate::{GameSettings, player_cosmetics::PlayerPngDesc};
 use bitcode::{Decode, Encode};
@@ -35,7 +39,11 @@ pub(crate) enum NetMsg {
     RespondFlagMoon(i32, i32, bool),
     PlayerPosition(i32, i32, bool, bool),
     RespondFlagStevari(i32, i32, OmniPeerId),
-    AudioData(Vec<Vec<u8>>, bool, i32, i32, f32),
+    AudioData(VoiceFrames),
+    VoiceChannel(Option<String>),
+    VoiceRecording(bool),
+    VoiceMute(bool),
+    VoiceModeration(VoiceModeration),
     MapData(FxHashMap<ChunkCoord, ChunkData>),
     MatData(FxHashMap<u16, u32>),
 }
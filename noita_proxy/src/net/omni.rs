@@ -1,7 +1,7 @@
 use super::steam_networking::{self, ExtraPeerState};
 use bitcode::{Decode, Encode};
 use serde::{Deserialize, Serialize};
-use std::fmt::Display;
+use std::{collections::HashMap, fmt::Display};
 use steamworks::{LobbyId, SteamError, SteamId};
 use tangled::{PeerId, Reliability};
 
@@ -66,6 +66,30 @@ impl OmniPeerId {
     }
 }
 
+/// Measured state of the connection to a peer.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct LinkQuality {
+    /// Fraction of packets lost, from 0 to 1.
+    pub(crate) loss: f32,
+    /// More is queued for sending than can be sent soon.
+    pub(crate) saturated: bool,
+}
+
+impl LinkQuality {
+    /// Quality of a tangled connection, from its stats since they were last taken.
+    pub(crate) fn from_tangled(stats: &tangled::LinkStats) -> Self {
+        Self {
+            loss: if stats.sent_packets == 0 {
+                0.0
+            } else {
+                (stats.lost_packets as f32 / stats.sent_packets as f32).min(1.0)
+            },
+            // Congestion controller only slows down once sending outgrows the link.
+            saturated: stats.congestion_events > 0,
+        }
+    }
+}
+
 pub enum OmniNetworkEvent {
     PeerConnected(OmniPeerId),
     PeerDisconnected(OmniPeerId),
@@ -180,6 +204,33 @@ impl PeerVariant {
         }
     }
 
+    /// Quality of connections to peers that it's known for.
+    pub(crate) fn link_quality(&self) -> HashMap<OmniPeerId, LinkQuality> {
+        match self {
+            PeerVariant::Tangled(p) => {
+                let links: HashMap<OmniPeerId, LinkQuality> = p
+                    .link_stats()
+                    .into_iter()
+                    .map(|(peer, stats)| (peer.into(), LinkQuality::from_tangled(&stats)))
+                    .collect();
+                if self.is_host() {
+                    return links;
+                }
+                // Clients reach everyone through host.
+                let Some(&host) = links.get(&self.host_id()) else {
+                    return links;
+                };
+                let my_id = self.my_id();
+                self.iter_peer_ids()
+                    .into_iter()
+                    .filter(|peer| *peer != my_id)
+                    .map(|peer| (peer, host))
+                    .collect()
+            }
+            PeerVariant::Steam(p) => p.link_quality(),
+        }
+    }
+
     pub fn is_steam(&self) -> bool {
         matches!(self, PeerVariant::Steam(_))
     }
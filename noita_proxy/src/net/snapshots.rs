@@ -0,0 +1,186 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    thread,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use bitcode::{Decode, Encode};
+use rustc_hash::FxHashMap;
+use shared::des::{FullEntityData, Gid};
+use tracing::{error, info, warn};
+
+use super::world::world_model::{ChunkCoord, ChunkData};
+
+/// How often host takes a snapshot.
+const SNAPSHOT_INTERVAL: Duration = Duration::from_secs(5 * 60);
+/// How many snapshots are kept around, older ones are removed.
+const SNAPSHOTS_KEPT: usize = 6;
+/// Every snapshot file starts with this, followed by `SNAPSHOT_VERSION` as little endian u32.
+const HEADER_MAGIC: &[u8; 4] = b"EWSN";
+const HEADER_LEN: usize = HEADER_MAGIC.len() + 4;
+/// Has to be bumped whenever encoded layout of `WorldSnapshot` changes, older snapshots are then ignored.
+const SNAPSHOT_VERSION: u32 = 1;
+
+/// Everything host stores about the world, to be able to roll it back after a glitch.
+#[derive(Encode, Decode)]
+pub(crate) struct WorldSnapshot {
+    pub(crate) world_num: i32,
+    pub(crate) chunks: FxHashMap<ChunkCoord, ChunkData>,
+    pub(crate) entities: FxHashMap<Gid, FullEntityData>,
+}
+
+/// Periodically writes snapshots to disk. Snapshots are named after the unix time they were taken at.
+pub(crate) struct Snapshots {
+    dir: PathBuf,
+    last_taken: Instant,
+}
+
+impl Snapshots {
+    pub(crate) fn new(save_state_path: &Path) -> Self {
+        Self {
+            dir: snapshot_dir(save_state_path),
+            last_taken: Instant::now(),
+        }
+    }
+
+    pub(crate) fn is_due(&self) -> bool {
+        self.last_taken.elapsed() >= SNAPSHOT_INTERVAL
+    }
+
+    /// Encodes and writes the snapshot in the background.
+    pub(crate) fn take(&mut self, snapshot: WorldSnapshot) {
+        self.last_taken = Instant::now();
+        let dir = self.dir.clone();
+        thread::spawn(move || {
+            let id = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs();
+            if let Err(err) = save(&dir, id, &snapshot) {
+                error!("Could not write snapshot: {err}");
+                return;
+            }
+            info!(
+                "Took world snapshot {id}: {} chunks, {} entities",
+                snapshot.chunks.len(),
+                snapshot.entities.len()
+            );
+            prune(&dir);
+        });
+    }
+
+    pub(crate) fn load(&self, id: u64) -> Option<WorldSnapshot> {
+        load(&self.dir, id)
+    }
+}
+
+fn path(dir: &Path, id: u64) -> PathBuf {
+    dir.join(format!("{id}.bit"))
+}
+
+fn save(dir: &Path, id: u64, snapshot: &WorldSnapshot) -> io::Result<()> {
+    fs::create_dir_all(dir)?;
+    let encoded = bitcode::encode(snapshot);
+    let mut file = Vec::with_capacity(HEADER_LEN + encoded.len());
+    file.extend_from_slice(HEADER_MAGIC);
+    file.extend_from_slice(&SNAPSHOT_VERSION.to_le_bytes());
+    file.extend_from_slice(&lz4_flex::compress_prepend_size(&encoded));
+    fs::write(path(dir, id), file)
+}
+
+fn load(dir: &Path, id: u64) -> Option<WorldSnapshot> {
+    let data = fs::read(path(dir, id))
+        .inspect_err(|err| warn!("Could not read snapshot {id}: {err}"))
+        .ok()?;
+    let Some(data) = data.strip_prefix(HEADER_MAGIC) else {
+        warn!("Snapshot {id} has no header, it's too old to be loaded");
+        return None;
+    };
+    let (version, data) = data.split_at_checked(HEADER_LEN - HEADER_MAGIC.len())?;
+    let version = u32::from_le_bytes(version.try_into().unwrap());
+    if version != SNAPSHOT_VERSION {
+        warn!("Snapshot {id} is of version {version}, only {SNAPSHOT_VERSION} can be loaded");
+        return None;
+    }
+    let data = lz4_flex::decompress_size_prepended(data)
+        .inspect_err(|err| warn!("Could not decompress snapshot {id}: {err}"))
+        .ok()?;
+    bitcode::decode(&data)
+        .inspect_err(|err| error!("Could not decode snapshot {id}: {err}"))
+        .ok()
+}
+
+/// Removes all but `SNAPSHOTS_KEPT` newest snapshots.
+fn prune(dir: &Path) {
+    for old in list(dir).into_iter().skip(SNAPSHOTS_KEPT) {
+        fs::remove_file(path(dir, old)).ok();
+    }
+}
+
+pub(crate) fn snapshot_dir(save_state_path: &Path) -> PathBuf {
+    save_state_path.join("snapshots")
+}
+
+/// Ids of available snapshots, newest first.
+pub(crate) fn list(dir: &Path) -> Vec<u64> {
+    let mut ids: Vec<u64> = fs::read_dir(dir)
+        .map(|entries| {
+            entries
+                .filter_map(|entry| {
+                    let name = entry.ok()?.file_name();
+                    name.to_str()?.strip_suffix(".bit")?.parse().ok()
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+    ids.sort_unstable_by(|a, b| b.cmp(a));
+    ids
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn snapshot(world_num: i32) -> WorldSnapshot {
+        WorldSnapshot {
+            world_num,
+            chunks: Default::default(),
+            entities: Default::default(),
+        }
+    }
+
+    #[test]
+    fn snapshots_round_trip_and_get_pruned() {
+        let dir = std::env::temp_dir().join("ew_snapshots_test");
+        fs::remove_dir_all(&dir).ok();
+        for id in 1..=SNAPSHOTS_KEPT as u64 + 2 {
+            save(&dir, id, &snapshot(id as i32)).unwrap();
+        }
+        assert_eq!(list(&dir).len(), SNAPSHOTS_KEPT + 2);
+        prune(&dir);
+        let ids = list(&dir);
+        assert_eq!(ids.len(), SNAPSHOTS_KEPT);
+        assert_eq!(ids[0], SNAPSHOTS_KEPT as u64 + 2);
+        assert!(!ids.contains(&1) && !ids.contains(&2));
+        assert_eq!(load(&dir, ids[0]).unwrap().world_num, ids[0] as i32);
+        assert!(load(&dir, 1).is_none());
+    }
+
+    #[test]
+    fn snapshots_of_other_versions_are_ignored() {
+        let dir = std::env::temp_dir().join("ew_snapshots_version_test");
+        fs::remove_dir_all(&dir).ok();
+        save(&dir, 1, &snapshot(3)).unwrap();
+        let mut data = fs::read(path(&dir, 1)).unwrap();
+        data[HEADER_MAGIC.len()..HEADER_LEN].copy_from_slice(&(SNAPSHOT_VERSION + 1).to_le_bytes());
+        fs::write(path(&dir, 1), data).unwrap();
+        assert!(load(&dir, 1).is_none());
+        fs::write(
+            path(&dir, 2),
+            lz4_flex::compress_prepend_size(&bitcode::encode(&snapshot(3))),
+        )
+        .unwrap();
+        assert!(load(&dir, 2).is_none());
+    }
+}
@@ -1,4 +1,4 @@
-use std::{fmt::Display, mem, sync::Mutex};
+use std::{collections::HashMap, fmt::Display, mem, sync::Mutex};
 
 use crossbeam::channel;
 use dashmap::DashMap;
@@ -20,7 +20,10 @@ use crate::{
     steam_helper::LobbyExtraData,
 };
 
-use super::omni::{OmniNetworkEvent, OmniPeerId};
+use super::omni::{LinkQuality, OmniNetworkEvent, OmniPeerId};
+
+/// Connection is saturated once more than this many seconds worth of data waits to be sent.
+const SATURATED_AFTER_SECS: f32 = 0.25;
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum ConnectError {
@@ -644,6 +647,36 @@ impl SteamPeer {
         ConnectionStatusReport { per_peer_statuses }
     }
 
+    pub(crate) fn link_quality(&self) -> HashMap<OmniPeerId, LinkQuality> {
+        self.generate_report()
+            .per_peer_statuses
+            .into_iter()
+            .filter_map(|PerPeerStatusEntry { peer, status }| {
+                let PerPeerStatus::Connected { realtimeinfo } = status else {
+                    return None;
+                };
+                // Quality is negative while it's not known yet.
+                let quality = realtimeinfo
+                    .connection_quality_local()
+                    .min(realtimeinfo.connection_quality_remote());
+                let pending = realtimeinfo.pending_unreliable() as f32
+                    + realtimeinfo.pending_reliable() as f32;
+                Some((
+                    peer,
+                    LinkQuality {
+                        loss: if quality < 0.0 {
+                            0.0
+                        } else {
+                            1.0 - quality.min(1.0)
+                        },
+                        saturated: pending
+                            > realtimeinfo.send_rate_bytes_per_sec() as f32 * SATURATED_AFTER_SECS,
+                    },
+                ))
+            })
+            .collect()
+    }
+
     pub(crate) fn update_lobby_data(&self, data: LobbyExtraData) {
         let matchmaking = self.client.matchmaking();
         if let Some(id) = self.lobby_id() {
@@ -1,20 +1,24 @@
+use authority_policy::{AuthorityPolicy, DistanceOnly, PeerLoad};
 use bitcode::{Decode, Encode};
+use chunk_storage::ChunkStorage;
 use image::RgbaImage;
-use rand::{Rng, rng};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
 use rayon::iter::IntoParallelIterator;
 use rayon::iter::ParallelIterator;
+use rayon::{ThreadPool, ThreadPoolBuilder};
 use rustc_hash::{FxHashMap, FxHashSet};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::f32::consts::TAU;
 use std::sync::mpsc;
 use std::sync::mpsc::{Receiver, Sender};
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use std::{cmp, env, mem, thread};
 use tracing::{debug, info, warn};
 use wide::f32x8;
 use world_model::{
-    CHUNK_SIZE, ChunkCoord, ChunkData, ChunkDelta, WorldModel,
+    CHUNK_SIZE, ChunkCoord, ChunkData, ChunkDelta, DeltaEncoding, WorldModel,
     chunk::{Chunk, Pixel},
 };
 
@@ -23,11 +27,13 @@ pub use world_model::encoding::NoitaWorldUpdate;
 use crate::bookkeeping::save_state::{SaveState, SaveStateEntry};
 
 use super::{
-    CellType, ExplosionData,
+    CellType, ExplosionData, LiquidType,
     messages::{Destination, MessageRequest},
     omni::OmniPeerId,
 };
 
+pub(crate) mod authority_policy;
+mod chunk_storage;
 pub mod world_model;
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -134,6 +140,12 @@ pub(crate) enum WorldNetMessage {
     NotifyNewAuthority {
         chunk: ChunkCoord,
     },
+    // Tell host how loaded we are, for authority balancing
+    ReportLoad {
+        load: PeerLoad,
+    },
+    // Host rolled the world back, drop whatever we have and request authority again
+    RollbackWorld,
 }
 
 #[derive(Debug, PartialEq, Eq)]
@@ -176,6 +188,8 @@ impl ChunkState {
 pub(crate) struct WorldManager {
     pub nice_terraforming: bool,
     pub is_host: bool,
+    /// Only messages host is allowed to send are checked against it, it's us until told otherwise.
+    pub host_id: OmniPeerId,
     my_pos: (i32, i32),
     cam_pos: (i32, i32),
     is_notplayer: bool,
@@ -186,7 +200,7 @@ pub(crate) struct WorldManager {
     /// We use that to create changes to be sent to other clients.
     outbound_model: WorldModel,
     /// Stores chunks that aren't under any authority.
-    chunk_storage: FxHashMap<ChunkCoord, ChunkData>,
+    chunk_storage: ChunkStorage,
     /// Who is the current chunk authority.
     authority_map: FxHashMap<ChunkCoord, (OmniPeerId, u8)>,
     /// Chunk states, according to docs/distributed_world_sync.drawio
@@ -207,8 +221,19 @@ pub(crate) struct WorldManager {
     explosion_data: Vec<(usize, usize, ExTarget, u64)>,
     explosion_heap: Vec<ExplosionData>,
     tx: Sender<(ChunkCoord, ChunkData)>,
+    /// Worker pool for terrain cuts and explosions, work is split per affected chunk.
+    pool: ThreadPool,
+    /// Decides how load affects chunk priorities.
+    authority_policy: Box<dyn AuthorityPolicy>,
+    local_load: PeerLoad,
+    last_end: Option<Instant>,
+    /// Load reported by peers, only used by host.
+    peer_loads: FxHashMap<OmniPeerId, PeerLoad>,
 }
 
+/// How often (in updates) load gets reported to host.
+const LOAD_REPORT_INTERVAL: u64 = 60;
+
 #[derive(Copy, Clone, PartialEq)]
 pub(crate) enum ExTarget {
     Ray(u64),
@@ -258,12 +283,18 @@ impl WorldManager {
         for (ch, c) in chunk_storage.iter() {
             let _ = tx.send((*ch, c.clone()));
         }
+        let chunk_storage = ChunkStorage::new(save_state.path().join("chunk_cache"), chunk_storage);
+        let threads = env::var("NP_WORLD_THREADS")
+            .ok()
+            .and_then(|x| x.parse().ok())
+            .unwrap_or(0);
         let (fx, _) = mpsc::channel::<(ChunkCoord, ChunkData)>();
         if is_host {
             (
                 WorldManager {
                     nice_terraforming: true,
                     is_host,
+                    host_id: my_peer_id,
                     my_pos: (i32::MIN / 2, i32::MIN / 2),
                     cam_pos: (i32::MIN / 2, i32::MIN / 2),
                     is_notplayer: false,
@@ -285,6 +316,11 @@ impl WorldManager {
                     explosion_data: Default::default(),
                     explosion_heap: Default::default(),
                     tx,
+                    pool: world_pool(threads),
+                    authority_policy: Box::new(DistanceOnly),
+                    local_load: Default::default(),
+                    last_end: None,
+                    peer_loads: Default::default(),
                 },
                 rx,
                 recv2,
@@ -296,6 +332,7 @@ impl WorldManager {
                 WorldManager {
                     nice_terraforming: true,
                     is_host,
+                    host_id: my_peer_id,
                     my_pos: (i32::MIN / 2, i32::MIN / 2),
                     cam_pos: (i32::MIN / 2, i32::MIN / 2),
                     is_notplayer: false,
@@ -317,6 +354,11 @@ impl WorldManager {
                     explosion_data: Default::default(),
                     explosion_heap: Default::default(),
                     tx: fx,
+                    pool: world_pool(threads),
+                    authority_policy: Box::new(DistanceOnly),
+                    local_load: Default::default(),
+                    last_end: None,
+                    peer_loads: Default::default(),
                 },
                 rx,
                 recv2,
@@ -327,7 +369,46 @@ impl WorldManager {
     }
 
     pub(crate) fn get_chunks(&self) -> FxHashMap<ChunkCoord, ChunkData> {
-        self.chunk_storage.clone()
+        self.chunk_storage.to_map()
+    }
+
+    pub(crate) fn world_num(&self) -> i32 {
+        self.world_num
+    }
+
+    /// Chunks to put in a snapshot: chunk storage, plus what we see of chunks we're currently syncing.
+    /// Chunks under authority of far away peers are as recent as their last storage update.
+    pub(crate) fn snapshot_chunks(&self) -> FxHashMap<ChunkCoord, ChunkData> {
+        let mut chunks = self.chunk_storage.to_map();
+        for (&chunk, state) in &self.chunk_state {
+            if matches!(
+                state,
+                ChunkState::Authority { .. } | ChunkState::Listening { .. }
+            ) && let Some(data) = self.outbound_model.get_chunk_data(chunk)
+            {
+                chunks.insert(chunk, data);
+            }
+        }
+        chunks
+    }
+
+    /// Replaces chunk storage with the snapshot and makes everyone re-request authority,
+    /// so that restored chunks get sent out with `GotAuthority`. Host only.
+    pub(crate) fn restore_snapshot(&mut self, chunks: FxHashMap<ChunkCoord, ChunkData>) {
+        info!(
+            "Rolling world back to a snapshot of {} chunks",
+            chunks.len()
+        );
+        self.chunk_storage.clear();
+        for (chunk, data) in chunks {
+            self.chunk_storage.insert(chunk, data);
+        }
+        self.authority_map.clear();
+        self.is_storage_recent.clear();
+        self.explosion_pointer.clear();
+        self.explosion_data.clear();
+        self.explosion_heap.clear();
+        self.emit_msg(Destination::Broadcast, WorldNetMessage::RollbackWorld);
     }
 
     pub(crate) fn add_update(&mut self, update: NoitaWorldUpdate) {
@@ -335,7 +416,49 @@ impl WorldManager {
             .apply_noita_update(&update, &mut self.is_storage_recent);
     }
 
+    pub(crate) fn set_authority_policy(&mut self, policy: Box<dyn AuthorityPolicy>) {
+        self.authority_policy = policy;
+    }
+
+    pub(crate) fn set_upload_rate(&mut self, upload_bps: u32) {
+        self.local_load.upload_bps = upload_bps;
+    }
+
+    fn update_local_load(&mut self) {
+        let now = Instant::now();
+        if let Some(last) = self.last_end.replace(now) {
+            let frame_time_ms = now.duration_since(last).as_secs_f32() * 1000.0;
+            self.local_load.frame_time_ms =
+                self.local_load.frame_time_ms * 0.9 + frame_time_ms * 0.1;
+        }
+        if self.authority_policy.wants_load_reports()
+            && self.current_update.is_multiple_of(LOAD_REPORT_INTERVAL)
+        {
+            self.local_load.authority_chunks = self
+                .chunk_state
+                .values()
+                .filter(|state| matches!(state, ChunkState::Authority { .. }))
+                .count() as u32;
+            self.emit_msg(
+                Destination::Host,
+                WorldNetMessage::ReportLoad {
+                    load: self.local_load,
+                },
+            );
+        }
+    }
+
+    /// Whether host should avoid giving more chunks to this peer.
+    fn is_overloaded(&self, peer: OmniPeerId) -> bool {
+        self.peer_loads
+            .get(&peer)
+            .map(|load| self.authority_policy.is_overloaded(load))
+            .unwrap_or(false)
+    }
+
     pub(crate) fn add_end(&mut self, priority: u8, pos: &[i32]) {
+        self.update_local_load();
+        let priority = self.authority_policy.priority(priority, &self.local_load);
         let updated_chunks = self
             .outbound_model
             .updated_chunks()
@@ -349,7 +472,10 @@ impl WorldManager {
             .collect();
         let mut chunk_packet: HashMap<OmniPeerId, Vec<(ChunkDelta, u8)>> = HashMap::new();
         for (chunk, who_sending) in updated_chunks.iter().zip(chunks_to_send.iter()) {
-            let Some(delta) = self.outbound_model.get_chunk_delta(*chunk, false) else {
+            let Some(delta) =
+                self.outbound_model
+                    .get_chunk_delta(*chunk, false, DeltaEncoding::Smallest)
+            else {
                 continue;
             };
             for (peer, pri) in who_sending {
@@ -449,7 +575,10 @@ impl WorldManager {
                 new_authority,
                 stop_sending,
             } => {
-                let Some(delta) = self.outbound_model.get_chunk_delta(chunk, false) else {
+                let Some(delta) =
+                    self.outbound_model
+                        .get_chunk_delta(chunk, false, DeltaEncoding::Smallest)
+                else {
                     return Vec::new();
                 };
                 if *pri != priority {
@@ -634,6 +763,7 @@ impl WorldManager {
             }
             retain
         });
+        self.chunk_storage.evict();
     }
 
     pub(crate) fn get_noita_updates(&mut self) -> Vec<Vec<u8>> {
@@ -681,6 +811,7 @@ impl WorldManager {
     }
 
     fn emit_got_authority(&mut self, chunk: ChunkCoord, source: OmniPeerId, priority: u8) {
+        self.chunk_storage.load(chunk);
         let auth = self.authority_map.get(&chunk);
         let chunk_data = if auth
             .map(|a| a.0 != source) //TODO doesn't work
@@ -749,7 +880,10 @@ impl WorldManager {
                         if source == authority {
                             debug!("{source} already has authority of {chunk:?}");
                             self.emit_got_authority(chunk, source, priority);
-                        } else if priority_state > priority && !can_wait {
+                        } else if priority_state > priority
+                            && !can_wait
+                            && !self.is_overloaded(source)
+                        {
                             debug!("{source} is gaining priority over {chunk:?} from {authority}");
                             self.emit_transfer_authority(chunk, source, priority, authority);
                         } else {
@@ -1162,6 +1296,51 @@ impl WorldManager {
                     },
                 );
             }
+            WorldNetMessage::ReportLoad { load } => {
+                if !self.is_host {
+                    warn!("{} sent ReportLoad to not-host.", source);
+                    return;
+                }
+                self.peer_loads.insert(source, load);
+            }
+            WorldNetMessage::RollbackWorld => {
+                if source != self.my_peer_id && self.is_host {
+                    warn!("{} sent RollbackWorld to host.", source);
+                    return;
+                }
+                if !self.is_host && source != self.host_id {
+                    warn!("{} sent RollbackWorld, but isn't host.", source);
+                    return;
+                }
+                // Authority map on host is already gone, so just forget about it without relinquishing.
+                let chunk_state = mem::take(&mut self.chunk_state);
+                for (chunk, state) in chunk_state {
+                    let priority = match state {
+                        ChunkState::RequestAuthority { priority, .. }
+                        | ChunkState::Listening { priority, .. }
+                        | ChunkState::Authority { priority, .. }
+                        | ChunkState::WantToGetAuth {
+                            my_priority: priority,
+                            ..
+                        } => priority,
+                        ChunkState::UnloadPending => continue,
+                        ChunkState::WaitingForAuthority | ChunkState::Transfer => {
+                            let Some(priority) = self.last_request_priority.get(&chunk) else {
+                                continue;
+                            };
+                            *priority
+                        }
+                    };
+                    self.chunk_state.insert(
+                        chunk,
+                        ChunkState::RequestAuthority {
+                            priority,
+                            can_wait: false,
+                        },
+                    );
+                }
+                self.last_request_priority.clear();
+            }
             WorldNetMessage::NotifyNewAuthority { chunk } => {
                 debug!("Notified of new authority");
                 let state = self.chunk_state.get_mut(&chunk);
@@ -1212,6 +1391,7 @@ impl WorldManager {
         if !self.is_host {
             return;
         }
+        self.peer_loads.remove(&source);
         let mut pending_messages = Vec::new();
 
         for (&chunk, peer) in self.authority_map.iter() {
@@ -1242,36 +1422,40 @@ impl WorldManager {
             flags: PixelFlags::Normal,
             material: 0,
         };
-        let chunk_storage: Vec<(ChunkCoord, ChunkData)> = self
-            .chunk_storage
-            .clone()
-            .into_par_iter()
-            .filter(|(coord, _)| {
-                min_cx <= coord.0 && max_cx >= coord.0 && coord.1 <= max_cy && coord.1 >= min_cy
-            })
-            .map(|(chunk_coord, chunk_encoded)| {
-                let chunk_start_x = chunk_coord.0 * CHUNK_SIZE as i32;
-                let chunk_end_x = chunk_start_x + CHUNK_SIZE as i32;
-                let chunk_start_y = chunk_coord.1 * CHUNK_SIZE as i32;
-                let mut chunk = Chunk::default();
-                chunk_encoded.apply_to_chunk(&mut chunk);
-                for in_chunk_y in 0..(CHUNK_SIZE as i32) {
-                    let global_y = in_chunk_y + chunk_start_y;
-                    let wiggle = -(global_y as f32 / interval * TAU).cos() * max_wiggle as f32;
-                    let wiggle = wiggle.round() as i32;
-                    let in_chunk_x_range = ((start + wiggle).clamp(chunk_start_x, chunk_end_x)
-                        - chunk_start_x)
-                        ..((end + wiggle).clamp(chunk_start_x, chunk_end_x) - chunk_start_x);
-                    for in_chunk_x in in_chunk_x_range {
-                        chunk.set_pixel(
-                            (in_chunk_y as usize) * CHUNK_SIZE + (in_chunk_x as usize),
-                            air_pixel,
-                        );
+        self.chunk_storage.load_area(min_cx, max_cx, min_cy, max_cy);
+        let chunk_storage: Vec<(ChunkCoord, ChunkData)> = self.pool.install(|| {
+            self.chunk_storage
+                .iter()
+                .filter(|(coord, _)| {
+                    min_cx <= coord.0 && max_cx >= coord.0 && coord.1 <= max_cy && coord.1 >= min_cy
+                })
+                .map(|(chunk_coord, chunk_encoded)| (*chunk_coord, chunk_encoded.clone()))
+                .collect::<Vec<_>>()
+                .into_par_iter()
+                .map(|(chunk_coord, chunk_encoded)| {
+                    let chunk_start_x = chunk_coord.0 * CHUNK_SIZE as i32;
+                    let chunk_end_x = chunk_start_x + CHUNK_SIZE as i32;
+                    let chunk_start_y = chunk_coord.1 * CHUNK_SIZE as i32;
+                    let mut chunk = Chunk::default();
+                    chunk_encoded.apply_to_chunk(&mut chunk);
+                    for in_chunk_y in 0..(CHUNK_SIZE as i32) {
+                        let global_y = in_chunk_y + chunk_start_y;
+                        let wiggle = -(global_y as f32 / interval * TAU).cos() * max_wiggle as f32;
+                        let wiggle = wiggle.round() as i32;
+                        let in_chunk_x_range = ((start + wiggle).clamp(chunk_start_x, chunk_end_x)
+                            - chunk_start_x)
+                            ..((end + wiggle).clamp(chunk_start_x, chunk_end_x) - chunk_start_x);
+                        for in_chunk_x in in_chunk_x_range {
+                            chunk.set_pixel(
+                                (in_chunk_y as usize) * CHUNK_SIZE + (in_chunk_x as usize),
+                                air_pixel,
+                            );
+                        }
                     }
-                }
-                (chunk_coord, chunk.to_chunk_data())
-            })
-            .collect();
+                    (chunk_coord, chunk.to_chunk_data())
+                })
+                .collect()
+        });
         for entry in chunk_storage.into_iter() {
             self.chunk_storage.insert(entry.0, entry.1);
         }
@@ -1340,114 +1524,117 @@ impl WorldManager {
         ]
         .into_iter();
         let r = r as u64 * r as u64;
-        let chunk_storage: Vec<(ChunkCoord, ChunkData, bool)> = (min_cx..=max_cx)
-            .into_par_iter()
-            .flat_map(|chunk_x| {
-                (min_cy..=max_cy)
-                    .into_par_iter()
-                    .map(move |chunk_y| (chunk_x, chunk_y))
-            })
-            .filter(|&(chunk_x, chunk_y)| {
-                let chunk_start_x = chunk_x * CHUNK_SIZE as i32;
-                let chunk_start_y = chunk_y * CHUNK_SIZE as i32;
-                close_check
-                    || [
-                        (chunk_start_x, chunk_start_y),
-                        (
-                            chunk_start_x + CHUNK_SIZE as i32 - 1,
-                            chunk_start_y + CHUNK_SIZE as i32 - 1,
-                        ),
-                        (chunk_start_x + CHUNK_SIZE as i32 - 1, chunk_start_y),
-                        (chunk_start_x, chunk_start_y + CHUNK_SIZE as i32 - 1),
-                    ]
-                    .iter()
-                    .any(|(cx, cy)| {
-                        let dcx = cx - x;
-                        let dcy = cy - y;
-                        let m = ((dcx.unsigned_abs() as u64 * dmx.unsigned_abs() as u64
-                            + dcy.unsigned_abs() as u64 * dmy.unsigned_abs() as u64)
-                            as f64
-                            * dm2)
-                            .clamp(0.0, 1.0);
-                        let dx = dcx.abs_diff((m * dmx as f64) as i32) as u64;
-                        let dy = dcy.abs_diff((m * dmy as f64) as i32) as u64;
-                        dx * dx + dy * dy <= r
-                    })
-                    || {
-                        let (end_x, end_y) = (
-                            chunk_start_x + CHUNK_SIZE as i32 - 1,
-                            chunk_start_y + CHUNK_SIZE as i32 - 1,
-                        );
-                        iter_check.clone().any(|(x, y)| {
-                            end_x >= x && x >= chunk_start_x && end_y >= y && y >= chunk_start_y
+        self.chunk_storage.load_area(min_cx, max_cx, min_cy, max_cy);
+        let chunk_storage: Vec<(ChunkCoord, ChunkData, bool)> = self.pool.install(|| {
+            (min_cx..=max_cx)
+                .into_par_iter()
+                .flat_map(|chunk_x| {
+                    (min_cy..=max_cy)
+                        .into_par_iter()
+                        .map(move |chunk_y| (chunk_x, chunk_y))
+                })
+                .filter(|&(chunk_x, chunk_y)| {
+                    let chunk_start_x = chunk_x * CHUNK_SIZE as i32;
+                    let chunk_start_y = chunk_y * CHUNK_SIZE as i32;
+                    close_check
+                        || [
+                            (chunk_start_x, chunk_start_y),
+                            (
+                                chunk_start_x + CHUNK_SIZE as i32 - 1,
+                                chunk_start_y + CHUNK_SIZE as i32 - 1,
+                            ),
+                            (chunk_start_x + CHUNK_SIZE as i32 - 1, chunk_start_y),
+                            (chunk_start_x, chunk_start_y + CHUNK_SIZE as i32 - 1),
+                        ]
+                        .iter()
+                        .any(|(cx, cy)| {
+                            let dcx = cx - x;
+                            let dcy = cy - y;
+                            let m = ((dcx.unsigned_abs() as u64 * dmx.unsigned_abs() as u64
+                                + dcy.unsigned_abs() as u64 * dmy.unsigned_abs() as u64)
+                                as f64
+                                * dm2)
+                                .clamp(0.0, 1.0);
+                            let dx = dcx.abs_diff((m * dmx as f64) as i32) as u64;
+                            let dy = dcy.abs_diff((m * dmy as f64) as i32) as u64;
+                            dx * dx + dy * dy <= r
                         })
-                    }
-            })
-            .filter_map(|(chunk_x, chunk_y)| {
-                let chunk_start_x = chunk_x * CHUNK_SIZE as i32;
-                let chunk_start_y = chunk_y * CHUNK_SIZE as i32;
-                let mut chunk = Chunk::default();
-                let coord = ChunkCoord(chunk_x, chunk_y);
-                let mut del = false;
-                let mut no_info = false;
-                if self.is_storage_recent.contains(&coord) {
-                    if let Some(chunk_encoded) = self.chunk_storage.get(&coord) {
+                        || {
+                            let (end_x, end_y) = (
+                                chunk_start_x + CHUNK_SIZE as i32 - 1,
+                                chunk_start_y + CHUNK_SIZE as i32 - 1,
+                            );
+                            iter_check.clone().any(|(x, y)| {
+                                end_x >= x && x >= chunk_start_x && end_y >= y && y >= chunk_start_y
+                            })
+                        }
+                })
+                .filter_map(|(chunk_x, chunk_y)| {
+                    let chunk_start_x = chunk_x * CHUNK_SIZE as i32;
+                    let chunk_start_y = chunk_y * CHUNK_SIZE as i32;
+                    let mut chunk = Chunk::default();
+                    let coord = ChunkCoord(chunk_x, chunk_y);
+                    let mut del = false;
+                    let mut no_info = false;
+                    if self.is_storage_recent.contains(&coord) {
+                        if let Some(chunk_encoded) = self.chunk_storage.get(&coord) {
+                            chunk_encoded.apply_to_chunk(&mut chunk)
+                        }
+                    } else if let Some(chunk_encoded) = self
+                        .outbound_model
+                        .get_chunk_data(coord)
+                        .or(self.inbound_model.get_chunk_data(coord))
+                    {
+                        del = true;
+                        chunk_encoded.apply_to_chunk(&mut chunk);
+                    } else if let Some(chunk_encoded) = self.chunk_storage.get(&coord) {
                         chunk_encoded.apply_to_chunk(&mut chunk)
+                    } else if !self.nice_terraforming {
+                        return None;
+                    } else {
+                        no_info = true;
                     }
-                } else if let Some(chunk_encoded) = self
-                    .outbound_model
-                    .get_chunk_data(coord)
-                    .or(self.inbound_model.get_chunk_data(coord))
-                {
-                    del = true;
-                    chunk_encoded.apply_to_chunk(&mut chunk);
-                } else if let Some(chunk_encoded) = self.chunk_storage.get(&coord) {
-                    chunk_encoded.apply_to_chunk(&mut chunk)
-                } else if !self.nice_terraforming {
-                    return None;
-                } else {
-                    no_info = true;
-                }
-                let mut changed = false;
-                let mut rng = rng();
-                for icx in 0..CHUNK_SIZE as i32 {
-                    let cx = chunk_start_x + icx;
-                    let dcx = cx - x;
-                    let dx2 = dcx.unsigned_abs() as u64 * dmx.unsigned_abs() as u64;
-                    for icy in 0..CHUNK_SIZE as i32 {
-                        let cy = chunk_start_y + icy;
-                        let dcy = cy - y;
-                        let m = ((dx2 + dcy.unsigned_abs() as u64 * dmy.unsigned_abs() as u64)
-                            as f64
-                            * dm2)
-                            .clamp(0.0, 1.0);
-                        let dx = dcx.abs_diff((m * dmx as f64) as i32) as u64;
-                        let dy = dcy.abs_diff((m * dmy as f64) as i32) as u64;
-                        if dx * dx + dy * dy <= r {
-                            let px = icy as usize * CHUNK_SIZE + icx as usize;
-                            if (no_info
-                                || chunk.pixel(px).flags == PixelFlags::Unknown
-                                || self
-                                    .materials
-                                    .get(&chunk.pixel(px).material)
-                                    .map(|(_, _, cell, _)| cell.can_remove(true, false))
-                                    .unwrap_or(true))
-                                && (chance == 100
-                                    || rng.random_bool((chance as f64 / 100.0).clamp(0.0, 1.0)))
-                            {
-                                changed = true;
-                                chunk.set_pixel(px, air_pixel);
+                    let mut changed = false;
+                    let mut rng = chunk_rng(coord, x, y, r);
+                    for icx in 0..CHUNK_SIZE as i32 {
+                        let cx = chunk_start_x + icx;
+                        let dcx = cx - x;
+                        let dx2 = dcx.unsigned_abs() as u64 * dmx.unsigned_abs() as u64;
+                        for icy in 0..CHUNK_SIZE as i32 {
+                            let cy = chunk_start_y + icy;
+                            let dcy = cy - y;
+                            let m = ((dx2 + dcy.unsigned_abs() as u64 * dmy.unsigned_abs() as u64)
+                                as f64
+                                * dm2)
+                                .clamp(0.0, 1.0);
+                            let dx = dcx.abs_diff((m * dmx as f64) as i32) as u64;
+                            let dy = dcy.abs_diff((m * dmy as f64) as i32) as u64;
+                            if dx * dx + dy * dy <= r {
+                                let px = icy as usize * CHUNK_SIZE + icx as usize;
+                                if (no_info
+                                    || chunk.pixel(px).flags == PixelFlags::Unknown
+                                    || self
+                                        .materials
+                                        .get(&chunk.pixel(px).material)
+                                        .map(|(_, _, cell, _)| cell.can_remove(true, false))
+                                        .unwrap_or(true))
+                                    && (chance == 100
+                                        || rng.random_bool((chance as f64 / 100.0).clamp(0.0, 1.0)))
+                                {
+                                    changed = true;
+                                    chunk.set_pixel(px, air_pixel);
+                                }
                             }
                         }
                     }
-                }
-                if changed {
-                    Some((coord, chunk.to_chunk_data(), del))
-                } else {
-                    None
-                }
-            })
-            .collect();
+                    if changed {
+                        Some((coord, chunk.to_chunk_data(), del))
+                    } else {
+                        None
+                    }
+                })
+                .collect()
+        });
         for entry in chunk_storage.into_iter() {
             self.chunk_storage.insert(entry.0, entry.1);
             if entry.2 {
@@ -1484,75 +1671,78 @@ impl WorldManager {
         );
         let do_continue = mat.unwrap_or(0) != 0;
         let rs = r as u64 * r as u64;
-        let chunk_storage: Vec<(ChunkCoord, ChunkData, bool)> = (min_cx..=max_cx)
-            .into_par_iter()
-            .flat_map(|chunk_x| {
-                (min_cy..=max_cy)
-                    .into_par_iter()
-                    .map(move |chunk_y| (chunk_x, chunk_y))
-            })
-            .filter(|&(chunk_x, chunk_y)| {
-                r <= CHUNK_SIZE as i32 || min_dist(x, y, chunkx, chunky, chunk_x, chunk_y) <= rs
-            })
-            .filter_map(|(chunk_x, chunk_y)| {
-                let coord = ChunkCoord(chunk_x, chunk_y);
-                let chunk_start_x = chunk_x * CHUNK_SIZE as i32;
-                let chunk_start_y = chunk_y * CHUNK_SIZE as i32;
-                let mut chunk = Chunk::default();
-                let mut del = false;
-                let mut no_info = false;
-                if self.is_storage_recent.contains(&coord) {
-                    if let Some(chunk_encoded) = self.chunk_storage.get(&coord) {
+        self.chunk_storage.load_area(min_cx, max_cx, min_cy, max_cy);
+        let chunk_storage: Vec<(ChunkCoord, ChunkData, bool)> = self.pool.install(|| {
+            (min_cx..=max_cx)
+                .into_par_iter()
+                .flat_map(|chunk_x| {
+                    (min_cy..=max_cy)
+                        .into_par_iter()
+                        .map(move |chunk_y| (chunk_x, chunk_y))
+                })
+                .filter(|&(chunk_x, chunk_y)| {
+                    r <= CHUNK_SIZE as i32 || min_dist(x, y, chunkx, chunky, chunk_x, chunk_y) <= rs
+                })
+                .filter_map(|(chunk_x, chunk_y)| {
+                    let coord = ChunkCoord(chunk_x, chunk_y);
+                    let chunk_start_x = chunk_x * CHUNK_SIZE as i32;
+                    let chunk_start_y = chunk_y * CHUNK_SIZE as i32;
+                    let mut chunk = Chunk::default();
+                    let mut del = false;
+                    let mut no_info = false;
+                    if self.is_storage_recent.contains(&coord) {
+                        if let Some(chunk_encoded) = self.chunk_storage.get(&coord) {
+                            chunk_encoded.apply_to_chunk(&mut chunk)
+                        }
+                    } else if let Some(chunk_encoded) = self
+                        .outbound_model
+                        .get_chunk_data(coord)
+                        .or(self.inbound_model.get_chunk_data(coord))
+                    {
+                        del = true;
+                        chunk_encoded.apply_to_chunk(&mut chunk);
+                    } else if let Some(chunk_encoded) = self.chunk_storage.get(&coord) {
                         chunk_encoded.apply_to_chunk(&mut chunk)
+                    } else if do_continue || !self.nice_terraforming {
+                        return None;
+                    } else {
+                        no_info = true;
                     }
-                } else if let Some(chunk_encoded) = self
-                    .outbound_model
-                    .get_chunk_data(coord)
-                    .or(self.inbound_model.get_chunk_data(coord))
-                {
-                    del = true;
-                    chunk_encoded.apply_to_chunk(&mut chunk);
-                } else if let Some(chunk_encoded) = self.chunk_storage.get(&coord) {
-                    chunk_encoded.apply_to_chunk(&mut chunk)
-                } else if do_continue || !self.nice_terraforming {
-                    return None;
-                } else {
-                    no_info = true;
-                }
-                let mut changed = false;
-                let mut rng = rng();
-                for icx in 0..CHUNK_SIZE as i32 {
-                    let cx = chunk_start_x + icx;
-                    let dx = cx.abs_diff(x) as u64;
-                    let dd = dx * dx;
-                    for icy in 0..CHUNK_SIZE as i32 {
-                        let cy = chunk_start_y + icy;
-                        let dy = cy.abs_diff(y) as u64;
-                        if dd + dy * dy <= rs {
-                            let px = icy as usize * CHUNK_SIZE + icx as usize;
-                            if (no_info
-                                || chunk.pixel(px).flags == PixelFlags::Unknown
-                                || self
-                                    .materials
-                                    .get(&chunk.pixel(px).material)
-                                    .map(|(_, _, cell, _)| cell.can_remove(true, false))
-                                    .unwrap_or(true))
-                                && (chance == 100
-                                    || rng.random_bool((chance as f64 / 100.0).clamp(0.0, 1.0)))
-                            {
-                                changed = true;
-                                chunk.set_pixel(px, air_pixel);
+                    let mut changed = false;
+                    let mut rng = chunk_rng(coord, x, y, r as u64);
+                    for icx in 0..CHUNK_SIZE as i32 {
+                        let cx = chunk_start_x + icx;
+                        let dx = cx.abs_diff(x) as u64;
+                        let dd = dx * dx;
+                        for icy in 0..CHUNK_SIZE as i32 {
+                            let cy = chunk_start_y + icy;
+                            let dy = cy.abs_diff(y) as u64;
+                            if dd + dy * dy <= rs {
+                                let px = icy as usize * CHUNK_SIZE + icx as usize;
+                                if (no_info
+                                    || chunk.pixel(px).flags == PixelFlags::Unknown
+                                    || self
+                                        .materials
+                                        .get(&chunk.pixel(px).material)
+                                        .map(|(_, _, cell, _)| cell.can_remove(true, false))
+                                        .unwrap_or(true))
+                                    && (chance == 100
+                                        || rng.random_bool((chance as f64 / 100.0).clamp(0.0, 1.0)))
+                                {
+                                    changed = true;
+                                    chunk.set_pixel(px, air_pixel);
+                                }
                             }
                         }
                     }
-                }
-                if changed {
-                    Some((coord, chunk.to_chunk_data(), del))
-                } else {
-                    None
-                }
-            })
-            .collect();
+                    if changed {
+                        Some((coord, chunk.to_chunk_data(), del))
+                    } else {
+                        None
+                    }
+                })
+                .collect()
+        });
         for entry in chunk_storage.into_iter() {
             self.chunk_storage.insert(entry.0, entry.1);
             if entry.2 {
@@ -1560,6 +1750,76 @@ impl WorldManager {
             }
         }
     }
+    /// How much terrain is on the straight line between two points, used to muffle voice.
+    /// Every solid pixel counts as 0.5 to 1 depending on its durability, which is capped at `max_durability`.
+    /// Liquids count as half a pixel, and chunks that aren't known count as empty.
+    pub(crate) fn material_between(
+        &self,
+        from: (i32, i32),
+        to: (i32, i32),
+        max_durability: u32,
+    ) -> f32 {
+        let (dx, dy) = (to.0 - from.0, to.1 - from.1);
+        let steps = dx.abs().max(dy.abs());
+        let max_durability = max_durability.max(1);
+        let mut working_chunk = Chunk::default();
+        let mut current = None;
+        let mut amount = 0.0;
+        for i in 0..=steps {
+            let (x, y) = if steps == 0 {
+                from
+            } else {
+                (from.0 + dx * i / steps, from.1 + dy * i / steps)
+            };
+            let co = ChunkCoord(
+                x.div_euclid(CHUNK_SIZE as i32),
+                y.div_euclid(CHUNK_SIZE as i32),
+            );
+            if current != Some(co) {
+                current = Some(co);
+                if !self.read_chunk(co, &mut working_chunk) {
+                    working_chunk = Chunk::default();
+                }
+            }
+            let px = y.rem_euclid(CHUNK_SIZE as i32) as usize * CHUNK_SIZE
+                + x.rem_euclid(CHUNK_SIZE as i32) as usize;
+            let pixel = working_chunk.pixel(px);
+            if let Some((durability, _, cell_type, _)) = self.materials.get(&pixel.material) {
+                let solidity =
+                    0.5 + 0.5 * (*durability).min(max_durability) as f32 / max_durability as f32;
+                amount += match cell_type {
+                    CellType::Solid
+                    | CellType::Liquid(LiquidType::Static)
+                    | CellType::Liquid(LiquidType::Sand) => solidity,
+                    CellType::Liquid(LiquidType::Liquid) => 0.5,
+                    _ => 0.0,
+                };
+            }
+        }
+        amount
+    }
+
+    /// Copies the most recent known state of a chunk into `chunk`, false if it isn't known.
+    fn read_chunk(&self, co: ChunkCoord, chunk: &mut Chunk) -> bool {
+        if self.is_storage_recent.contains(&co) {
+            let Some(c) = self.chunk_storage.get(&co) else {
+                return false;
+            };
+            c.apply_to_chunk(chunk);
+        } else if let Some(c) = self
+            .outbound_model
+            .get_chunk_data(co)
+            .or(self.inbound_model.get_chunk_data(co))
+        {
+            c.apply_to_chunk(chunk);
+        } else if let Some(c) = self.chunk_storage.get(&co) {
+            c.apply_to_chunk(chunk);
+        } else {
+            return false;
+        }
+        true
+    }
+
     #[allow(clippy::too_many_arguments)]
     #[allow(clippy::type_complexity)]
     fn do_ray(
@@ -1717,10 +1977,20 @@ impl WorldManager {
 
     #[allow(clippy::type_complexity)]
     pub(crate) fn cut_through_world_explosion(&mut self, exp: Vec<ExplosionData>) {
-        let resres: Vec<((Vec<ExRet>, Vec<u64>), ExplosionData)> = exp
-            .into_par_iter()
-            .map(|ex| (self.interior_iter(ex), ex))
-            .collect();
+        for ex in &exp {
+            let r = ex.r as i32;
+            self.chunk_storage.load_area(
+                (ex.x - r).div_euclid(CHUNK_SIZE as i32),
+                (ex.x + r).div_euclid(CHUNK_SIZE as i32),
+                (ex.y - r).div_euclid(CHUNK_SIZE as i32),
+                (ex.y + r).div_euclid(CHUNK_SIZE as i32),
+            );
+        }
+        let resres: Vec<((Vec<ExRet>, Vec<u64>), ExplosionData)> = self.pool.install(|| {
+            exp.into_par_iter()
+                .map(|ex| (self.interior_iter(ex), ex))
+                .collect()
+        });
         for ((chunks, raydata), ex) in resres {
             let m = self.explosion_heap.len();
             self.explosion_heap.push(ex);
@@ -1730,10 +2000,8 @@ impl WorldManager {
                 if let Some(entry) = entry.loaded {
                     if entry.3 {
                         self.chunk_storage.insert(entry.0, entry.1);
-                    } else {
-                        self.chunk_storage
-                            .entry(entry.0)
-                            .and_modify(|c| c.apply_delta(entry.1));
+                    } else if let Some(c) = self.chunk_storage.get_mut(entry.0) {
+                        c.apply_delta(entry.1);
                     }
                     if entry.2 {
                         self.is_storage_recent.insert(entry.0);
@@ -1928,7 +2196,7 @@ impl WorldManager {
                 let chunk_start_y = chunk_y * CHUNK_SIZE as i32;
                 let mut all = true;
                 let mut none = true;
-                let mut rng = rng();
+                let mut rng = chunk_rng(coord, x, y, r);
                 let atan: Vec<f32> = compute_atans(chunk_start_x, chunk_start_y, rays as f32, x, y);
                 for icx in 0..CHUNK_SIZE as i32 {
                     let cx = chunk_start_x + icx;
@@ -2038,6 +2306,7 @@ impl WorldManager {
 
     #[allow(clippy::type_complexity)]
     pub(crate) fn cut_through_world_explosion_chunk(&mut self, chunk: ChunkCoord) {
+        self.chunk_storage.load(chunk);
         let exp: Vec<(usize, (usize, usize, ExTarget, u64))> = self
             .explosion_pointer
             .remove(&chunk)
@@ -2045,23 +2314,22 @@ impl WorldManager {
             .iter()
             .map(|i| (*i, self.explosion_data[*i]))
             .collect();
-        let data: Vec<(usize, Option<(Option<u64>, ExTarget, u64)>)> = exp
-            .into_par_iter()
-            .map(|ex| {
-                (
-                    ex.0,
-                    self.interior_iter_chunk(self.explosion_heap[ex.1.0], ex.1, chunk, 0.5),
-                )
-            })
-            .collect();
+        let data: Vec<(usize, Option<(Option<u64>, ExTarget, u64)>)> = self.pool.install(|| {
+            exp.into_par_iter()
+                .map(|ex| {
+                    (
+                        ex.0,
+                        self.interior_iter_chunk(self.explosion_heap[ex.1.0], ex.1, chunk, 0.5),
+                    )
+                })
+                .collect()
+        });
         let ch = self.explosion_chunk(&data, chunk);
         if let Some(ch) = ch {
             if ch.1 {
                 self.chunk_storage.insert(chunk, ch.0);
-            } else {
-                self.chunk_storage
-                    .entry(chunk)
-                    .and_modify(|c| c.apply_delta(ch.0));
+            } else if let Some(c) = self.chunk_storage.get_mut(chunk) {
+                c.apply_delta(ch.0);
             }
             self.is_storage_recent.insert(chunk);
         }
@@ -2102,7 +2370,9 @@ impl WorldManager {
         for (key, a, b) in data {
             grouped.entry(key).or_default().push((a, b));
         }
-        let data: Vec<(usize, Vec<(usize, u64)>)> = grouped.into_iter().collect();
+        // Order matters, first explosion that reaches a pixel decides what it becomes.
+        let mut data: Vec<(usize, Vec<(usize, u64)>)> = grouped.into_iter().collect();
+        data.sort_unstable_by_key(|(i, _)| *i);
         let air_pixel = Pixel {
             flags: PixelFlags::Normal,
             material: 0,
@@ -2114,7 +2384,12 @@ impl WorldManager {
         let chunk_start_y = coord.1 * CHUNK_SIZE as i32;
         let mut all = true;
         let mut none = true;
-        let mut rng = rng();
+        let mut rng = chunk_rng(
+            coord,
+            0,
+            0,
+            data.first().map(|(i, _)| *i as u64).unwrap_or(0),
+        );
         let data: Vec<(usize, &Vec<(usize, u64)>, Vec<f32>)> = data
             .iter()
             .map(|(i, data)| {
@@ -2386,6 +2661,34 @@ fn create_image(chunk: ChunkData, materials: &FxHashMap<u16, u32>) -> RgbaImage
     }
     image
 }
+/// Rng for randomized cuts, seeded from the chunk and the cut itself, so that the result
+/// doesn't depend on which worker ended up processing the chunk.
+fn chunk_rng(coord: ChunkCoord, x: i32, y: i32, r: u64) -> StdRng {
+    let seed = [
+        coord.0 as u32 as u64,
+        coord.1 as u32 as u64,
+        x as u32 as u64,
+        y as u32 as u64,
+        r,
+    ]
+    .into_iter()
+    .fold(0x9E37_79B9_7F4A_7C15, |acc: u64, v| {
+        (acc ^ v)
+            .wrapping_mul(0x0000_0100_0000_01B3)
+            .rotate_left(29)
+    });
+    StdRng::seed_from_u64(seed)
+}
+
+/// Pool that terrain cuts and explosions are processed on, 0 threads means rayon's default.
+fn world_pool(threads: usize) -> ThreadPool {
+    ThreadPoolBuilder::new()
+        .num_threads(threads)
+        .thread_name(|i| format!("world-worker-{i}"))
+        .build()
+        .expect("can create world worker pool")
+}
+
 #[allow(clippy::too_many_arguments)]
 fn should_process_chunk(
     chunk_x: i32,
@@ -2413,13 +2716,14 @@ fn should_process_chunk(
 impl Drop for WorldManager {
     fn drop(&mut self) {
         if self.is_host {
-            self.save_state.save(&self.chunk_storage);
+            self.save_state.save(&self.chunk_storage.to_map());
             info!("Saved chunk data");
         }
     }
 }
 impl SaveStateEntry for FxHashMap<ChunkCoord, ChunkData> {
     const FILENAME: &'static str = "world_chunks";
+    const VERSION: u32 = 1;
 }
 pub(crate) struct ExRet {
     loaded: Option<(ChunkCoord, ChunkData, bool, bool)>,
@@ -2664,8 +2968,8 @@ fn test_explosion_img_big() {
     iter.shuffle(&mut rng);
     for (i, j) in iter {
         let c = ChunkCoord(i, j);
-        if let std::collections::hash_map::Entry::Vacant(e) = world.chunk_storage.entry(c) {
-            e.insert(_brickwork.clone());
+        if !world.chunk_storage.contains_key(&c) {
+            world.chunk_storage.insert(c, _brickwork.clone());
         }
         if world.explosion_pointer.contains_key(&c) {
             world.cut_through_world_explosion_chunk(c)
@@ -2736,12 +3040,15 @@ fn test_explosion_img_big_br() {
     iter.shuffle(&mut rng);
     for (i, j) in iter {
         let c = ChunkCoord(i, j);
-        if let std::collections::hash_map::Entry::Vacant(e) = world.chunk_storage.entry(c) {
-            e.insert(if rng.random_bool(0.2) {
-                _brickwork.clone()
-            } else {
-                _dirt.clone()
-            });
+        if !world.chunk_storage.contains_key(&c) {
+            world.chunk_storage.insert(
+                c,
+                if rng.random_bool(0.2) {
+                    _brickwork.clone()
+                } else {
+                    _dirt.clone()
+                },
+            );
         }
         if world.explosion_pointer.contains_key(&c) {
             world.cut_through_world_explosion_chunk(c)
@@ -2795,12 +3102,15 @@ fn test_explosion_img_big_empty() {
     iter.shuffle(&mut rng);
     for (i, j) in iter {
         let c = ChunkCoord(i, j);
-        if let std::collections::hash_map::Entry::Vacant(e) = world.chunk_storage.entry(c) {
-            e.insert(if rng.random_bool(0.2) {
-                _brickwork.clone()
-            } else {
-                _dirt.clone()
-            });
+        if !world.chunk_storage.contains_key(&c) {
+            world.chunk_storage.insert(
+                c,
+                if rng.random_bool(0.2) {
+                    _brickwork.clone()
+                } else {
+                    _dirt.clone()
+                },
+            );
         }
         if world.explosion_pointer.contains_key(&c) {
             world.cut_through_world_explosion_chunk(c)
@@ -3108,12 +3418,15 @@ fn test_explosion_img_big_many() {
     iter.shuffle(&mut rng);
     for (i, j) in iter {
         let c = ChunkCoord(i, j);
-        if let std::collections::hash_map::Entry::Vacant(e) = world.chunk_storage.entry(c) {
-            e.insert(if rng.random_bool(0.2) {
-                _brickwork.clone()
-            } else {
-                _dirt.clone()
-            });
+        if !world.chunk_storage.contains_key(&c) {
+            world.chunk_storage.insert(
+                c,
+                if rng.random_bool(0.2) {
+                    _brickwork.clone()
+                } else {
+                    _dirt.clone()
+                },
+            );
         }
         if world.explosion_pointer.contains_key(&c) {
             world.cut_through_world_explosion_chunk(c)
@@ -3130,6 +3443,8 @@ fn test_explosion_img_big_many() {
 use crate::net::LiquidType;
 use crate::net::world::world_model::chunk::PixelFlags;
 #[cfg(test)]
+use rand::rng;
+#[cfg(test)]
 use rand::seq::SliceRandom;
 #[cfg(test)]
 use serial_test::serial;
@@ -3230,12 +3545,15 @@ fn test_explosion_perf_unloaded() {
         iter.shuffle(&mut rng);
         for (i, j) in iter {
             let c = ChunkCoord(i, j);
-            if let std::collections::hash_map::Entry::Vacant(e) = world.chunk_storage.entry(c) {
-                e.insert(if rng.random_bool(0.2) {
-                    _brickwork.clone()
-                } else {
-                    _dirt.clone()
-                });
+            if !world.chunk_storage.contains_key(&c) {
+                world.chunk_storage.insert(
+                    c,
+                    if rng.random_bool(0.2) {
+                        _brickwork.clone()
+                    } else {
+                        _dirt.clone()
+                    },
+                );
             }
             if world.explosion_pointer.contains_key(&c) {
                 let timer = std::time::Instant::now();
@@ -3402,3 +3720,125 @@ fn test_cut_perf() {
     }
     println!("total micros: {}", total / iters);
 }
+
+#[cfg(test)]
+fn perf_world(threads: usize) -> WorldManager {
+    let (mut world, _, _, _, _) =
+        WorldManager::new(true, OmniPeerId(0), SaveState::new("/tmp/ew_tmp_save"));
+    world.pool = world_pool(threads);
+    world
+        .materials
+        .insert(0, (0, 100, CellType::Liquid(LiquidType::Liquid), 0));
+    world
+        .materials
+        .insert(1, (6, 2000, CellType::Liquid(LiquidType::Static), 0));
+    world
+        .materials
+        .insert(2, (14, 1_000_000, CellType::Liquid(LiquidType::Static), 0));
+    let w = 16;
+    for i in -w..w {
+        for j in -w..w {
+            world.chunk_storage.insert(
+                ChunkCoord(i, j),
+                ChunkData::new(1 + (i + j).rem_euclid(2) as u16),
+            );
+        }
+    }
+    world
+}
+
+#[cfg(test)]
+fn run_perf_cuts(world: &mut WorldManager) {
+    world.cut_through_world_explosion(vec![
+        ExplosionData::new(0, 0, 380, 14, 2_000_000_000, true, true, 1, 50),
+        ExplosionData::new(256, -128, 200, 14, 2_000_000_000, true, true, 1, 50),
+    ]);
+    world.cut_through_world_line(-512, -512, 512, 512, 48, 50);
+    world.cut_through_world_circle(-256, 256, 300, None, 50);
+}
+
+#[cfg(test)]
+#[test]
+#[serial]
+fn test_cuts_deterministic() {
+    let mut single = perf_world(1);
+    run_perf_cuts(&mut single);
+    let mut multi = perf_world(0);
+    run_perf_cuts(&mut multi);
+    let single = single.chunk_storage.to_map();
+    let multi = multi.chunk_storage.to_map();
+    assert_eq!(single.len(), multi.len());
+    for (coord, data) in single {
+        assert_eq!(
+            bitcode::encode(&data),
+            bitcode::encode(&multi[&coord]),
+            "{coord:?} differs"
+        );
+    }
+}
+
+#[cfg(test)]
+#[test]
+#[serial]
+fn test_cuts_perf_threads() {
+    let iters = 4;
+    let time = |threads| {
+        let mut total = 0;
+        for _ in 0..iters {
+            let mut world = perf_world(threads);
+            let timer = std::time::Instant::now();
+            run_perf_cuts(&mut world);
+            total += timer.elapsed().as_micros();
+        }
+        total / iters
+    };
+    let single = time(1);
+    let multi = time(0);
+    println!(
+        "single thread micros: {single}, pool micros: {multi}, speedup: {:.2}",
+        single as f64 / multi.max(1) as f64
+    );
+}
+
+#[cfg(test)]
+#[test]
+#[serial]
+fn test_load_reports_follow_policy() {
+    let (mut world, _, _, _, _) =
+        WorldManager::new(false, OmniPeerId(1), SaveState::new("/tmp/ew_tmp_save"));
+    let reports = |world: &mut WorldManager| {
+        for _ in 0..LOAD_REPORT_INTERVAL * 2 {
+            world.add_end(0, &[0, 0, 0, 0, 0, 0]);
+        }
+        world
+            .get_emitted_msgs()
+            .into_iter()
+            .filter(|msg| matches!(msg.msg, WorldNetMessage::ReportLoad { .. }))
+            .count()
+    };
+    assert_eq!(reports(&mut world), 0);
+    world.set_authority_policy(Box::new(authority_policy::LoadAware::default()));
+    assert_eq!(reports(&mut world), 2);
+}
+
+#[cfg(test)]
+#[test]
+#[serial]
+fn test_material_between() {
+    let (mut world, _, _, _, _) =
+        WorldManager::new(true, OmniPeerId(0), SaveState::new("/tmp/ew_tmp_save"));
+    world.materials.insert(0, (0, 0, CellType::Gas, 0));
+    world.materials.insert(1, (14, 2000, CellType::Solid, 0));
+    world
+        .chunk_storage
+        .insert(ChunkCoord(0, 0), ChunkData::new(0));
+    world
+        .chunk_storage
+        .insert(ChunkCoord(1, 0), ChunkData::new(1));
+    let size = CHUNK_SIZE as i32;
+    assert_eq!(world.material_between((10, 10), (size - 1, 10), 14), 0.0);
+    assert_eq!(world.material_between((10, 10), (size + 9, 10), 14), 10.0);
+    assert_eq!(world.material_between((size + 9, 10), (10, 10), 28), 7.5);
+    // Unknown chunks are treated as empty.
+    assert_eq!(world.material_between((10, -10), (10, -100), 14), 0.0);
+}
@@ -0,0 +1,128 @@
+use bitcode::{Decode, Encode};
+
+/// Load of a peer, reported to the host every once in a while.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Encode, Decode)]
+pub(crate) struct PeerLoad {
+    /// Average time between world updates from Noita, in milliseconds.
+    pub frame_time_ms: f32,
+    /// Average upload, in bytes per second.
+    pub upload_bps: u32,
+    /// Amount of chunks this peer is an authority of.
+    pub authority_chunks: u32,
+}
+
+/// Decides how load affects chunk authority.
+/// Priorities work the same way as everywhere else: lower value wins.
+pub(crate) trait AuthorityPolicy: Send + Sync {
+    /// Priority to use for a chunk, given the one Noita asked for and our own load.
+    /// Worse priority makes listeners with better one take the chunk over.
+    fn priority(&self, requested: u8, load: &PeerLoad) -> u8;
+    /// Host won't transfer chunks to peers that are overloaded.
+    fn is_overloaded(&self, load: &PeerLoad) -> bool;
+    /// Peers only measure and report their load if the policy looks at it.
+    fn wants_load_reports(&self) -> bool {
+        true
+    }
+}
+
+/// Only distance matters, load is ignored.
+pub(crate) struct DistanceOnly;
+
+impl AuthorityPolicy for DistanceOnly {
+    fn priority(&self, requested: u8, _load: &PeerLoad) -> u8 {
+        requested
+    }
+
+    fn is_overloaded(&self, _load: &PeerLoad) -> bool {
+        false
+    }
+
+    fn wants_load_reports(&self) -> bool {
+        false
+    }
+}
+
+/// Penalizes peers that are slow, are running out of upload or own too many chunks.
+pub(crate) struct LoadAware {
+    pub max_frame_time_ms: f32,
+    pub max_upload_bps: u32,
+    pub max_authority_chunks: u32,
+    /// Priority penalty when a peer is twice over any of the limits.
+    pub max_penalty: u8,
+}
+
+impl Default for LoadAware {
+    fn default() -> Self {
+        Self {
+            max_frame_time_ms: 25.0,
+            max_upload_bps: 512 * 1024,
+            max_authority_chunks: 64,
+            max_penalty: 8,
+        }
+    }
+}
+
+impl LoadAware {
+    /// How far over the limits the peer is, 1.0 being exactly at the limit.
+    fn load_ratio(&self, load: &PeerLoad) -> f32 {
+        let frame = load.frame_time_ms / self.max_frame_time_ms.max(1.0);
+        let upload = load.upload_bps as f32 / self.max_upload_bps.max(1) as f32;
+        let chunks = load.authority_chunks as f32 / self.max_authority_chunks.max(1) as f32;
+        frame.max(upload).max(chunks)
+    }
+}
+
+impl AuthorityPolicy for LoadAware {
+    fn priority(&self, requested: u8, load: &PeerLoad) -> u8 {
+        let over = (self.load_ratio(load) - 1.0).clamp(0.0, 1.0);
+        requested.saturating_add((over * self.max_penalty as f32).round() as u8)
+    }
+
+    fn is_overloaded(&self, load: &PeerLoad) -> bool {
+        self.load_ratio(load) > 1.0
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn load_aware_penalty() {
+        let policy = LoadAware::default();
+        let idle = PeerLoad {
+            frame_time_ms: 16.0,
+            upload_bps: 1024,
+            authority_chunks: 4,
+        };
+        assert_eq!(policy.priority(3, &idle), 3);
+        assert!(!policy.is_overloaded(&idle));
+
+        let slow = PeerLoad {
+            frame_time_ms: 50.0,
+            ..idle
+        };
+        assert_eq!(policy.priority(3, &slow), 3 + policy.max_penalty);
+        assert!(policy.is_overloaded(&slow));
+
+        let busy = PeerLoad {
+            authority_chunks: 96,
+            ..idle
+        };
+        assert_eq!(policy.priority(3, &busy), 3 + policy.max_penalty / 2);
+        assert_eq!(policy.priority(u8::MAX, &busy), u8::MAX);
+    }
+
+    #[test]
+    fn distance_only_ignores_load() {
+        let load = PeerLoad {
+            frame_time_ms: 1000.0,
+            upload_bps: u32::MAX,
+            authority_chunks: u32::MAX,
+        };
+        assert_eq!(DistanceOnly.priority(5, &load), 5);
+        assert!(!DistanceOnly.is_overloaded(&load));
+        assert!(!DistanceOnly.wants_load_reports());
+        assert!(LoadAware::default().wants_load_reports());
+    }
+}
@@ -0,0 +1,236 @@
+use std::{env, fs, path::PathBuf};
+
+use rustc_hash::{FxHashMap, FxHashSet};
+use tracing::{info, warn};
+
+use super::world_model::{ChunkCoord, ChunkData};
+
+/// How many chunks are kept in memory by default, can be overriden with `NP_CHUNK_STORAGE_CAP`.
+const DEFAULT_MAX_RESIDENT: usize = 4096;
+
+/// Host-side storage for chunks that aren't under any authority.
+///
+/// Keeps at most `max_resident` chunks in memory, least recently used ones are spilled
+/// to a compressed on-disk cache and loaded back on demand.
+/// Spilled chunks still count as stored, but `get` only sees resident ones, as it's used from
+/// parallel code. Call `load` or `load_area` beforehand to make sure the chunks are in memory.
+pub(crate) struct ChunkStorage {
+    /// Resident chunks, along with the tick they were last used in.
+    resident: FxHashMap<ChunkCoord, (ChunkData, u64)>,
+    spilled: FxHashSet<ChunkCoord>,
+    tick: u64,
+    max_resident: usize,
+    cache_dir: PathBuf,
+}
+
+impl ChunkStorage {
+    pub(crate) fn new(cache_dir: PathBuf, chunks: FxHashMap<ChunkCoord, ChunkData>) -> Self {
+        // Spilled chunks are only valid for the session that created them.
+        fs::remove_dir_all(&cache_dir).ok();
+        let max_resident = env::var("NP_CHUNK_STORAGE_CAP")
+            .ok()
+            .and_then(|x| x.parse().ok())
+            .unwrap_or(DEFAULT_MAX_RESIDENT);
+        Self {
+            resident: chunks.into_iter().map(|(k, v)| (k, (v, 0))).collect(),
+            spilled: Default::default(),
+            tick: 0,
+            max_resident,
+            cache_dir,
+        }
+    }
+
+    /// Returns the chunk if it's currently in memory.
+    pub(crate) fn get(&self, coord: &ChunkCoord) -> Option<&ChunkData> {
+        self.resident.get(coord).map(|(data, _)| data)
+    }
+
+    pub(crate) fn get_mut(&mut self, coord: ChunkCoord) -> Option<&mut ChunkData> {
+        self.load(coord);
+        let tick = self.tick;
+        self.resident.get_mut(&coord).map(|(data, last_used)| {
+            *last_used = tick;
+            data
+        })
+    }
+
+    pub(crate) fn contains_key(&self, coord: &ChunkCoord) -> bool {
+        self.resident.contains_key(coord) || self.spilled.contains(coord)
+    }
+
+    pub(crate) fn insert(&mut self, coord: ChunkCoord, data: ChunkData) {
+        if self.spilled.remove(&coord) {
+            fs::remove_file(self.path_for(coord)).ok();
+        }
+        self.resident.insert(coord, (data, self.tick));
+    }
+
+    /// Iterates over resident chunks.
+    pub(crate) fn iter(&self) -> impl Iterator<Item = (&ChunkCoord, &ChunkData)> {
+        self.resident.iter().map(|(coord, (data, _))| (coord, data))
+    }
+
+    /// Makes sure the chunk is in memory, if we have it at all.
+    pub(crate) fn load(&mut self, coord: ChunkCoord) -> bool {
+        if let Some((_, last_used)) = self.resident.get_mut(&coord) {
+            *last_used = self.tick;
+            return true;
+        }
+        if !self.spilled.remove(&coord) {
+            return false;
+        }
+        match self.read_spilled(coord) {
+            Some(data) => {
+                fs::remove_file(self.path_for(coord)).ok();
+                self.resident.insert(coord, (data, self.tick));
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Makes sure every chunk we have in the given (inclusive) range of chunk coords is in memory.
+    pub(crate) fn load_area(&mut self, min_cx: i32, max_cx: i32, min_cy: i32, max_cy: i32) {
+        let in_area = |coord: &ChunkCoord| {
+            (min_cx..=max_cx).contains(&coord.0) && (min_cy..=max_cy).contains(&coord.1)
+        };
+        let tick = self.tick;
+        for (_, (_, last_used)) in self.resident.iter_mut().filter(|(coord, _)| in_area(coord)) {
+            *last_used = tick;
+        }
+        let to_load: Vec<ChunkCoord> = self.spilled.iter().copied().filter(in_area).collect();
+        for coord in to_load {
+            self.load(coord);
+        }
+    }
+
+    /// Spills least recently used chunks to disk if there are too many in memory.
+    /// Should be called once per update.
+    pub(crate) fn evict(&mut self) {
+        self.tick += 1;
+        if self.resident.len() <= self.max_resident {
+            return;
+        }
+        // Spill a bit more than needed, so that we don't end up doing this every update.
+        let target = self.max_resident * 3 / 4;
+        let mut by_age: Vec<(u64, ChunkCoord)> = self
+            .resident
+            .iter()
+            .map(|(coord, (_, last_used))| (*last_used, *coord))
+            .collect();
+        by_age.sort_unstable_by_key(|(last_used, _)| *last_used);
+        if let Err(err) = fs::create_dir_all(&self.cache_dir) {
+            warn!("Could not create chunk cache dir: {err}");
+            return;
+        }
+        let to_spill = self.resident.len() - target;
+        let mut spilled = 0;
+        for (_, coord) in by_age.into_iter().take(to_spill) {
+            let Some((data, _)) = self.resident.get(&coord) else {
+                continue;
+            };
+            let encoded = bitcode::encode(data);
+            let compressed = lz4_flex::compress_prepend_size(&encoded);
+            if let Err(err) = fs::write(self.path_for(coord), compressed) {
+                warn!("Could not spill chunk {coord:?}: {err}");
+                break;
+            }
+            self.resident.remove(&coord);
+            self.spilled.insert(coord);
+            spilled += 1;
+        }
+        info!(
+            "Spilled {spilled} chunks to disk, {} in memory, {} on disk",
+            self.resident.len(),
+            self.spilled.len()
+        );
+    }
+
+    /// Collects every chunk, including spilled ones, without changing what's resident.
+    pub(crate) fn to_map(&self) -> FxHashMap<ChunkCoord, ChunkData> {
+        let mut map: FxHashMap<ChunkCoord, ChunkData> = self
+            .resident
+            .iter()
+            .map(|(coord, (data, _))| (*coord, data.clone()))
+            .collect();
+        for coord in &self.spilled {
+            if let Some(data) = self.read_spilled(*coord) {
+                map.insert(*coord, data);
+            }
+        }
+        map
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.resident.clear();
+        self.spilled.clear();
+        fs::remove_dir_all(&self.cache_dir).ok();
+    }
+
+    fn read_spilled(&self, coord: ChunkCoord) -> Option<ChunkData> {
+        let data = fs::read(self.path_for(coord))
+            .inspect_err(|err| warn!("Could not read spilled chunk {coord:?}: {err}"))
+            .ok()?;
+        let data = lz4_flex::decompress_size_prepended(&data)
+            .inspect_err(|err| warn!("Could not decompress spilled chunk {coord:?}: {err}"))
+            .ok()?;
+        bitcode::decode(&data)
+            .inspect_err(|err| warn!("Could not decode spilled chunk {coord:?}: {err}"))
+            .ok()
+    }
+
+    fn path_for(&self, coord: ChunkCoord) -> PathBuf {
+        self.cache_dir.join(format!("{}_{}.bit", coord.0, coord.1))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn spill_and_reload() {
+        let dir = std::env::temp_dir().join("ew_chunk_storage_test");
+        let mut storage = ChunkStorage::new(dir, Default::default());
+        storage.max_resident = 4;
+        for i in 0..8 {
+            storage.insert(ChunkCoord(i, 0), ChunkData::new(i as u16 + 1));
+            storage.evict();
+        }
+        assert!(storage.resident.len() <= 4);
+        assert_eq!(storage.resident.len() + storage.spilled.len(), 8);
+        assert!(storage.get(&ChunkCoord(0, 0)).is_none());
+        assert!(storage.contains_key(&ChunkCoord(0, 0)));
+
+        storage.load_area(0, 1, 0, 0);
+        assert!(storage.get(&ChunkCoord(0, 0)).is_some());
+        assert!(storage.get(&ChunkCoord(1, 0)).is_some());
+        assert_eq!(storage.to_map().len(), 8);
+
+        storage.clear();
+        assert!(!storage.contains_key(&ChunkCoord(0, 0)));
+    }
+
+    #[test]
+    fn spilled_data_round_trips() {
+        let dir = std::env::temp_dir().join("ew_chunk_storage_round_trip_test");
+        let mut storage = ChunkStorage::new(dir, Default::default());
+        storage.max_resident = 4;
+        let chunks: Vec<ChunkData> = (0..8).map(|_| ChunkData::make_random()).collect();
+        for (i, data) in chunks.iter().enumerate() {
+            storage.insert(ChunkCoord(i as i32, 0), data.clone());
+        }
+        storage.evict();
+        assert!(storage.resident.len() <= 4);
+
+        for (i, data) in chunks.iter().enumerate() {
+            let coord = ChunkCoord(i as i32, 0);
+            assert!(storage.contains_key(&coord));
+            assert!(storage.load(coord));
+            let loaded = storage.get(&coord).unwrap();
+            assert_eq!(bitcode::encode(loaded), bitcode::encode(data));
+        }
+        assert!(storage.spilled.is_empty());
+        storage.clear();
+    }
+}
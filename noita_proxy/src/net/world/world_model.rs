@@ -34,7 +34,70 @@ pub(crate) struct ChunkData {
 #[derive(Debug, Encode, Decode, Clone)]
 pub(crate) struct ChunkDelta {
     pub chunk_coord: ChunkCoord,
-    runs: Arc<Vec<PixelRun<Option<CompactPixel>>>>,
+    pixels: Arc<DeltaPixels>,
+}
+
+/// Which encoding to use for pixels of a `ChunkDelta`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum DeltaEncoding {
+    Runs,
+    Masked,
+    /// Pick whichever of the above is expected to be smaller for this chunk.
+    Smallest,
+}
+
+#[derive(Debug, Encode, Decode, Clone)]
+enum DeltaPixels {
+    /// Runs of pixels, `None` for unchanged ones. Good for large changed areas.
+    Runs(Vec<PixelRun<Option<CompactPixel>>>),
+    /// Changed mask followed by changed pixels. Good for scattered changes, like liquids moving around.
+    Masked {
+        /// Alternating lengths of unchanged and changed spans, starting with an unchanged one.
+        mask: Vec<u16>,
+        /// Changed pixels, in order.
+        pixels: Vec<CompactPixel>,
+    },
+}
+
+impl DeltaPixels {
+    fn for_each_changed(&self, mut f: impl FnMut(usize, CompactPixel)) {
+        let mut offset = 0;
+        match self {
+            DeltaPixels::Runs(runs) => {
+                for run in runs {
+                    if let Some(pixel) = run.data {
+                        for _ in 0..run.length {
+                            f(offset, pixel);
+                            offset += 1;
+                        }
+                    } else {
+                        offset += run.length as usize
+                    }
+                }
+            }
+            DeltaPixels::Masked { mask, pixels } => {
+                let mut pixels = pixels.iter();
+                for (i, len) in mask.iter().enumerate() {
+                    if i % 2 == 1 {
+                        for (_, pixel) in (0..*len).zip(pixels.by_ref()) {
+                            f(offset, *pixel);
+                            offset += 1;
+                        }
+                    } else {
+                        offset += *len as usize
+                    }
+                }
+            }
+        }
+    }
+
+    /// Rough size estimate, close enough to compare encodings with each other.
+    fn estimated_size(&self) -> usize {
+        match self {
+            DeltaPixels::Runs(runs) => runs.len() * 3,
+            DeltaPixels::Masked { mask, pixels } => mask.len() * 2 + pixels.len() * 2,
+        }
+    }
 }
 
 impl ChunkData {
@@ -216,31 +279,60 @@ impl WorldModel {
     pub(crate) fn apply_chunk_delta(&mut self, delta: &ChunkDelta) {
         self.updated_chunks.insert(delta.chunk_coord);
         let chunk = self.chunks.entry(delta.chunk_coord).or_default();
-        let mut offset = 0;
-        for run in delta.runs.iter() {
-            if let Some(pixel) = run.data {
-                for _ in 0..run.length {
-                    chunk.set_compact_pixel(offset, pixel);
-                    offset += 1;
-                }
-            } else {
-                offset += run.length as usize
-            }
-        }
+        delta
+            .pixels
+            .for_each_changed(|offset, pixel| chunk.set_compact_pixel(offset, pixel));
     }
 
     pub(crate) fn get_chunk_delta(
         &self,
         chunk_coord: ChunkCoord,
         ignore_changed: bool,
+        encoding: DeltaEncoding,
     ) -> Option<ChunkDelta> {
         let chunk = self.chunks.get(&chunk_coord)?;
+        let use_runs = encoding != DeltaEncoding::Masked;
+        let use_mask = encoding != DeltaEncoding::Runs;
         let mut runner = PixelRunner::new();
+        let mut mask = Vec::new();
+        let mut pixels = Vec::new();
+        let mut span_changed = false;
+        let mut span_len = 0;
         for i in 0..CHUNK_SIZE * CHUNK_SIZE {
-            runner.put_pixel((ignore_changed || chunk.changed(i)).then(|| chunk.compact_pixel(i)))
+            let changed = ignore_changed || chunk.changed(i);
+            if use_runs {
+                runner.put_pixel(changed.then(|| chunk.compact_pixel(i)))
+            }
+            if use_mask {
+                if changed != span_changed {
+                    mask.push(span_len);
+                    span_changed = changed;
+                    span_len = 0;
+                }
+                span_len += 1;
+                if changed {
+                    pixels.push(chunk.compact_pixel(i))
+                }
+            }
         }
-        let runs = runner.build().into();
-        Some(ChunkDelta { chunk_coord, runs })
+        mask.push(span_len);
+        let runs = DeltaPixels::Runs(runner.build());
+        let masked = DeltaPixels::Masked { mask, pixels };
+        let pixels = match encoding {
+            DeltaEncoding::Runs => runs,
+            DeltaEncoding::Masked => masked,
+            DeltaEncoding::Smallest => {
+                if masked.estimated_size() < runs.estimated_size() {
+                    masked
+                } else {
+                    runs
+                }
+            }
+        };
+        Some(ChunkDelta {
+            chunk_coord,
+            pixels: pixels.into(),
+        })
     }
 
     pub fn updated_chunks(&self) -> &FxHashSet<ChunkCoord> {
@@ -278,3 +370,77 @@ impl WorldModel {
         self.updated_chunks.remove(&chunk);
     }
 }
+
+#[cfg(test)]
+fn delta_test_models(changes: usize, spread: usize) -> (WorldModel, WorldModel) {
+    use rand::Rng;
+    let coord = ChunkCoord(0, 0);
+    let base = ChunkData::make_random();
+    let mut source = WorldModel::default();
+    let mut target = WorldModel::default();
+    source.apply_chunk_data(coord, &base);
+    target.apply_chunk_data(coord, &base);
+    source.reset_change_tracking();
+    let mut rng = rand::rng();
+    let chunk = source.chunks.get_mut(&coord).unwrap();
+    let start = rng.random_range(0..CHUNK_SIZE * CHUNK_SIZE - spread);
+    for _ in 0..changes {
+        chunk.set_pixel(
+            start + rng.random_range(0..spread),
+            Pixel {
+                flags: PixelFlags::Fluid,
+                material: rng.random_range(0..512),
+            },
+        );
+    }
+    (source, target)
+}
+
+#[cfg(test)]
+#[test]
+fn test_delta_round_trip() {
+    let coord = ChunkCoord(0, 0);
+    for (changes, spread) in [(0, 1), (1, 1), (200, 16000), (4000, 2000), (16384, 16383)] {
+        for encoding in [
+            DeltaEncoding::Runs,
+            DeltaEncoding::Masked,
+            DeltaEncoding::Smallest,
+        ] {
+            let (source, mut target) = delta_test_models(changes, spread);
+            let delta = source.get_chunk_delta(coord, false, encoding).unwrap();
+            let delta: ChunkDelta = bitcode::decode(&bitcode::encode(&delta)).unwrap();
+            target.apply_chunk_delta(&delta);
+            let (source, target) = (&source.chunks[&coord], &target.chunks[&coord]);
+            for i in 0..CHUNK_SIZE * CHUNK_SIZE {
+                assert_eq!(source.pixel(i), target.pixel(i), "{encoding:?} at {i}");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn test_delta_size() {
+    let coord = ChunkCoord(0, 0);
+    for (name, changes, spread) in [
+        ("scattered", 300, 16000),
+        ("clustered", 2000, 3000),
+        ("dense", 16000, 16383),
+    ] {
+        let (source, _) = delta_test_models(changes, spread);
+        let sizes = [
+            DeltaEncoding::Runs,
+            DeltaEncoding::Masked,
+            DeltaEncoding::Smallest,
+        ]
+        .map(|encoding| {
+            let delta = source.get_chunk_delta(coord, false, encoding).unwrap();
+            bitcode::encode(&delta).len()
+        });
+        println!(
+            "{name}: runs {} bytes, masked {} bytes, smallest {} bytes",
+            sizes[0], sizes[1], sizes[2]
+        );
+        assert!(sizes[2] <= sizes[0].min(sizes[1]), "{name}: {sizes:?}");
+    }
+}
@@ -9,6 +9,7 @@ pub const DEFAULT_PROXY_LOG_NAME: &str = "ew_log.txt";
 pub const DEFAULT_PROXY_LOG_OLD_NAME: &str = "ew_log_old.txt";
 pub const DEFAULT_PROXY_SETTINGS_NAME: &str = "proxy.ron";
 pub const DEFAULT_PROXY_SAVE_STATE_NAME: &str = "save_state"; // this is a dir
+pub const DEFAULT_VOICE_RECORDINGS_NAME: &str = "voice_recordings"; // this is a dir, next to save_state
 
 pub const STEAM_COMPATDATA_NOITA_SAVE: &str =
     "compatdata/881100/pfx/drive_c/users/steamuser/AppData/LocalLow/Nolla_Games_Noita";
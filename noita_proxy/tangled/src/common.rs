@@ -1,6 +1,6 @@
 //! Various common public types.
 
-use std::fmt::Display;
+use std::{fmt::Display, time::Duration};
 
 use bitcode::{Decode, Encode};
 
@@ -59,6 +59,18 @@ pub struct Message {
     pub data: Vec<u8>,
 }
 
+/// State of a direct connection to a peer, as measured by QUIC.
+/// Counters are the ones since stats were last taken.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LinkStats {
+    /// Current round trip time.
+    pub rtt: Duration,
+    pub sent_packets: u64,
+    pub lost_packets: u64,
+    /// How many times the congestion controller had to slow sending down.
+    pub congestion_events: u64,
+}
+
 /// Current peer state
 #[derive(Default, Clone, Copy, Debug, PartialEq, Eq)]
 pub enum PeerState {
@@ -15,8 +15,8 @@ use crossbeam::{
 };
 use dashmap::DashMap;
 use quinn::{
-    ClientConfig, ConnectError, Connecting, ConnectionError, Endpoint, Incoming, RecvStream,
-    ServerConfig, TransportConfig,
+    ClientConfig, ConnectError, Connecting, Connection, ConnectionError, Endpoint, Incoming,
+    RecvStream, ServerConfig, TransportConfig,
     crypto::rustls::QuicClientConfig,
     rustls::{
         self,
@@ -29,7 +29,7 @@ use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tracing::{debug, error, info, trace, warn};
 
 use crate::{
-    common::{Destination, NetworkEvent, PeerId, PeerState, Reliability, Settings},
+    common::{Destination, LinkStats, NetworkEvent, PeerId, PeerState, Reliability, Settings},
     helpers::SkipServerVerification,
 };
 
@@ -57,6 +57,40 @@ enum DirectConnectionError {
     DecodeError,
 }
 
+/// Connection to a peer, along with counters of it at the time stats were last taken.
+pub(crate) struct MeasuredConnection {
+    connection: Connection,
+    taken: LinkStats,
+}
+
+impl MeasuredConnection {
+    fn new(connection: Connection) -> Self {
+        Self {
+            connection,
+            taken: LinkStats::default(),
+        }
+    }
+
+    /// Stats since the last time they were taken.
+    pub(crate) fn take_stats(&mut self) -> LinkStats {
+        let path = self.connection.stats().path;
+        let total = LinkStats {
+            rtt: path.rtt,
+            sent_packets: path.sent_packets,
+            lost_packets: path.lost_packets,
+            congestion_events: path.congestion_events,
+        };
+        let since = LinkStats {
+            rtt: total.rtt,
+            sent_packets: total.sent_packets - self.taken.sent_packets,
+            lost_packets: total.lost_packets - self.taken.lost_packets,
+            congestion_events: total.congestion_events - self.taken.congestion_events,
+        };
+        self.taken = total;
+        since
+    }
+}
+
 struct DirectPeer {
     my_id: PeerId,
     remote_id: PeerId,
@@ -102,6 +136,9 @@ impl DirectPeer {
             .map_err(|_err| DirectConnectionError::InitialExchangeFailed)?;
 
         let (send_stream, recv_stream) = connection.open_bi().await?;
+        shared
+            .connections
+            .insert(assigned_peer_id, MeasuredConnection::new(connection));
         tokio::spawn(Self::recv_task(shared, recv_stream, assigned_peer_id));
         debug!("Server: spawned recv task");
 
@@ -128,6 +165,9 @@ impl DirectPeer {
         debug!("Got peer id {peer_id}");
 
         let (send_stream, recv_stream) = connection.accept_bi().await?;
+        shared
+            .connections
+            .insert(PeerId::HOST, MeasuredConnection::new(connection));
         tokio::spawn(Self::recv_task(shared, recv_stream, PeerId::HOST));
         debug!("Client: spawned recv task");
 
@@ -172,6 +212,8 @@ pub(crate) struct Shared {
     pub remote_peers: DashMap<PeerId, RemotePeer>,
     pub host_addr: Option<SocketAddr>,
     pub my_id: AtomicCell<Option<PeerId>>,
+    /// Direct connections: to every client on host, only to host on clients.
+    pub connections: DashMap<PeerId, MeasuredConnection>,
     // ConnectionManager-specific stuff
     direct_peers: DashMap<PeerId, DirectPeer>,
     internal_incoming_messages_s: tokio::sync::mpsc::Sender<(PeerId, InternalMessage)>,
@@ -208,6 +250,7 @@ impl ConnectionManager {
             peer_state: Default::default(),
             remote_peers: Default::default(),
             my_id: AtomicCell::new(is_server.then_some(PeerId(0))),
+            connections: DashMap::default(),
             direct_peers: DashMap::default(),
             internal_incoming_messages_s,
             internal_events_s,
@@ -380,6 +423,7 @@ impl ConnectionManager {
             InternalEvent::Disconnected(peer_id) => {
                 debug!("Peer {} disconnected", peer_id);
                 self.shared.direct_peers.remove(&peer_id);
+                self.shared.connections.remove(&peer_id);
                 self.shared
                     .inbound_channel
                     .0
@@ -124,6 +124,16 @@ impl Peer {
         self.shared.peer_state.load()
     }
 
+    /// Stats of direct connections since this was last called.
+    /// Host is connected to every peer directly, clients are only connected to host.
+    pub fn link_stats(&self) -> Vec<(PeerId, LinkStats)> {
+        self.shared
+            .connections
+            .iter_mut()
+            .map(|mut item| (*item.key(), item.value_mut().take_stats()))
+            .collect()
+    }
+
     /// Iterate over connected peers, returning ther `PeerId`.
     pub fn iter_peer_ids(&self) -> impl Iterator<Item = PeerId> + '_ {
         self.shared
@@ -187,6 +197,30 @@ mod test {
         assert_eq!(host.shared.remote_peers.len(), 1);
     }
 
+    #[test_log::test(tokio::test)]
+    async fn test_link_stats() {
+        let settings: Option<Settings> = Some(Default::default());
+        let addr = "127.0.0.1:56008".parse().unwrap();
+        let host = Peer::host(addr, settings.clone()).unwrap();
+        let peer = Peer::connect(addr, settings.clone()).unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        peer.send(PeerId(0), vec![1, 2, 3], Reliability::Unreliable)
+            .unwrap();
+        tokio::time::sleep(Duration::from_millis(10)).await;
+
+        let host_links = host.link_stats();
+        assert_eq!(host_links.len(), 1);
+        assert_eq!(host_links[0].0, PeerId(1));
+        let peer_links = peer.link_stats();
+        assert_eq!(peer_links.len(), 1);
+        let (id, stats) = peer_links[0];
+        assert_eq!(id, PeerId(0));
+        assert!(stats.sent_packets > 0);
+        assert_eq!(stats.lost_packets, 0);
+        // Counters start over once taken.
+        assert!(peer.link_stats()[0].1.sent_packets < stats.sent_packets);
+    }
+
     #[test_log::test(tokio::test)]
     async fn test_broadcast() {
         let settings: Option<Settings> = Some(Default::default());
@@ -37,6 +37,13 @@ function module.on_world_initialized()
     ewext.set_log(log)
     cache = ModSettingGet("quant.ew.cache") or false
     ewext.set_cache(cache)
+    ewext.set_smoothing(
+        ctx.proxy_opt.entity_smoothing or false,
+        ctx.proxy_opt.entity_smoothing_delay or 50,
+        ctx.proxy_opt.entity_max_extrapolation or 150,
+        ctx.proxy_opt.entity_smoothing_exclude or ""
+    )
+    ewext.set_compact_updates(ctx.proxy_opt.compact_entity_updates or false)
 end
 
 local function oh_another_world_state(entity)
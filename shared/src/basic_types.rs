@@ -84,7 +84,7 @@ impl Sub<WorldVec> for WorldVec {
 
 impl WorldVec {
     pub fn dot(self, other: WorldVec) -> f32 {
-        (self.x as f32) * (other.y as f32) + (self.y as f32) * (other.x as f32)
+        (self.x as f32) * (other.x as f32) + (self.y as f32) * (other.y as f32)
     }
     pub fn hypot(self) -> f32 {
         f32::hypot(self.x as f32, self.y as f32)
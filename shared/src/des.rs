@@ -2,6 +2,9 @@ use std::num::NonZero;
 
 use crate::{GameEffectData, GameEffectEnum, PeerId, SpawnOnce, WorldPos};
 use bitcode::{Decode, Encode};
+use compact::{CompactUpdates, UpdateEncoding};
+
+pub mod compact;
 
 pub const REQUEST_AUTHORITY_RADIUS: i32 = 512;
 pub const TRANSFER_RADIUS: f32 = 512.0;
@@ -80,10 +83,14 @@ pub enum ProxyToDes {
     GotAuthoritys(Vec<FullEntityData>),
     RemoveEntities(PeerId),
     DeleteEntity(NonZero<isize>),
+    /// Someone else has authority over entity, local copy has to go.
+    LostAuthority(Gid),
 }
 #[derive(Debug, Encode, Decode, Clone)]
 pub struct InterestRequest {
     pub pos: WorldPos,
+    /// Which encoding the peer wants entity updates in.
+    pub encoding: UpdateEncoding,
     //pub radius: i32,
 }
 
@@ -201,6 +208,7 @@ pub enum RemoteDes {
     Reset,
     InterestRequest(InterestRequest),
     EntityUpdate(Vec<EntityUpdate>),
+    CompactEntityUpdate(CompactUpdates),
     EntityInit(Vec<EntityInit>),
     ExitedInterest,
     Projectiles(Vec<ProjectileFired>),
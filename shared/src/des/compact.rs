@@ -0,0 +1,605 @@
+//! Compact encoding of [`EntityUpdate`]s.
+//!
+//! Coordinates are sent as fixed-point values relative to their chunk, positions and limbs are sent
+//! as deltas from the last value sent for the same entity, and synced var names are only sent once.
+//! Encoder and decoder keep state, so every stream needs reliable, ordered delivery and a pair of them.
+
+use std::collections::HashMap;
+use std::f32::consts::TAU;
+
+use bitcode::{Decode, Encode};
+
+use super::{EntityUpdate, Lid, PhysBodyInfo};
+
+/// Size of a chunk fixed-point coordinates are relative to, in pixels.
+const CHUNK_SIZE: i32 = 512;
+/// Positions are rounded to 1/POS_SCALE of a pixel.
+const POS_SCALE: f32 = 8.0;
+/// Velocities are rounded to 1/VEL_SCALE of a pixel per second.
+const VEL_SCALE: f32 = 8.0;
+/// Physics body velocities are in physics units, and much smaller.
+const PHYS_VEL_SCALE: f32 = 64.0;
+const ANGULAR_VEL_SCALE: f32 = 256.0;
+
+/// Which encoding a peer accepts entity updates in.
+#[derive(Debug, Encode, Decode, Clone, Copy, PartialEq, Eq, Default)]
+pub enum UpdateEncoding {
+    #[default]
+    Plain,
+    Compact,
+}
+
+/// Fixed-point world position.
+#[derive(Debug, Encode, Decode, Clone, Copy, PartialEq)]
+pub struct FixedPos {
+    pub chunk: (i32, i32),
+    /// Offset from the chunk corner, in 1/POS_SCALE of a pixel.
+    pub offset: (u16, u16),
+}
+
+impl FixedPos {
+    fn from_fixed(x: i32, y: i32) -> Self {
+        let size = CHUNK_SIZE * POS_SCALE as i32;
+        Self {
+            chunk: (x.div_euclid(size), y.div_euclid(size)),
+            offset: (x.rem_euclid(size) as u16, y.rem_euclid(size) as u16),
+        }
+    }
+
+    fn to_fixed(self) -> (i32, i32) {
+        let size = CHUNK_SIZE * POS_SCALE as i32;
+        (
+            self.chunk.0 * size + self.offset.0 as i32,
+            self.chunk.1 * size + self.offset.1 as i32,
+        )
+    }
+}
+
+#[derive(Debug, Encode, Decode, Clone, Copy, PartialEq)]
+pub struct CompactPhys {
+    pub pos: FixedPos,
+    pub angle: u16,
+    pub vx: i16,
+    pub vy: i16,
+    pub av: i16,
+}
+
+/// Synced var name, sent in full only the first time.
+#[derive(Debug, Encode, Decode, Clone, PartialEq)]
+pub enum VarName {
+    /// Gets the next free index.
+    New(String),
+    Known(u16),
+}
+
+#[derive(Debug, Encode, Decode, Clone, PartialEq)]
+pub struct CompactVar {
+    pub name: VarName,
+    pub value_string: String,
+    pub value_int: i32,
+    pub value_float: f32,
+    pub value_bool: bool,
+}
+
+#[derive(Debug, Encode, Decode, Clone)]
+pub enum CompactUpdate {
+    CurrentEntity(Lid),
+    Position(FixedPos),
+    /// Change since the last position sent for this entity, in 1/POS_SCALE of a pixel.
+    PositionDelta(i16, i16),
+    Velocity(i16, i16),
+    Rotation(u16),
+    PhysInfo(Vec<Option<CompactPhys>>),
+    Limbs(Vec<FixedPos>),
+    /// Change of every limb since the last limbs sent for this entity.
+    LimbsDelta(Vec<(i16, i16)>),
+    SyncedVar(Vec<CompactVar>),
+    /// Everything that doesn't have a compact form.
+    Plain(EntityUpdate),
+}
+
+#[derive(Debug, Encode, Decode, Clone)]
+pub struct CompactUpdates {
+    /// Changes every time the encoder is reset, so that the decoder knows to reset as well.
+    pub generation: u32,
+    pub updates: Vec<CompactUpdate>,
+}
+
+fn to_fixed(v: f32) -> i32 {
+    (v * POS_SCALE).round() as i32
+}
+
+fn from_fixed(v: i32) -> f32 {
+    v as f32 / POS_SCALE
+}
+
+fn quantize_i16(v: f32, scale: f32) -> i16 {
+    (v * scale).round().clamp(i16::MIN as f32, i16::MAX as f32) as i16
+}
+
+fn quantize_angle(angle: f32) -> u16 {
+    ((angle.rem_euclid(TAU) / TAU * 65536.0).round() as u32 % 65536) as u16
+}
+
+fn dequantize_angle(angle: u16) -> f32 {
+    angle as f32 / 65536.0 * TAU
+}
+
+fn delta(from: (i32, i32), to: (i32, i32)) -> Option<(i16, i16)> {
+    Some((
+        i16::try_from(to.0 - from.0).ok()?,
+        i16::try_from(to.1 - from.1).ok()?,
+    ))
+}
+
+fn apply_delta(from: (i32, i32), (dx, dy): (i16, i16)) -> (i32, i32) {
+    (from.0 + dx as i32, from.1 + dy as i32)
+}
+
+/// What was last sent for an entity, deltas are relative to this.
+#[derive(Default)]
+struct Baseline {
+    pos: Option<(i32, i32)>,
+    limbs: Vec<(i32, i32)>,
+}
+
+#[derive(Default)]
+pub struct UpdateEncoder {
+    generation: u32,
+    baselines: HashMap<Lid, Baseline>,
+    var_names: HashMap<String, u16>,
+}
+
+impl UpdateEncoder {
+    /// Forgets everything sent so far, the next message starts a new generation.
+    pub fn reset(&mut self) {
+        self.generation = self.generation.wrapping_add(1);
+        self.baselines.clear();
+        self.var_names.clear();
+    }
+
+    pub fn encode(&mut self, updates: &[EntityUpdate]) -> CompactUpdates {
+        let mut current = None;
+        let updates = updates
+            .iter()
+            .map(|update| match update {
+                EntityUpdate::CurrentEntity(lid) => {
+                    current = Some(*lid);
+                    CompactUpdate::CurrentEntity(*lid)
+                }
+                EntityUpdate::SetPosition(x, y) => {
+                    let pos = (to_fixed(*x), to_fixed(*y));
+                    let baseline = self.baseline(current);
+                    let last = baseline.pos.replace(pos);
+                    match last.and_then(|last| delta(last, pos)) {
+                        Some((dx, dy)) => CompactUpdate::PositionDelta(dx, dy),
+                        None => CompactUpdate::Position(FixedPos::from_fixed(pos.0, pos.1)),
+                    }
+                }
+                EntityUpdate::SetVelocity(vx, vy) => CompactUpdate::Velocity(
+                    quantize_i16(*vx, VEL_SCALE),
+                    quantize_i16(*vy, VEL_SCALE),
+                ),
+                EntityUpdate::SetRotation(r) => CompactUpdate::Rotation(quantize_angle(*r)),
+                EntityUpdate::SetPhysInfo(phys) => CompactUpdate::PhysInfo(
+                    phys.iter()
+                        .map(|body| {
+                            body.map(|body| CompactPhys {
+                                pos: FixedPos::from_fixed(to_fixed(body.x), to_fixed(body.y)),
+                                angle: quantize_angle(body.angle),
+                                vx: quantize_i16(body.vx, PHYS_VEL_SCALE),
+                                vy: quantize_i16(body.vy, PHYS_VEL_SCALE),
+                                av: quantize_i16(body.av, ANGULAR_VEL_SCALE),
+                            })
+                        })
+                        .collect(),
+                ),
+                EntityUpdate::SetLimbs(limbs) => {
+                    let limbs: Vec<(i32, i32)> = limbs
+                        .iter()
+                        .map(|(x, y)| (to_fixed(*x), to_fixed(*y)))
+                        .collect();
+                    let baseline = self.baseline(current);
+                    let deltas = (baseline.limbs.len() == limbs.len())
+                        .then(|| {
+                            baseline
+                                .limbs
+                                .iter()
+                                .zip(&limbs)
+                                .map(|(from, to)| delta(*from, *to))
+                                .collect::<Option<Vec<_>>>()
+                        })
+                        .flatten();
+                    let update = match deltas {
+                        Some(deltas) => CompactUpdate::LimbsDelta(deltas),
+                        None => CompactUpdate::Limbs(
+                            limbs
+                                .iter()
+                                .map(|(x, y)| FixedPos::from_fixed(*x, *y))
+                                .collect(),
+                        ),
+                    };
+                    baseline.limbs = limbs;
+                    update
+                }
+                EntityUpdate::SetSyncedVar(vars) => CompactUpdate::SyncedVar(
+                    vars.iter()
+                        .map(|(name, value_string, value_int, value_float, value_bool)| {
+                            let next = self.var_names.len() as u16;
+                            let name = match self.var_names.get(name) {
+                                Some(index) => VarName::Known(*index),
+                                None => {
+                                    self.var_names.insert(name.clone(), next);
+                                    VarName::New(name.clone())
+                                }
+                            };
+                            CompactVar {
+                                name,
+                                value_string: value_string.clone(),
+                                value_int: *value_int,
+                                value_float: *value_float,
+                                value_bool: *value_bool,
+                            }
+                        })
+                        .collect(),
+                ),
+                EntityUpdate::RemoveEntity(lid)
+                | EntityUpdate::LocalizeEntity(lid, _)
+                | EntityUpdate::KillEntity { lid, .. } => {
+                    self.baselines.remove(lid);
+                    if current == Some(*lid) {
+                        current = None;
+                    }
+                    CompactUpdate::Plain(update.clone())
+                }
+                other => CompactUpdate::Plain(other.clone()),
+            })
+            .collect();
+        CompactUpdates {
+            generation: self.generation,
+            updates,
+        }
+    }
+
+    /// Updates without a current entity are ignored on the other side, their baseline doesn't matter.
+    fn baseline(&mut self, current: Option<Lid>) -> &mut Baseline {
+        self.baselines
+            .entry(current.unwrap_or(Lid(u32::MAX)))
+            .or_default()
+    }
+}
+
+#[derive(Default)]
+pub struct UpdateDecoder {
+    generation: Option<u32>,
+    baselines: HashMap<Lid, Baseline>,
+    var_names: Vec<String>,
+}
+
+impl UpdateDecoder {
+    pub fn reset(&mut self) {
+        self.generation = None;
+        self.baselines.clear();
+        self.var_names.clear();
+    }
+
+    /// Updates that refer to something the decoder doesn't know about are dropped,
+    /// that only happens when the other side reset without the decoder noticing.
+    pub fn decode(&mut self, compact: CompactUpdates) -> Vec<EntityUpdate> {
+        if self.generation != Some(compact.generation) {
+            self.reset();
+            self.generation = Some(compact.generation);
+        }
+        let mut current = None;
+        compact
+            .updates
+            .into_iter()
+            .filter_map(|update| {
+                Some(match update {
+                    CompactUpdate::CurrentEntity(lid) => {
+                        current = Some(lid);
+                        EntityUpdate::CurrentEntity(lid)
+                    }
+                    CompactUpdate::Position(pos) => {
+                        let pos = pos.to_fixed();
+                        self.baseline(current).pos = Some(pos);
+                        EntityUpdate::SetPosition(from_fixed(pos.0), from_fixed(pos.1))
+                    }
+                    CompactUpdate::PositionDelta(dx, dy) => {
+                        let baseline = self.baseline(current);
+                        let pos = apply_delta(baseline.pos?, (dx, dy));
+                        baseline.pos = Some(pos);
+                        EntityUpdate::SetPosition(from_fixed(pos.0), from_fixed(pos.1))
+                    }
+                    CompactUpdate::Velocity(vx, vy) => {
+                        EntityUpdate::SetVelocity(vx as f32 / VEL_SCALE, vy as f32 / VEL_SCALE)
+                    }
+                    CompactUpdate::Rotation(r) => EntityUpdate::SetRotation(dequantize_angle(r)),
+                    CompactUpdate::PhysInfo(phys) => EntityUpdate::SetPhysInfo(
+                        phys.into_iter()
+                            .map(|body| {
+                                body.map(|body| {
+                                    let (x, y) = body.pos.to_fixed();
+                                    PhysBodyInfo {
+                                        x: from_fixed(x),
+                                        y: from_fixed(y),
+                                        angle: dequantize_angle(body.angle),
+                                        vx: body.vx as f32 / PHYS_VEL_SCALE,
+                                        vy: body.vy as f32 / PHYS_VEL_SCALE,
+                                        av: body.av as f32 / ANGULAR_VEL_SCALE,
+                                    }
+                                })
+                            })
+                            .collect(),
+                    ),
+                    CompactUpdate::Limbs(limbs) => {
+                        let limbs: Vec<(i32, i32)> =
+                            limbs.into_iter().map(FixedPos::to_fixed).collect();
+                        let update = limbs_update(&limbs);
+                        self.baseline(current).limbs = limbs;
+                        update
+                    }
+                    CompactUpdate::LimbsDelta(deltas) => {
+                        let baseline = self.baseline(current);
+                        if baseline.limbs.len() != deltas.len() {
+                            return None;
+                        }
+                        let limbs: Vec<(i32, i32)> = baseline
+                            .limbs
+                            .iter()
+                            .zip(deltas)
+                            .map(|(from, d)| apply_delta(*from, d))
+                            .collect();
+                        let update = limbs_update(&limbs);
+                        baseline.limbs = limbs;
+                        update
+                    }
+                    CompactUpdate::SyncedVar(vars) => EntityUpdate::SetSyncedVar(
+                        vars.into_iter()
+                            .map(|var| {
+                                let name = match var.name {
+                                    VarName::New(name) => {
+                                        self.var_names.push(name.clone());
+                                        name
+                                    }
+                                    VarName::Known(index) => {
+                                        self.var_names.get(index as usize)?.clone()
+                                    }
+                                };
+                                Some((
+                                    name,
+                                    var.value_string,
+                                    var.value_int,
+                                    var.value_float,
+                                    var.value_bool,
+                                ))
+                            })
+                            .collect::<Option<_>>()?,
+                    ),
+                    CompactUpdate::Plain(update) => {
+                        if let EntityUpdate::RemoveEntity(lid)
+                        | EntityUpdate::LocalizeEntity(lid, _)
+                        | EntityUpdate::KillEntity { lid, .. } = &update
+                        {
+                            self.baselines.remove(lid);
+                            if current == Some(*lid) {
+                                current = None;
+                            }
+                        }
+                        update
+                    }
+                })
+            })
+            .collect()
+    }
+
+    fn baseline(&mut self, current: Option<Lid>) -> &mut Baseline {
+        self.baselines
+            .entry(current.unwrap_or(Lid(u32::MAX)))
+            .or_default()
+    }
+}
+
+fn limbs_update(limbs: &[(i32, i32)]) -> EntityUpdate {
+    EntityUpdate::SetLimbs(
+        limbs
+            .iter()
+            .map(|(x, y)| (from_fixed(*x), from_fixed(*y)))
+            .collect(),
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn round_trip(
+        encoder: &mut UpdateEncoder,
+        decoder: &mut UpdateDecoder,
+        updates: &[EntityUpdate],
+    ) -> (Vec<EntityUpdate>, CompactUpdates) {
+        let compact = encoder.encode(updates);
+        let sent: CompactUpdates = bitcode::decode(&bitcode::encode(&compact)).unwrap();
+        (decoder.decode(sent), compact)
+    }
+
+    fn assert_close(a: f32, b: f32, tolerance: f32) {
+        assert!((a - b).abs() <= tolerance, "{a} != {b}");
+    }
+
+    #[test]
+    fn positions_round_trip() {
+        let mut encoder = UpdateEncoder::default();
+        let mut decoder = UpdateDecoder::default();
+        for (x, y) in [
+            (0.0, 0.0),
+            (100.3, -200.7),
+            (-1.06, 511.99),
+            (5000.0, -12345.5),
+            (5001.25, -12344.0),
+        ] {
+            let (decoded, _) = round_trip(
+                &mut encoder,
+                &mut decoder,
+                &[
+                    EntityUpdate::CurrentEntity(Lid(1)),
+                    EntityUpdate::SetPosition(x, y),
+                ],
+            );
+            let EntityUpdate::SetPosition(dx, dy) = decoded[1] else {
+                panic!("expected position, got {decoded:?}")
+            };
+            assert_close(dx, x, 0.5 / POS_SCALE);
+            assert_close(dy, y, 0.5 / POS_SCALE);
+        }
+    }
+
+    #[test]
+    fn small_moves_are_deltas() {
+        let mut encoder = UpdateEncoder::default();
+        let mut decoder = UpdateDecoder::default();
+        let updates = |x| {
+            [
+                EntityUpdate::CurrentEntity(Lid(3)),
+                EntityUpdate::SetPosition(x, 10.0),
+            ]
+        };
+        let (_, first) = round_trip(&mut encoder, &mut decoder, &updates(10.0));
+        assert!(matches!(first.updates[1], CompactUpdate::Position(_)));
+        let (decoded, second) = round_trip(&mut encoder, &mut decoder, &updates(12.5));
+        assert!(matches!(
+            second.updates[1],
+            CompactUpdate::PositionDelta(20, 0)
+        ));
+        assert!(matches!(decoded[1], EntityUpdate::SetPosition(12.5, 10.0)));
+        // Too far for a delta.
+        let (decoded, third) = round_trip(&mut encoder, &mut decoder, &updates(9000.0));
+        assert!(matches!(third.updates[1], CompactUpdate::Position(_)));
+        assert!(matches!(
+            decoded[1],
+            EntityUpdate::SetPosition(9000.0, 10.0)
+        ));
+    }
+
+    #[test]
+    fn motion_and_physics_round_trip() {
+        let mut encoder = UpdateEncoder::default();
+        let mut decoder = UpdateDecoder::default();
+        let body = PhysBodyInfo {
+            x: -300.4,
+            y: 1200.9,
+            angle: -1.0,
+            vx: 1.5,
+            vy: -0.25,
+            av: 3.0,
+        };
+        let (decoded, _) = round_trip(
+            &mut encoder,
+            &mut decoder,
+            &[
+                EntityUpdate::CurrentEntity(Lid(1)),
+                EntityUpdate::SetVelocity(-120.3, 45.0),
+                EntityUpdate::SetRotation(7.0),
+                EntityUpdate::SetPhysInfo(vec![Some(body), None]),
+            ],
+        );
+        let EntityUpdate::SetVelocity(vx, vy) = decoded[1] else {
+            panic!()
+        };
+        assert_close(vx, -120.3, 0.5 / VEL_SCALE);
+        assert_close(vy, 45.0, 0.5 / VEL_SCALE);
+        let EntityUpdate::SetRotation(r) = decoded[2] else {
+            panic!()
+        };
+        assert_close(r, 7.0 - TAU, 0.001);
+        let EntityUpdate::SetPhysInfo(phys) = &decoded[3] else {
+            panic!()
+        };
+        let decoded_body = phys[0].unwrap();
+        assert!(phys[1].is_none());
+        assert_close(decoded_body.x, body.x, 0.5 / POS_SCALE);
+        assert_close(decoded_body.y, body.y, 0.5 / POS_SCALE);
+        assert_close(decoded_body.angle, body.angle + TAU, 0.001);
+        assert_close(decoded_body.vx, body.vx, 0.5 / PHYS_VEL_SCALE);
+        assert_close(decoded_body.av, body.av, 0.5 / ANGULAR_VEL_SCALE);
+    }
+
+    #[test]
+    fn limbs_round_trip() {
+        let mut encoder = UpdateEncoder::default();
+        let mut decoder = UpdateDecoder::default();
+        let limbs = |dx: f32| {
+            [
+                EntityUpdate::CurrentEntity(Lid(2)),
+                EntityUpdate::SetLimbs(vec![(10.0 + dx, 20.0), (-30.5, 40.0 - dx)]),
+            ]
+        };
+        let (decoded, first) = round_trip(&mut encoder, &mut decoder, &limbs(0.0));
+        assert!(matches!(first.updates[1], CompactUpdate::Limbs(_)));
+        assert!(
+            matches!(&decoded[1], EntityUpdate::SetLimbs(l) if l == &[(10.0, 20.0), (-30.5, 40.0)])
+        );
+        let (decoded, second) = round_trip(&mut encoder, &mut decoder, &limbs(1.0));
+        assert!(matches!(second.updates[1], CompactUpdate::LimbsDelta(_)));
+        assert!(
+            matches!(&decoded[1], EntityUpdate::SetLimbs(l) if l == &[(11.0, 20.0), (-30.5, 39.0)])
+        );
+    }
+
+    #[test]
+    fn var_names_are_interned() {
+        let mut encoder = UpdateEncoder::default();
+        let mut decoder = UpdateDecoder::default();
+        let vars = vec![
+            ("ew_was_stealable".to_string(), String::new(), 0, 0.0, true),
+            ("ew_rng".to_string(), "abc".to_string(), 5, 1.5, false),
+        ];
+        let updates = [
+            EntityUpdate::CurrentEntity(Lid(1)),
+            EntityUpdate::SetSyncedVar(vars.clone()),
+        ];
+        let (decoded, first) = round_trip(&mut encoder, &mut decoder, &updates);
+        let CompactUpdate::SyncedVar(sent) = &first.updates[1] else {
+            panic!()
+        };
+        assert!(matches!(sent[0].name, VarName::New(_)));
+        assert!(matches!(&decoded[1], EntityUpdate::SetSyncedVar(v) if v == &vars));
+        let (decoded, second) = round_trip(&mut encoder, &mut decoder, &updates);
+        let CompactUpdate::SyncedVar(sent) = &second.updates[1] else {
+            panic!()
+        };
+        assert_eq!(sent[0].name, VarName::Known(0));
+        assert_eq!(sent[1].name, VarName::Known(1));
+        assert!(matches!(&decoded[1], EntityUpdate::SetSyncedVar(v) if v == &vars));
+    }
+
+    #[test]
+    fn reset_starts_new_generation() {
+        let mut encoder = UpdateEncoder::default();
+        let mut decoder = UpdateDecoder::default();
+        let updates = |x| {
+            [
+                EntityUpdate::CurrentEntity(Lid(1)),
+                EntityUpdate::SetPosition(x, 0.0),
+            ]
+        };
+        round_trip(&mut encoder, &mut decoder, &updates(0.0));
+        encoder.reset();
+        // Decoder still has the old baseline, but has to use the new absolute position.
+        let (decoded, compact) = round_trip(&mut encoder, &mut decoder, &updates(1.0));
+        assert!(matches!(compact.updates[1], CompactUpdate::Position(_)));
+        assert!(matches!(decoded[1], EntityUpdate::SetPosition(1.0, 0.0)));
+        // A removed entity doesn't keep its baseline either.
+        let (decoded, compact) = round_trip(
+            &mut encoder,
+            &mut decoder,
+            &[
+                EntityUpdate::RemoveEntity(Lid(1)),
+                EntityUpdate::CurrentEntity(Lid(1)),
+                EntityUpdate::SetPosition(2.0, 0.0),
+            ],
+        );
+        assert!(matches!(compact.updates[2], CompactUpdate::Position(_)));
+        assert!(matches!(decoded[2], EntityUpdate::SetPosition(2.0, 0.0)));
+    }
+}